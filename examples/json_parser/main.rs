@@ -1,4 +1,4 @@
-use flux_bnf::{bnf, lexer::CullStrategy, tokens::Token};
+use flux_bnf::{bnf, tokens::Token};
 use std::str::FromStr;
 use std::{collections::HashMap, error::Error};
 
@@ -11,6 +11,7 @@ fn main() {
     drop(parsed);
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 enum JSONValue {
     Integer(i64),
@@ -26,9 +27,7 @@ impl FromStr for JSONValue {
     type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut lexer = bnf::parse(include_str!("json.bnf")).map_err(|e| format!("{:#}", e))?;
-        lexer.add_rule_for_names(vec!["sep", "object"], CullStrategy::LiftChildren);
-        lexer.set_unnamed_rule(CullStrategy::LiftChildren);
+        let lexer = bnf::parse(include_str!("json.bnf")).map_err(|e| format!("{:#}", e))?;
         lexer.tokenize(s, |t| parse_token(t))?
     }
 }
@@ -61,7 +60,7 @@ fn parse_string(token: &Token) -> String {
 }
 
 fn parse_escape_sequence(token: &Token) -> String {
-    match &*token.get_match() {
+    match token.get_match() {
         "\\\"" => "\"".to_string(),
         "\\\\" => "\\".to_string(),
         "\\/" => "/".to_string(),
@@ -96,9 +95,9 @@ fn parse_list(token: &Token) -> ResultAlias<Vec<JSONValue>> {
 fn parse_map(token: &Token) -> ResultAlias<HashMap<String, JSONValue>> {
     let mut map = HashMap::with_capacity(token.children.len());
     for child in &token.children {
-        let key = parse_string(&child.children[0]);
-        let value = parse_token(&child.children[1])?;
-        map.insert(key, value);
+        let key = child.field("key").ok_or("map entry without a key")?;
+        let value = child.field("value").ok_or("map entry without a value")?;
+        map.insert(parse_string(key), parse_token(value)?);
     }
     Ok(map)
 }
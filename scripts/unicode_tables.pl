@@ -0,0 +1,48 @@
+# Generates src/unicode_tables.rs from the Unicode data bundled with Perl:
+#     perl scripts/unicode_tables.pl > src/unicode_tables.rs
+use strict;
+use Unicode::UCD qw(prop_invlist prop_values prop_value_aliases);
+
+sub ranges {
+    my @inv = prop_invlist($_[0]);
+    my @out;
+    for (my $i = 0; $i < @inv; $i += 2) {
+        my $lo = $inv[$i];
+        my $hi = $i + 1 < @inv ? $inv[$i + 1] - 1 : 0x10FFFF;
+        # Surrogates can't be represented as a `char`
+        if ($lo <= 0xDFFF && $hi >= 0xD800) {
+            push @out, [$lo, 0xD7FF] if $lo < 0xD800;
+            $lo = 0xE000;
+            next if $hi < $lo;
+        }
+        push @out, [$lo, $hi];
+    }
+    return @out;
+}
+
+sub table {
+    my ($name, $prop, @values) = @_;
+    print "#[rustfmt::skip]\npub(crate) const $name: &[(&str, &[(u32, u32)])] = &[\n";
+    for my $v (@values) {
+        my @r = ranges("$prop=$v");
+        next unless @r;
+        print "    (\"$v\", &[\n";
+        while (my @line = splice(@r, 0, 6)) {
+            print "        ", join(" ", map { sprintf "(0x%x, 0x%x),", @$_ } @line), "\n";
+        }
+        print "    ]),\n";
+    }
+    print "];\n";
+}
+
+my @gc = grep { length == 2 && $_ ne 'LC' && $_ ne 'Cs' }
+    map { (prop_value_aliases("gc", $_))[0] } prop_values("gc");
+my @sc = sort map { (prop_value_aliases("sc", $_))[1] } prop_values("sc");
+
+print "//! Unicode general category and script tables, generated from the Unicode ",
+    Unicode::UCD::UnicodeVersion(), "\n//! character database by `scripts/unicode_tables.pl`.\n//!\n",
+    "//! Ranges are inclusive codepoint pairs, sorted and non-overlapping. Surrogates are left\n",
+    "//! out since they can never appear in a `char`.\n\n";
+table("GENERAL_CATEGORIES", "gc", sort @gc);
+print "\n";
+table("SCRIPTS", "sc", @sc);
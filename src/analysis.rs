@@ -0,0 +1,290 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::error::FluxError;
+use crate::matchers::{Location, Matcher, MatcherType};
+use crate::position::{self, LineCol};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that is probably a mistake, but still leaves a usable grammar
+    Warning,
+    /// Something that makes the grammar unusable, which fails `bnf::parse`
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A rule that can never match, because it can't avoid referring back to itself
+    InfiniteRecursion,
+    /// A rule that isn't used by `root`, directly or through other rules
+    UnreachableRule,
+    /// A template rule that is never used
+    UnusedTemplate,
+    /// A repetition of something that can match without consuming anything, which ends the
+    /// repetition as soon as it does
+    NullableRepetition,
+    /// An alternative of a choice that can never be matched, because an earlier one always
+    /// matches first
+    ShadowedAlternative,
+}
+
+impl DiagnosticKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            DiagnosticKind::InfiniteRecursion => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+/// A problem found in a grammar by `bnf::analyze` or `Lexer::validate`
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// Position in the grammar source the problem was found at
+    pub location: usize,
+    pub src_text: Option<Arc<str>>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(kind: DiagnosticKind, message: String, location: Option<&Location>) -> Self {
+        let (src_text, location) = match location {
+            Some(location) => {
+                let (source, pos) = position::from_chars(&location.source, location.pos);
+                (Some(source), pos)
+            }
+            None => (None, 0),
+        };
+        Diagnostic {
+            severity: kind.severity(),
+            kind,
+            message,
+            location,
+            src_text,
+        }
+    }
+}
+
+impl From<Diagnostic> for FluxError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        FluxError::new_dyn(diagnostic.message, diagnostic.location, diagnostic.src_text)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}", self.message)?;
+        match &self.src_text {
+            Some(source) => {
+                let LineCol { line, column } = position::line_col(source, self.location);
+                write!(f, " at line {line} col {column}")
+            }
+            None => write!(f, " at position {}", self.location),
+        }
+    }
+}
+
+/// Find which matchers can succeed without consuming anything
+pub(crate) fn nullable(matchers: &[Matcher]) -> Vec<bool> {
+    fixed_point(matchers, Matcher::can_match_empty)
+}
+
+/// Find which matchers can match any input at all
+pub(crate) fn productive(matchers: &[Matcher]) -> Vec<bool> {
+    fixed_point(matchers, Matcher::can_match)
+}
+
+/// Find the smallest set of matchers satisfying `holds`, which is given the set found so far
+fn fixed_point(matchers: &[Matcher], holds: impl Fn(&Matcher, &[bool]) -> bool) -> Vec<bool> {
+    let mut set = vec![false; matchers.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, matcher) in matchers.iter().enumerate() {
+            if !set[i] && holds(matcher, &set) {
+                set[i] = true;
+                changed = true;
+            }
+        }
+    }
+    set
+}
+
+fn reachable(root: usize, matchers: &[Matcher]) -> Vec<bool> {
+    let mut reachable = vec![false; matchers.len()];
+    let mut stack = vec![root];
+    while let Some(id) = stack.pop() {
+        if !std::mem::replace(&mut reachable[id], true) {
+            stack.extend(matchers[id].child_ids());
+            stack.extend(matchers[id].recover);
+        }
+    }
+    reachable
+}
+
+pub(crate) fn validate(root: usize, matchers: &[Matcher]) -> Vec<Diagnostic> {
+    let nullable = nullable(matchers);
+    let productive = productive(matchers);
+    let reachable = reachable(root, matchers);
+    let mut diagnostics = Vec::new();
+    for rule in matchers.iter() {
+        let Some(name) = &*rule.name else {
+            continue;
+        };
+        let location = rule.location.as_ref();
+        if !productive[rule.id] {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::InfiniteRecursion,
+                format!(
+                    "Rule {name} can never match, because it can't avoid referring back to itself"
+                ),
+                location,
+            ));
+        }
+        // Rules of imported grammars are usually only partly used
+        if !reachable[rule.id] && !name.contains('.') {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::UnreachableRule,
+                format!("Rule {name} is never used by root"),
+                location,
+            ));
+        }
+        check_parts(rule.id, location, matchers, &nullable, &mut diagnostics);
+    }
+    diagnostics
+}
+
+/// Check a matcher and the unnamed matchers under it, which make up the rest of its rule
+fn check_parts(
+    id: usize,
+    location: Option<&Location>,
+    matchers: &[Matcher],
+    nullable: &[bool],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let matcher = &matchers[id];
+    let location = part_location(matcher, location);
+    match &matcher.matcher_type {
+        MatcherType::Repeating(child, range, _) if *range.end() > 1 && nullable[*child] => {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::NullableRepetition,
+                "Repeated expression can match without consuming anything".to_string(),
+                part_location(&matchers[*child], location),
+            ));
+        }
+        MatcherType::Choice(children, _) => {
+            check_alternatives(children, location, matchers, diagnostics)
+        }
+        _ => (),
+    }
+    for child in matcher.child_ids() {
+        if matchers[child].name.is_none() {
+            check_parts(child, location, matchers, nullable, diagnostics);
+        }
+    }
+}
+
+fn check_alternatives(
+    children: &[usize],
+    location: Option<&Location>,
+    matchers: &[Matcher],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (i, earlier) in children.iter().enumerate() {
+        let later_children = &children[i + 1..];
+        if always_matches(*earlier, matchers) {
+            if let Some(later) = later_children.first() {
+                diagnostics.push(Diagnostic::new(
+                    DiagnosticKind::ShadowedAlternative,
+                    "Alternative can never be matched, since an earlier one always matches"
+                        .to_string(),
+                    part_location(&matchers[*later], location),
+                ));
+            }
+            return;
+        }
+        for later in later_children {
+            if matches_prefix_of(*earlier, *later, matchers) {
+                diagnostics.push(Diagnostic::new(
+                    DiagnosticKind::ShadowedAlternative,
+                    "Alternative can never be matched, since an earlier one matches the start of it"
+                        .to_string(),
+                    part_location(&matchers[*later], location),
+                ));
+            }
+        }
+    }
+}
+
+/// Get where a part of a rule was defined, falling back to the location of what it's part of.
+/// Other rules used by the part were defined somewhere else, so they always fall back.
+fn part_location<'m>(matcher: &'m Matcher, location: Option<&'m Location>) -> Option<&'m Location> {
+    match &*matcher.name {
+        Some(_) => location,
+        None => matcher.location.as_ref().or(location),
+    }
+}
+
+/// Check if a matcher succeeds on any input
+fn always_matches(id: usize, matchers: &[Matcher]) -> bool {
+    match &matchers[id].matcher_type {
+        MatcherType::String(s, _) => s.is_empty(),
+        MatcherType::Repeating(_, range, _) => *range.start() == 0,
+        MatcherType::List(children) => children.iter().all(|c| always_matches(*c, matchers)),
+        MatcherType::Labeled(_, child) | MatcherType::Wrapper(child) => {
+            always_matches(*child, matchers)
+        }
+        _ => false,
+    }
+}
+
+/// Check if `earlier` always matches the start of anything `later` matches, so `later`
+/// never gets to match after it
+fn matches_prefix_of(earlier: usize, later: usize, matchers: &[Matcher]) -> bool {
+    let Some((prefix, later_case_sensitive)) = literal_prefix(later, matchers) else {
+        return false;
+    };
+    let earlier = unwrapped(earlier, matchers);
+    match &earlier.matcher_type {
+        MatcherType::String(s, case_sensitive) => {
+            (!*case_sensitive || later_case_sensitive)
+                && s.len() <= prefix.len()
+                && s.iter().zip(&prefix).all(|(a, b)| match case_sensitive {
+                    true => a == b,
+                    false => a.eq_ignore_ascii_case(b),
+                })
+        }
+        MatcherType::CharClass(_) => prefix.first().is_some_and(|c| {
+            let caseless = c.to_ascii_uppercase() == c.to_ascii_lowercase();
+            (later_case_sensitive || caseless) && earlier.can_start_with(*c, matchers)
+        }),
+        _ => false,
+    }
+}
+
+/// Get the literal text anything `id` matches has to start with, and whether it's case
+/// sensitive
+fn literal_prefix(id: usize, matchers: &[Matcher]) -> Option<(Vec<char>, bool)> {
+    match &unwrapped(id, matchers).matcher_type {
+        MatcherType::String(s, case_sensitive) if !s.is_empty() => {
+            Some((s.clone(), *case_sensitive))
+        }
+        MatcherType::List(children) => literal_prefix(*children.first()?, matchers),
+        _ => None,
+    }
+}
+
+/// Skip past any labels and wrappers around a matcher
+fn unwrapped(id: usize, matchers: &[Matcher]) -> &Matcher {
+    match matchers[id].matcher_type {
+        MatcherType::Labeled(_, child) | MatcherType::Wrapper(child) => unwrapped(child, matchers),
+        _ => &matchers[id],
+    }
+}
@@ -1,99 +1,470 @@
-use crate::error::{FluxError, Result};
+use crate::analysis::{Diagnostic, DiagnosticKind, Severity};
+use crate::char_class::{self, CharClass};
+use crate::error::{ErrorMessage, FluxError, Result};
 use crate::lexer::{CullStrategy, Lexer};
-use crate::matchers::{Matcher, MatcherType};
-use std::collections::HashMap;
+use crate::loader::{GrammarLoader, NoLoader};
+use crate::matchers::{Location, Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity, Operator, OperatorTable};
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
+mod abnf;
+
 const COMMENT_SYMBOL: &str = "//";
 const ERROR_TRANSPARENT_SYMBOL: char = '!';
+const ANNOTATION_SYMBOL: char = '@';
+const IMPORT_DIRECTIVE: &str = "@import";
+const PRECEDENCE_DIRECTIVE: &str = "@precedence";
+const MEMO_ANNOTATION: &str = "memo";
+const RECOVER_ANNOTATION: &str = "recover";
 
+/// Parse a grammar, failing if it has any error diagnostics
 pub fn parse(input: &str) -> Result<Lexer> {
-    let id_map: HashMap<String, usize> = input
-        .lines()
-        .map(str::trim_start)
-        .filter(|s| !s.is_empty() && !s.starts_with(COMMENT_SYMBOL))
-        .map(|s| {
-            s.chars()
-                .take_while(|c| !c.is_whitespace() && *c != ERROR_TRANSPARENT_SYMBOL)
-                .collect()
-        })
-        .filter(|s: &String| !s.contains('<'))
-        .enumerate()
-        .map(|(i, s)| (s, i))
-        .collect();
-    BNFParserState {
-        matchers: vec![
-            Matcher {
-                name: None.into(),
-                id: 0,
-                matcher_type: MatcherType::Placeholder,
-                cull_strategy: CullStrategy::None,
-                show_in_errors: true,
-            };
-            id_map.len()
-        ],
+    parse_with_loader(input, &NoLoader)
+}
+
+/// Parse a grammar and check it for likely mistakes, returning every warning and error found.
+/// Fails only if the grammar can't be parsed at all.
+pub fn analyze(input: &str) -> Result<Vec<Diagnostic>> {
+    analyze_with_loader(input, &NoLoader)
+}
+
+/// Analyze a grammar which can `@import` other grammars, loading them with `loader`
+pub fn analyze_with_loader(input: &str, loader: &dyn GrammarLoader) -> Result<Vec<Diagnostic>> {
+    build(input, loader).map(|(_, diagnostics)| diagnostics)
+}
+
+/// Parse a grammar which can `@import` other grammars, loading them with `loader`.
+///
+/// The rules of an imported grammar are referred to by the name of the import, like
+/// `common.number` for `@import "common.bnf"`, or `c.number` for `@import "common.bnf" as c`.
+/// The path of an import is relative to the file it's in, and a file imported more than once
+/// is only parsed the first time, its rules keeping the names they got there.
+pub fn parse_with_loader(input: &str, loader: &dyn GrammarLoader) -> Result<Lexer> {
+    without_errors(build(input, loader)?)
+}
+
+/// Parse a grammar written in ABNF, as specified by RFC 5234 and RFC 7405, failing if it has
+/// any error diagnostics.
+///
+/// The root is the rule called `root` if there is one, and otherwise the first rule. The core
+/// rules like `ALPHA`, `DIGIT` and `CRLF` can be used without defining them.
+pub fn parse_abnf(input: &str) -> Result<Lexer> {
+    without_errors(abnf::build(input)?)
+}
+
+pub(crate) fn without_errors((lexer, diagnostics): (Lexer, Vec<Diagnostic>)) -> Result<Lexer> {
+    match diagnostics
+        .into_iter()
+        .find(|d| d.severity == Severity::Error)
+    {
+        Some(error) => Err(error.into()),
+        None => Ok(lexer),
+    }
+}
+
+fn build(input: &str, loader: &dyn GrammarLoader) -> Result<(Lexer, Vec<Diagnostic>)> {
+    let mut state = BNFParserState {
+        id_map: HashMap::new(),
         templates: HashMap::new(),
-        id_map,
+        unnamed_rule: None,
+        matchers: Vec::new(),
         source: input.chars().collect(),
         pos: 0,
+        loader,
+        prefix: String::new(),
+        path: String::new(),
+        import_stack: Vec::new(),
+        modules: HashMap::new(),
+        template_stack: Vec::new(),
+        used_templates: HashSet::new(),
+        diagnostics: Vec::new(),
+    };
+    state.parse_module()?;
+    flatten_wrappers(&mut state.matchers);
+    let root = state.id_map.get("root").ok_or_else(|| {
+        FluxError::new("No root matcher specified", 0, None).in_chars(&state.source)
+    })?;
+    let lexer = Lexer::new(*root, state.id_map, state.matchers);
+    let mut diagnostics = state.diagnostics;
+    diagnostics.extend(lexer.validate());
+    Ok((lexer, diagnostics))
+}
+
+/// Resolve the path of an import against the path of the file it's in, normalizing away `.`
+/// and `..`, so the loader gets every path relative to the root grammar
+fn resolve_import(importer: &str, path: &str) -> String {
+    let absolute = path.starts_with(['/', '\\']);
+    let dir = match importer.rsplit_once(['/', '\\']) {
+        Some((dir, _)) if !absolute => dir,
+        _ => "",
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in dir.split(['/', '\\']).chain(path.split(['/', '\\'])) {
+        match part {
+            "" | "." => (),
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    let resolved = parts.join("/");
+    match absolute {
+        true => format!("/{resolved}"),
+        false => resolved,
+    }
+}
+
+/// Point everything that uses a rule straight at its matcher, rather than at a wrapper
+/// around it
+pub(crate) fn flatten_wrappers(matchers: &mut [Matcher]) {
+    let mut wrappers: HashMap<usize, usize> = HashMap::new();
+    for (index, matcher) in matchers.iter().enumerate() {
+        if let MatcherType::Wrapper(child) = matcher.matcher_type {
+            wrappers.insert(index, child);
+        };
+    }
+    for matcher in matchers.iter_mut() {
+        let Some(children) = matcher.children() else {
+            continue;
+        };
+        for child in children {
+            *child = *wrappers.get(child).unwrap_or(child);
+        }
+    }
+    for matcher in matchers.iter_mut() {
+        if let Some(sync) = &mut matcher.recover {
+            *sync = *wrappers.get(sync).unwrap_or(sync);
+        }
+    }
+}
+
+/// Strip leading whitespace and any `@annotation` or `@annotation(args)` from a line
+fn strip_annotations(line: &str) -> &str {
+    let mut rest = line.trim_start();
+    while let Some(annotation) = rest.strip_prefix(ANNOTATION_SYMBOL) {
+        rest = annotation.trim_start_matches(|c: char| c.is_alphabetic() || c == '_');
+        if rest.starts_with('(') {
+            rest = closing_paren(rest).map_or("", |end| &rest[end + 1..]);
+        }
+        rest = rest.trim_start();
+    }
+    rest
+}
+
+/// Find the `)` closing the `(` that `s` starts with, skipping over parens that are nested,
+/// escaped, or inside a string or character class
+fn closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut delimiter = None;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (delimiter, c) {
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(end), c) if c == end => delimiter = None,
+            (Some(_), _) => (),
+            (None, '"') => delimiter = Some('"'),
+            (None, '[') => delimiter = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
     }
-    .parse()
+    None
+}
+
+/// Identifies a line that starts a new rule, like `name ::=` or `name<param> ::=`
+fn is_rule_header(line: &str) -> bool {
+    let rest = strip_annotations(line)
+        .trim_start_matches(|c: char| c.is_alphabetic() || c == '_' || c == '.');
+    let rest = rest.strip_prefix(ERROR_TRANSPARENT_SYMBOL).unwrap_or(rest);
+    let rest = match rest.strip_prefix('<') {
+        Some(params) => match params.find('>') {
+            Some(end) => &params[end + 1..],
+            None => return false,
+        },
+        None => rest,
+    };
+    rest.trim_start().starts_with("::=")
+}
+
+/// Identifies a line that continues the rule on the line before it, either because it
+/// starts with `|` or because it is indented and does not define a rule of its own
+fn is_continuation(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with('|')
+        || (trimmed.len() < line.len()
+            && !trimmed.starts_with(ANNOTATION_SYMBOL)
+            && !is_rule_header(trimmed))
 }
 
-struct BNFParserState {
+struct BNFParserState<'l> {
     id_map: HashMap<String, usize>,
     templates: HashMap<String, TemplateRule>,
+    unnamed_rule: Option<CullStrategy>,
     matchers: Vec<Matcher>,
     source: Arc<[char]>,
     pos: usize,
+    loader: &'l dyn GrammarLoader,
+    /// Prepended to the names of rules defined in this file, like `common.` for an import
+    prefix: String,
+    /// The path of this file relative to the root grammar's, which is empty
+    path: String,
+    /// Paths of the files currently being imported, to detect import cycles
+    import_stack: Vec<String>,
+    /// Every file imported so far by its path, so none is parsed twice
+    modules: HashMap<String, Arc<ModuleScope>>,
+    /// Templates currently being expanded, to detect templates that use themselves
+    template_stack: Vec<TemplateKey>,
+    used_templates: HashSet<TemplateKey>,
+    diagnostics: Vec<Diagnostic>,
 }
 
+/// Identifies a template rule by the address of the source it's in and where its body starts
+type TemplateKey = (usize, usize);
+
 #[derive(Clone)]
 struct TemplateRule {
     rule_start: usize,
     names: Vec<String>,
+    /// The file the template was imported from, if it wasn't defined in the current one
+    module: Option<Arc<ModuleScope>>,
+}
+
+/// Everything needed to parse a template body in the file that defined it
+struct ModuleScope {
+    source: Arc<[char]>,
+    id_map: HashMap<String, usize>,
+    templates: HashMap<String, TemplateRule>,
+}
+
+/// Settings declared for a rule with annotations like `@lift` before its name
+#[derive(Default)]
+struct RuleAnnotations {
+    cull_strategy: Option<CullStrategy>,
+    memoize: bool,
+    recover: Option<usize>,
 }
 
 enum ParseLineOutput {
-    Rule(MatcherType, String, bool),
+    Rule(MatcherType, String, bool, RuleAnnotations),
     TemplateRule(TemplateRule, String),
 }
 
-impl BNFParserState {
-    fn parse(mut self) -> Result<Lexer> {
+impl<'l> BNFParserState<'l> {
+    fn parse_module(&mut self) -> Result<()> {
+        self.parse_imports()?;
+        let first_matcher = self.matchers.len();
+        self.declare_rules()?;
         self.consume_line_breaks();
         while self.pos < self.source.len() {
+            let rule_pos = self.pos;
             match self.parse_rule()? {
-                Some(ParseLineOutput::Rule(rule, name, show_in_errors)) => {
-                    self.add_named_matcher(rule, name, show_in_errors);
+                Some(ParseLineOutput::Rule(rule, name, show_in_errors, annotations)) => {
+                    self.add_named_matcher(rule, name, show_in_errors, annotations, rule_pos)?;
                 }
                 Some(ParseLineOutput::TemplateRule(rule, name)) => {
+                    if self.templates.contains_key(&name) {
+                        return Err(FluxError::new_dyn(
+                            format!("Template rule {name} is defined more than once"),
+                            rule_pos,
+                            None,
+                        )
+                        .in_chars(&self.source));
+                    }
                     self.templates.insert(name, rule);
                 }
                 _ => (),
             }
             self.consume_line_breaks();
         }
-        self.flatten_wrappers();
-        let root = self.id_map.get("root").ok_or_else(|| {
-            FluxError::new("No root matcher specified", 0, Some(self.source.clone()))
-        })?;
-        Ok(Lexer::new(*root, self.id_map, self.matchers))
+        self.check_unused_templates()?;
+        if let Some(unnamed_rule) = self.unnamed_rule {
+            for matcher in &mut self.matchers[first_matcher..] {
+                if matcher.name.is_none() {
+                    matcher.cull_strategy = unnamed_rule;
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn flatten_wrappers(&mut self) {
-        let mut wrappers: HashMap<usize, usize> = HashMap::new();
-        for (index, matcher) in self.matchers.iter().enumerate() {
-            if let MatcherType::Wrapper(child) = matcher.matcher_type {
-                wrappers.insert(index, child);
-            };
+    /// Find the name of every rule in the file and reserve a matcher for it, so rules can
+    /// refer to each other regardless of the order they're defined in
+    fn declare_rules(&mut self) -> Result<()> {
+        let text: String = self.source.iter().collect();
+        let mut line_start = 0;
+        for line in text.split('\n') {
+            let pos = line_start;
+            line_start += line.chars().count() + 1;
+            let rule = strip_annotations(line);
+            if is_continuation(line)
+                || rule.is_empty()
+                || rule.starts_with(COMMENT_SYMBOL)
+                || line.trim_start().starts_with(IMPORT_DIRECTIVE)
+            {
+                continue;
+            }
+            let name: String = rule
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != ERROR_TRANSPARENT_SYMBOL)
+                .collect();
+            if name.contains('<') {
+                continue;
+            }
+            if self.id_map.contains_key(&name) {
+                return Err(FluxError::new_dyn(
+                    format!("Rule {name} is defined more than once"),
+                    pos,
+                    None,
+                )
+                .in_chars(&self.source));
+            }
+            self.id_map.insert(name, self.matchers.len());
+            self.matchers.push(Matcher {
+                name: None.into(),
+                id: self.matchers.len(),
+                matcher_type: MatcherType::Placeholder,
+                cull_strategy: CullStrategy::None,
+                show_in_errors: true,
+                left_recursive: false,
+                memoize: false,
+                recover: None,
+                location: None,
+            });
         }
-        for index in 0..self.matchers.len() {
-            let Some(children) = self.matchers[index].children() else {continue};
-            for child in children {
-                *child = *wrappers.get(child).unwrap_or(child);
+        Ok(())
+    }
+
+    /// Find every `@import` directive in the file and parse the grammars they refer to, so
+    /// their rules can be used anywhere in this one
+    fn parse_imports(&mut self) -> Result<()> {
+        while self.pos < self.source.len() {
+            self.consume_whitespace();
+            if self.check_str(IMPORT_DIRECTIVE) {
+                self.parse_import()?;
             }
+            self.consume_comment();
+            self.advance();
+        }
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn parse_import(&mut self) -> Result<()> {
+        let import_pos = self.pos - IMPORT_DIRECTIVE.len();
+        self.call_assert("whitespace", Self::consume_whitespace)?;
+        self.assert_char('"')?;
+        let path = self.parse_str_chars('"')?;
+        self.assert_char('"')?;
+        self.consume_whitespace();
+        let alias = if self.check_str("as") {
+            self.call_assert("whitespace", Self::consume_whitespace)?;
+            self.parse_word()?
+        } else {
+            let file_name = path.rsplit(['/', '\\']).next().unwrap_or_default();
+            let stem = file_name.split('.').next().unwrap_or_default();
+            stem.to_string()
+        };
+        self.consume_whitespace();
+        if self.check_str(COMMENT_SYMBOL) {
+            self.consume_comment();
+        }
+        if !matches!(self.peek(), None | Some('\n' | '\r')) {
+            let message = format!("Unexpected text after importing {path}");
+            return Err(FluxError::new_dyn(message, self.pos, None).in_chars(&self.source));
+        }
+        let import_error =
+            |message: String| FluxError::new_dyn(message, import_pos, None).in_chars(&self.source);
+        if alias.is_empty() || !alias.chars().all(|c| c.is_alphabetic() || c == '_') {
+            return Err(import_error(format!(
+                "Can't import {path} as `{alias}`, name it with `as`"
+            )));
+        }
+        let prefix = format!("{alias}.");
+        let mut names = self.id_map.keys().chain(self.templates.keys());
+        if names.any(|name| name.starts_with(&prefix)) {
+            return Err(import_error(format!("{alias} is imported more than once")));
+        }
+        let path = resolve_import(&self.path, &path);
+        if self.import_stack.contains(&path) {
+            let cycle = self.import_stack.join(" -> ");
+            return Err(import_error(format!("Import cycle: {cycle} -> {path}")));
+        }
+        let scope = match self.modules.get(&path) {
+            Some(scope) => scope.clone(),
+            None => self.parse_imported(&path, &prefix, import_pos)?,
+        };
+        for (name, id) in &scope.id_map {
+            self.id_map.insert(format!("{prefix}{name}"), *id);
+        }
+        for (name, template) in &scope.templates {
+            let mut template = template.clone();
+            template.module.get_or_insert_with(|| scope.clone());
+            self.templates.insert(format!("{prefix}{name}"), template);
+        }
+        Ok(())
+    }
+
+    /// Load and parse the grammar at `path`, which is imported with `prefix`
+    fn parse_imported(
+        &mut self,
+        path: &str,
+        prefix: &str,
+        import_pos: usize,
+    ) -> Result<Arc<ModuleScope>> {
+        let import_error =
+            |message: String| FluxError::new_dyn(message, import_pos, None).in_chars(&self.source);
+        let grammar = self
+            .loader
+            .load(path)
+            .map_err(|e| import_error(format!("Could not import {path}: {e}")))?;
+        let mut module = BNFParserState {
+            id_map: HashMap::new(),
+            templates: HashMap::new(),
+            unnamed_rule: None,
+            matchers: std::mem::take(&mut self.matchers),
+            source: grammar.chars().collect(),
+            pos: 0,
+            loader: self.loader,
+            prefix: format!("{}{}", self.prefix, prefix),
+            path: path.to_string(),
+            import_stack: self
+                .import_stack
+                .iter()
+                .cloned()
+                .chain([path.to_string()])
+                .collect(),
+            template_stack: Vec::new(),
+            used_templates: HashSet::new(),
+            diagnostics: Vec::new(),
+            modules: std::mem::take(&mut self.modules),
+        };
+        let parsed = module.parse_module();
+        self.matchers = std::mem::take(&mut module.matchers);
+        self.modules = std::mem::take(&mut module.modules);
+        self.diagnostics.append(&mut module.diagnostics);
+        if let Err(mut error) = parsed {
+            error.description =
+                ErrorMessage::Dynamic(format!("{} (in {path})", error.description.get_message()));
+            return Err(error);
         }
+        let scope = Arc::new(ModuleScope {
+            source: module.source,
+            id_map: module.id_map,
+            templates: module.templates,
+        });
+        self.modules.insert(path.to_string(), scope.clone());
+        Ok(scope)
     }
 
     fn add_matcher(&mut self, matcher_type: MatcherType) -> &Matcher {
@@ -103,27 +474,98 @@ impl BNFParserState {
             matcher_type,
             cull_strategy: CullStrategy::None,
             show_in_errors: false,
+            left_recursive: false,
+            memoize: false,
+            recover: None,
+            location: None,
         };
         self.matchers.push(matcher);
         &self.matchers[self.matchers.len() - 1]
     }
 
+    /// Add a matcher, recording that it was defined at `pos` so diagnostics can point at it
+    fn add_located_matcher(&mut self, matcher_type: MatcherType, pos: usize) -> &Matcher {
+        let location = self.location(pos);
+        let id = self.add_matcher(matcher_type).id;
+        self.matchers[id].location = Some(location);
+        &self.matchers[id]
+    }
+
+    fn location(&self, pos: usize) -> Location {
+        Location {
+            pos,
+            source: self.source.clone(),
+        }
+    }
+
+    fn template_key(&self, template: &TemplateRule) -> TemplateKey {
+        let source = template.module.as_ref().map_or(&self.source, |m| &m.source);
+        (
+            Arc::as_ptr(source) as *const char as usize,
+            template.rule_start,
+        )
+    }
+
+    /// Warn about template rules in this file that are never used, and parse their bodies
+    /// anyway so mistakes in them are still found
+    fn check_unused_templates(&mut self) -> Result<()> {
+        let mut unused: Vec<(String, TemplateRule)> = self
+            .templates
+            .iter()
+            .filter(|(_, t)| t.module.is_none())
+            .filter(|(_, t)| !self.used_templates.contains(&self.template_key(t)))
+            .map(|(name, t)| (name.clone(), t.clone()))
+            .collect();
+        unused.sort_by_key(|(_, t)| t.rule_start);
+        let matchers_len = self.matchers.len();
+        for (name, template) in unused {
+            // Templates of imported grammars are meant to be used by the grammars importing them
+            if self.prefix.is_empty() {
+                self.diagnostics.push(Diagnostic::new(
+                    DiagnosticKind::UnusedTemplate,
+                    format!("Template rule {name} is never used"),
+                    Some(&self.location(template.rule_start)),
+                ));
+            }
+            let param = self.add_matcher(MatcherType::String(Vec::new(), true)).id;
+            let extras = template.names.iter().map(|n| (n.clone(), param)).collect();
+            let old_pos = std::mem::replace(&mut self.pos, template.rule_start);
+            self.template_stack.push(self.template_key(&template));
+            self.parse_list(&Some(extras))?;
+            self.template_stack.pop();
+            self.pos = old_pos;
+        }
+        self.matchers.truncate(matchers_len);
+        Ok(())
+    }
+
     fn add_named_matcher(
         &mut self,
         matcher_type: MatcherType,
         name: String,
         show_in_errors: bool,
-    ) -> &Matcher {
-        let id = self.id_map[&name];
+        annotations: RuleAnnotations,
+        pos: usize,
+    ) -> Result<&Matcher> {
+        let Some(&id) = self.id_map.get(&name) else {
+            return Err(
+                FluxError::new_dyn(format!("Rule {name} was never declared"), pos, None)
+                    .in_chars(&self.source),
+            );
+        };
         let matcher = Matcher {
-            name: Some(name).into(),
+            name: Some(format!("{}{}", self.prefix, name)).into(),
             id,
             matcher_type,
-            cull_strategy: CullStrategy::None,
+            cull_strategy: annotations.cull_strategy.unwrap_or(CullStrategy::None),
             show_in_errors,
+            left_recursive: false,
+            memoize: annotations.memoize,
+            recover: annotations.recover,
+            location: Some(self.location(pos)),
         };
         self.matchers[id] = matcher;
-        &self.matchers[id]
+        Ok(&self.matchers[id])
     }
 
     fn parse_rule(&mut self) -> Result<Option<ParseLineOutput>> {
@@ -131,28 +573,148 @@ impl BNFParserState {
             self.consume_comment();
             return Ok(None);
         }
-        let name = self.parse_word()?;
+        let mut annotations = RuleAnnotations::default();
+        while self.check_char(ANNOTATION_SYMBOL) {
+            let annotation = self.parse_word()?;
+            if annotation == IMPORT_DIRECTIVE[1..] {
+                // Imports were already parsed before any rules
+                self.consume_comment();
+                return Ok(None);
+            }
+            if annotation == "unnamed" {
+                self.call_assert("whitespace", Self::consume_whitespace)?;
+                self.assert_char(ANNOTATION_SYMBOL)?;
+                let strategy = self.parse_word()?;
+                self.unnamed_rule = Some(self.parse_cull_strategy(&strategy)?);
+                return Ok(None);
+            }
+            if annotation == MEMO_ANNOTATION {
+                annotations.memoize = true;
+            } else if annotation == RECOVER_ANNOTATION {
+                self.assert_char('(')?;
+                self.consume_whitespace();
+                let sync = self.parse_matcher_with_modifiers(&None)?;
+                annotations.recover = Some(self.add_matcher(sync).id);
+                self.consume_whitespace();
+                self.assert_char(')')?;
+            } else {
+                annotations.cull_strategy = Some(self.parse_cull_strategy(&annotation)?);
+            }
+            self.consume_line_breaks();
+        }
+        let name = self.parse_name()?;
         let show_in_errors = !self.check_char(ERROR_TRANSPARENT_SYMBOL);
         let template = self.check_char('<').then(|| self.parse_generic_params());
         self.call_assert("whitespace", Self::consume_whitespace)?;
         self.assert_str("::=")?;
-        self.call_assert("whitespace", Self::consume_whitespace)?;
+        self.call_assert("whitespace", Self::consume_separator)?;
+        if self.check_char('|') {
+            self.consume_separator();
+        }
         if let Some(params) = template {
+            if annotations.cull_strategy.is_some() {
+                return Err(
+                    self.create_error(format!("Template rule {name} can't have a cull strategy"))
+                );
+            }
+            if annotations.memoize {
+                return Err(self.create_error(format!("Template rule {name} can't be memoized")));
+            }
+            if annotations.recover.is_some() {
+                return Err(self.create_error(format!("Template rule {name} can't recover")));
+            }
             let template_rule = TemplateRule {
                 rule_start: self.pos,
                 names: params?,
+                module: None,
             };
-            while !self.check_char('\n') {
-                self.advance();
+            self.consume_comment();
+            while self.consume_continuation() {
+                self.consume_comment();
             }
             return Ok(Some(ParseLineOutput::TemplateRule(template_rule, name)));
         }
-        let matcher = self.parse_list(&None)?;
+        let matcher = if self.check_str(PRECEDENCE_DIRECTIVE) {
+            self.parse_precedence()?
+        } else {
+            self.parse_list(&None)?
+        };
         self.consume_whitespace();
         if self.check_str(COMMENT_SYMBOL) {
             self.consume_comment();
         }
-        Ok(Some(ParseLineOutput::Rule(matcher, name, show_in_errors)))
+        Ok(Some(ParseLineOutput::Rule(
+            matcher,
+            name,
+            show_in_errors,
+            annotations,
+        )))
+    }
+
+    /// Parse the cull strategy named by an annotation, like `@lift` or `@liftAtMost(1)`
+    fn parse_cull_strategy(&mut self, annotation: &str) -> Result<CullStrategy> {
+        match annotation {
+            "lift" => Ok(CullStrategy::LiftChildren),
+            "delete" => Ok(CullStrategy::DeleteAll),
+            "deleteChildren" => Ok(CullStrategy::DeleteChildren),
+            "liftAtMost" => {
+                self.assert_char('(')?;
+                let n = self.parse_number()?;
+                self.assert_char(')')?;
+                Ok(CullStrategy::LiftAtMost(n))
+            }
+            _ => Err(self.create_error(format!("Unknown annotation @{annotation}"))),
+        }
+    }
+
+    /// Parse an operator table like `@precedence(operand, separator)`, followed by a line for
+    /// each precedence level from the loosest to the tightest binding, like `left "+" "-"`
+    fn parse_precedence(&mut self) -> Result<MatcherType> {
+        self.assert_char('(')?;
+        self.consume_whitespace();
+        let operand = self.parse_matcher_with_modifiers(&None)?;
+        let operand = self.add_matcher(operand).id;
+        self.consume_whitespace();
+        let separator = if self.check_char(',') {
+            self.consume_whitespace();
+            let separator = self.parse_matcher_with_modifiers(&None)?;
+            self.consume_whitespace();
+            Some(self.add_matcher(separator).id)
+        } else {
+            None
+        };
+        self.assert_char(')')?;
+        let mut operators = Vec::new();
+        let mut precedence = 0;
+        while self.consume_continuation() {
+            let fixity = match self.parse_word()?.as_str() {
+                "left" => Fixity::Infix(Associativity::Left),
+                "right" => Fixity::Infix(Associativity::Right),
+                "none" => Fixity::Infix(Associativity::None),
+                "prefix" => Fixity::Prefix,
+                "postfix" => Fixity::Postfix,
+                _ => {
+                    return Err(self
+                        .create_error("Expected left, right, none, prefix or postfix".to_string()))
+                }
+            };
+            precedence += 1;
+            self.call_assert("whitespace", Self::consume_whitespace)?;
+            while self.pos < self.source.len() && self.list_should_continue() {
+                let matcher = self.parse_matcher_with_modifiers(&None)?;
+                operators.push(Operator {
+                    matcher: self.add_matcher(matcher).id,
+                    precedence,
+                    fixity,
+                });
+                self.consume_whitespace();
+            }
+        }
+        if operators.is_empty() {
+            return Err(self.create_error("Expected a line of operators".to_string()));
+        }
+        let table = OperatorTable::new(operand, separator, operators);
+        Ok(MatcherType::Precedence(table.into()))
     }
 
     fn parse_generic_params(&mut self) -> Result<Vec<String>> {
@@ -180,11 +742,10 @@ impl BNFParserState {
     fn assert_char(&mut self, match_char: char) -> Result<()> {
         match self.advance() {
             Some(c) if c == match_char => Ok(()),
-            _ => Err(FluxError::new_dyn(
-                format!("Expected {}", match_char),
-                self.pos,
-                Some(self.source.clone()),
-            )),
+            _ => Err(
+                FluxError::new_dyn(format!("Expected {}", match_char), self.pos, None)
+                    .in_chars(&self.source),
+            ),
         }
     }
 
@@ -199,12 +760,14 @@ impl BNFParserState {
     }
 
     fn check_str(&mut self, match_str: &str) -> bool {
-        if self.source[self.pos..]
-            .iter()
-            .zip(match_str.chars())
-            .all(|(c1, c2)| *c1 == c2)
+        let len = match_str.chars().count();
+        if self.pos + len <= self.source.len()
+            && self.source[self.pos..]
+                .iter()
+                .zip(match_str.chars())
+                .all(|(c1, c2)| *c1 == c2)
         {
-            self.pos += match_str.len();
+            self.pos += len;
             true
         } else {
             false
@@ -215,11 +778,10 @@ impl BNFParserState {
         let start = self.pos;
         func(self);
         if start == self.pos {
-            Err(FluxError::new_dyn(
-                format!("Expected {}", error_context),
-                self.pos,
-                Some(self.source.clone()),
-            ))
+            Err(
+                FluxError::new_dyn(format!("Expected {}", error_context), self.pos, None)
+                    .in_chars(&self.source),
+            )
         } else {
             Ok(())
         }
@@ -232,7 +794,7 @@ impl BNFParserState {
     }
 
     fn consume_comment(&mut self) {
-        while self.peek().map_or(false, |c| c != '\n') {
+        while self.peek().is_some_and(|c| c != '\n') {
             self.pos += 1;
         }
     }
@@ -246,6 +808,42 @@ impl BNFParserState {
         }
     }
 
+    /// Consume a line break and the indentation after it if the next line continues the
+    /// current rule, skipping over any blank or comment lines in between
+    fn consume_continuation(&mut self) -> bool {
+        let start = self.pos;
+        loop {
+            self.consume_whitespace();
+            if self.check_str(COMMENT_SYMBOL) {
+                self.consume_comment();
+            }
+            if !self.check_char('\n') {
+                break;
+            }
+            let line: String = self.source[self.pos..]
+                .iter()
+                .take_while(|c| **c != '\n')
+                .collect();
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with(COMMENT_SYMBOL) {
+                continue;
+            }
+            if is_continuation(&line) {
+                self.consume_whitespace();
+                return true;
+            }
+            break;
+        }
+        self.pos = start;
+        false
+    }
+
+    /// Consume whitespace between two parts of a rule, which may span onto continuation lines
+    fn consume_separator(&mut self) {
+        self.consume_whitespace();
+        self.consume_continuation();
+    }
+
     /// Identifies a non line break whitespace
     fn is_whitespace(c: char) -> bool {
         c.is_whitespace() && c != '\n'
@@ -253,7 +851,7 @@ impl BNFParserState {
 
     /// Consume all whitespace excluding line breaks
     fn consume_whitespace(&mut self) {
-        while self.peek().map_or(false, BNFParserState::is_whitespace) {
+        while self.peek().is_some_and(BNFParserState::is_whitespace) {
             self.advance();
         }
     }
@@ -269,20 +867,14 @@ impl BNFParserState {
         match self.advance() {
             Some('\\') => self.parse_escape_seq(),
             Some(c) => Ok(c),
-            _ => Err(FluxError::new(
-                "Unexpected end of file",
-                self.pos,
-                Some(self.source.clone()),
-            )),
+            _ => {
+                Err(FluxError::new("Unexpected end of file", self.pos, None).in_chars(&self.source))
+            }
         }
     }
 
     fn invalid_escape_sequence(&self) -> FluxError {
-        FluxError::new(
-            "Invalid escape sequence",
-            self.pos,
-            Some(self.source.clone()),
-        )
+        FluxError::new("Invalid escape sequence", self.pos, None).in_chars(&self.source)
     }
 
     fn parse_escape_seq(&mut self) -> Result<char> {
@@ -328,39 +920,41 @@ impl BNFParserState {
 
     fn parse_number(&mut self) -> Result<usize> {
         let mut out = String::new();
-        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
             out.push(self.advance().unwrap());
         }
         out.parse()
-            .map_err(|_| FluxError::new("Invalid number", self.pos, Some(self.source.clone())))
+            .map_err(|_| FluxError::new("Invalid number", self.pos, None).in_chars(&self.source))
     }
 
     fn parse_matcher_with_modifiers(
         &mut self,
         extras: &Option<HashMap<String, usize>>,
     ) -> Result<MatcherType> {
+        let label = self.parse_label()?;
         let inverted = self.check_char('!');
+        let lookahead = !inverted && self.check_char('&');
+        let start = self.pos;
         let mut matcher = self.parse_matcher(extras)?;
-        let _pos = self.pos;
         match self.peek() {
             Some('+') => {
                 self.advance();
-                let child = self.add_matcher(matcher);
+                let child = self.add_located_matcher(matcher, start);
                 matcher = MatcherType::Repeating(child.id, 1..=usize::MAX, None);
             }
             Some('*') => {
                 self.advance();
-                let child = self.add_matcher(matcher);
+                let child = self.add_located_matcher(matcher, start);
                 matcher = MatcherType::Repeating(child.id, 0..=usize::MAX, None);
             }
             Some('?') => {
                 self.advance();
-                let child = self.add_matcher(matcher);
+                let child = self.add_located_matcher(matcher, start);
                 matcher = MatcherType::Repeating(child.id, 0..=1, None);
             }
             Some('{') => {
                 let bounds = self.parse_repeating_bounds()?;
-                let child = self.add_matcher(matcher);
+                let child = self.add_located_matcher(matcher, start);
                 matcher = MatcherType::Repeating(child.id, bounds.0..=bounds.1, None);
             }
             _ => (),
@@ -368,10 +962,29 @@ impl BNFParserState {
         if inverted {
             let child = self.add_matcher(matcher);
             matcher = MatcherType::Inverted(child.id);
+        } else if lookahead {
+            let child = self.add_matcher(matcher);
+            matcher = MatcherType::Lookahead(child.id);
+        }
+        if let Some(label) = label {
+            let child = self.add_matcher(matcher);
+            matcher = MatcherType::Labeled(label.into(), child.id);
         }
         Ok(matcher)
     }
 
+    /// Parse a `label:` prefix, leaving the position unchanged if there isn't one
+    fn parse_label(&mut self) -> Result<Option<String>> {
+        let start = self.pos;
+        let label = self.parse_word()?;
+        if !label.is_empty() && self.check_char(':') {
+            Ok(Some(label))
+        } else {
+            self.pos = start;
+            Ok(None)
+        }
+    }
+
     fn parse_repeating_bounds(&mut self) -> Result<(usize, usize)> {
         self.assert_char('{')?;
         let min = if let Some(',') = self.peek() {
@@ -397,18 +1010,16 @@ impl BNFParserState {
         match self.peek() {
             Some('(') => {
                 self.assert_char('(')?;
+                self.consume_separator();
                 let list = self.parse_list(extras)?;
                 self.assert_char(')')?;
                 Ok(list)
             }
-            Some('[') => {
-                let pos = self.pos;
-                let matcher = self.parse_char_range().or_else(|_| {
-                    self.pos = pos;
-                    self.parse_char_set()
-                })?;
-                Ok(matcher)
-            }
+            Some('[') => self.parse_char_class(),
+            Some('\\') => match self.parse_unicode_property()? {
+                Some(ranges) => Ok(MatcherType::CharClass(CharClass::new(ranges, false))),
+                None => Err(self.create_error("Expected \\p{...} or \\P{...}".to_string())),
+            },
             Some('<') => {
                 if self.check_str("<eof>") {
                     Ok(MatcherType::Eof)
@@ -418,19 +1029,39 @@ impl BNFParserState {
                     Err(self.create_error("Expected <eof> or <nl>".to_string()))
                 }
             }
+            Some('~') => {
+                self.advance();
+                Ok(MatcherType::Cut)
+            }
             Some('"') => self.parse_string(),
             Some('i') if self.source.get(self.pos + 1) == Some(&'"') => self.parse_string(),
             Some(c) if c.is_alphabetic() => self.parse_named(extras),
-            _ => Err(FluxError::new(
-                "Unexpected character or end of file",
-                self.pos,
-                Some(self.source.clone()),
-            )),
+            _ => Err(
+                FluxError::new("Unexpected character or end of file", self.pos, None)
+                    .in_chars(&self.source),
+            ),
         }
     }
 
+    /// Parse the name of a rule, which can have dots in it like the names of rules from
+    /// imported grammars, `import.rule`
+    fn parse_name(&mut self) -> Result<String> {
+        let mut name = self.parse_word()?;
+        while self.peek() == Some('.')
+            && self
+                .source
+                .get(self.pos + 1)
+                .is_some_and(|c| c.is_alphabetic())
+        {
+            self.advance();
+            name.push('.');
+            name.push_str(&self.parse_word()?);
+        }
+        Ok(name)
+    }
+
     fn parse_named(&mut self, extras: &Option<HashMap<String, usize>>) -> Result<MatcherType> {
-        let name = self.parse_word()?;
+        let name = self.parse_name()?;
         if self.check_char('<') {
             let template = self
                 .templates
@@ -466,29 +1097,94 @@ impl BNFParserState {
         self.consume_whitespace();
         self.assert_char('>')?;
         let new_extras: HashMap<_, _> = template.names.iter().cloned().zip(params).collect();
+        let key = self.template_key(&template);
+        if self.template_stack.contains(&key) {
+            return Err(self.create_error("Template rule can't use itself".to_string()));
+        }
+        self.used_templates.insert(key);
+        self.template_stack.push(key);
         let old_pos = self.pos;
         self.pos = template.rule_start;
-        let parsed = self.parse_list(&Some(new_extras))?;
+        // Templates from other files are parsed with the rules of the file they came from
+        let old_scope = template.module.map(|module| {
+            (
+                std::mem::replace(&mut self.source, module.source.clone()),
+                std::mem::replace(&mut self.id_map, module.id_map.clone()),
+                std::mem::replace(&mut self.templates, module.templates.clone()),
+            )
+        });
+        let parsed = self.parse_list(&Some(new_extras));
+        self.template_stack.pop();
+        if let Some((source, id_map, templates)) = old_scope {
+            self.source = source;
+            self.id_map = id_map;
+            self.templates = templates;
+        }
         self.pos = old_pos;
-        Ok(parsed)
+        parsed
     }
 
-    fn parse_char_range(&mut self) -> Result<MatcherType> {
+    fn parse_char_class(&mut self) -> Result<MatcherType> {
         self.assert_char('[')?;
         let inverted = self.check_char('^');
-        let low = self.parse_char_or_escape_seq()?;
-        self.assert_char('-')?;
-        let high = self.parse_char_or_escape_seq()?;
-        self.assert_char(']')?;
-        Ok(MatcherType::CharRange(low..=high, inverted))
+        let mut ranges = Vec::new();
+        while !self.check_char(']') {
+            if let Some(property) = self.parse_unicode_property()? {
+                ranges.extend(property);
+                continue;
+            }
+            let low = self.parse_class_char()?;
+            if self.peek() == Some('-') && self.source.get(self.pos + 1) != Some(&']') {
+                self.advance();
+                let high = self.parse_class_char()?;
+                if high < low {
+                    return Err(self.create_error(format!("Invalid character range {low}-{high}")));
+                }
+                ranges.push(low..=high);
+            } else {
+                ranges.push(low..=low);
+            }
+        }
+        Ok(MatcherType::CharClass(CharClass::new(ranges, inverted)))
     }
 
-    fn parse_char_set(&mut self) -> Result<MatcherType> {
-        self.assert_char('[')?;
-        let inverted = self.check_char('^');
-        let chars = self.parse_str_chars(']')?;
-        self.assert_char(']')?;
-        Ok(MatcherType::CharSet(chars.chars().collect(), inverted))
+    /// Parse a Unicode general category or script like `\p{L}` or `\p{Greek}` into its
+    /// ranges, or `\P{...}` into the ranges of every character outside it
+    fn parse_unicode_property(&mut self) -> Result<Option<Vec<RangeInclusive<char>>>> {
+        let negated = if self.check_str("\\p{") {
+            false
+        } else if self.check_str("\\P{") {
+            true
+        } else {
+            return Ok(None);
+        };
+        let name = self.parse_str_chars('}')?;
+        self.assert_char('}')?;
+        let ranges = char_class::unicode_property(&name)
+            .ok_or_else(|| self.create_error(format!("Unknown Unicode property {name}")))?;
+        Ok(Some(if negated {
+            char_class::complement(ranges)
+        } else {
+            ranges
+        }))
+    }
+
+    /// Parse a single character inside a character class, where `]`, `-`, `^` and `[` can
+    /// also be escaped
+    fn parse_class_char(&mut self) -> Result<char> {
+        match self.peek() {
+            Some('\\') => {
+                self.advance();
+                match self.peek() {
+                    Some(c @ (']' | '-' | '^' | '[')) => {
+                        self.advance();
+                        Ok(c)
+                    }
+                    _ => self.parse_escape_seq(),
+                }
+            }
+            _ => self.parse_char_or_escape_seq(),
+        }
     }
 
     fn parse_string(&mut self) -> Result<MatcherType> {
@@ -509,31 +1205,32 @@ impl BNFParserState {
     }
 
     fn list_should_continue(&self) -> bool {
-        let next = self.peek();
-        next != Some('\n') && next != Some('/')
+        !matches!(self.peek(), Some('\n' | '/' | ')'))
     }
 
     fn parse_list(&mut self, extras: &Option<HashMap<String, usize>>) -> Result<MatcherType> {
         let mut matcher_list = Vec::new();
         let mut choices = Vec::new();
+        let mut choice_start = self.pos;
         while self.pos < self.source.len() && self.list_should_continue() {
             matcher_list.push(self.parse_matcher_with_modifiers(extras)?);
-            if !self.call_check(Self::consume_whitespace) {
+            if !self.call_check(Self::consume_separator) {
                 break;
             }
             if self.check_char('|') {
                 let matcher = self.maybe_list(matcher_list);
-                self.consume_whitespace();
+                self.consume_separator();
                 matcher_list = Vec::new();
-                choices.push(matcher);
+                choices.push((choice_start, matcher));
+                choice_start = self.pos;
             }
         }
         if !choices.is_empty() {
             let list_matcher = self.maybe_list(matcher_list);
-            choices.push(list_matcher);
+            choices.push((choice_start, list_matcher));
             let choices = choices
                 .into_iter()
-                .map(|m| self.add_matcher(m).id)
+                .map(|(start, m)| self.add_located_matcher(m, start).id)
                 .collect();
             Ok(MatcherType::Choice(choices, None))
         } else {
@@ -542,6 +1239,6 @@ impl BNFParserState {
     }
 
     fn create_error(&self, msg: String) -> FluxError {
-        FluxError::new_dyn(msg, self.pos, Some(self.source.clone()))
+        FluxError::new_dyn(msg, self.pos, None).in_chars(&self.source)
     }
 }
@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::flatten_wrappers;
+use crate::analysis::Diagnostic;
+use crate::char_class::CharClass;
+use crate::error::{FluxError, Result};
+use crate::lexer::{CullStrategy, Lexer};
+use crate::matchers::{Location, Matcher, MatcherType};
+
+const COMMENT_SYMBOL: char = ';';
+
+/// The core rules from appendix B.1 of RFC 5234, added to a grammar when it uses them
+/// without defining them
+const CORE_RULES: &str = r#"ALPHA = %x41-5A / %x61-7A
+BIT = "0" / "1"
+CHAR = %x01-7F
+CR = %x0D
+CRLF = CR LF
+CTL = %x00-1F / %x7F
+DIGIT = %x30-39
+DQUOTE = %x22
+HEXDIG = DIGIT / "A" / "B" / "C" / "D" / "E" / "F"
+HTAB = %x09
+LF = %x0A
+LWSP = *(WSP / CRLF WSP)
+OCTET = %x00-FF
+SP = %x20
+VCHAR = %x21-7E
+WSP = SP / HTAB
+"#;
+
+pub(super) fn build(input: &str) -> Result<(Lexer, Vec<Diagnostic>)> {
+    let mut state = ABNFParserState {
+        source: input.chars().collect(),
+        pos: 0,
+        indent: 0,
+        rules: HashMap::new(),
+        matchers: Vec::new(),
+        first_rule: None,
+    };
+    state.parse_rulelist()?;
+    state.add_core_rules()?;
+    flatten_wrappers(&mut state.matchers);
+    let root = match state.rules.get("root") {
+        Some(rule) => rule.id,
+        None => state
+            .first_rule
+            .ok_or_else(|| FluxError::new("No rules defined", 0, None).in_chars(&state.source))?,
+    };
+    let names = state
+        .rules
+        .values()
+        .map(|rule| {
+            (
+                state.matchers[rule.id].name.as_deref().unwrap().to_string(),
+                rule.id,
+            )
+        })
+        .collect();
+    let lexer = Lexer::new(root, names, state.matchers);
+    let diagnostics = lexer.validate();
+    Ok((lexer, diagnostics))
+}
+
+struct Rule {
+    id: usize,
+    /// The name as it was first written, since ABNF rule names are case-insensitive
+    name: String,
+    defined: bool,
+    /// Where the rule was first used, to point at if it's never defined
+    first_use: usize,
+}
+
+struct ABNFParserState {
+    source: Arc<[char]>,
+    pos: usize,
+    /// The indentation of the rule being parsed. Lines indented further continue it.
+    indent: usize,
+    /// Rules by their lowercased names
+    rules: HashMap<String, Rule>,
+    matchers: Vec<Matcher>,
+    first_rule: Option<usize>,
+}
+
+impl ABNFParserState {
+    fn parse_rulelist(&mut self) -> Result<()> {
+        loop {
+            self.skip_blank_lines();
+            if self.pos >= self.source.len() {
+                return Ok(());
+            }
+            self.indent = 0;
+            while matches!(self.peek(), Some(' ' | '\t')) {
+                self.indent += 1;
+                self.advance();
+            }
+            self.parse_rule()?;
+        }
+    }
+
+    fn parse_rule(&mut self) -> Result<()> {
+        let rule_pos = self.pos;
+        let name = self.parse_rulename()?;
+        self.consume_c_wsp();
+        let incremental = self.check_str("=/");
+        if !incremental {
+            self.assert_char('=')?;
+        }
+        self.consume_c_wsp();
+        let alternation = self.parse_alternation()?;
+        self.consume_c_wsp();
+        if !matches!(self.peek(), None | Some('\n' | '\r' | COMMENT_SYMBOL)) {
+            return Err(self.create_error("Expected end of rule".into()));
+        }
+        self.define(name, alternation, incremental, rule_pos)
+    }
+
+    fn define(
+        &mut self,
+        name: String,
+        alternation: MatcherType,
+        incremental: bool,
+        rule_pos: usize,
+    ) -> Result<()> {
+        let id = self.rule_id(&name, rule_pos);
+        let rule = self.rules.get_mut(&name.to_ascii_lowercase()).unwrap();
+        match (incremental, rule.defined) {
+            (false, true) => Err(FluxError::new_dyn(
+                format!("Rule {name} is defined more than once"),
+                rule_pos,
+                None,
+            )
+            .in_chars(&self.source)),
+            (true, false) => Err(FluxError::new_dyn(
+                format!("Rule {name} is extended with =/ before it's defined"),
+                rule_pos,
+                None,
+            )
+            .in_chars(&self.source)),
+            (false, false) => {
+                rule.defined = true;
+                let name = rule.name.clone();
+                self.first_rule.get_or_insert(id);
+                let location = self.location(rule_pos);
+                let matcher = &mut self.matchers[id];
+                matcher.name = Some(name).into();
+                matcher.matcher_type = alternation;
+                matcher.show_in_errors = true;
+                matcher.location = Some(location);
+                Ok(())
+            }
+            (true, true) => {
+                let mut children = match std::mem::replace(
+                    &mut self.matchers[id].matcher_type,
+                    MatcherType::Placeholder,
+                ) {
+                    MatcherType::Choice(children, _) => children,
+                    other => vec![self.add_located_matcher(other, rule_pos)],
+                };
+                match alternation {
+                    MatcherType::Choice(alternatives, _) => children.extend(alternatives),
+                    other => children.push(self.add_located_matcher(other, rule_pos)),
+                }
+                self.matchers[id].matcher_type = MatcherType::Choice(children, None);
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the matcher for a rule, adding a placeholder for it if it hasn't been seen yet
+    fn rule_id(&mut self, name: &str, pos: usize) -> usize {
+        let key = name.to_ascii_lowercase();
+        if let Some(rule) = self.rules.get(&key) {
+            return rule.id;
+        }
+        let id = self.add_matcher(MatcherType::Placeholder);
+        self.rules.insert(
+            key,
+            Rule {
+                id,
+                name: name.to_string(),
+                defined: false,
+                first_use: pos,
+            },
+        );
+        id
+    }
+
+    /// Define the core rules the grammar uses but doesn't define, failing on any other rules
+    /// that aren't defined
+    fn add_core_rules(&mut self) -> Result<()> {
+        let source = std::mem::replace(&mut self.source, CORE_RULES.chars().collect());
+        let result = self.add_core_rules_from_source(&source);
+        self.source = source;
+        result
+    }
+
+    fn add_core_rules_from_source(&mut self, source: &Arc<[char]>) -> Result<()> {
+        loop {
+            let mut undefined: Vec<_> = self
+                .rules
+                .iter()
+                .filter(|(_, rule)| !rule.defined)
+                .map(|(key, rule)| (key.clone(), rule.name.clone(), rule.first_use))
+                .collect();
+            if undefined.is_empty() {
+                return Ok(());
+            }
+            undefined.sort_by_key(|(_, _, first_use)| *first_use);
+            for (key, name, first_use) in undefined {
+                let line = CORE_RULES
+                    .lines()
+                    .scan(0, |start, line| {
+                        let line_start = *start;
+                        *start += line.len() + 1;
+                        Some((line_start, line))
+                    })
+                    .find(|(_, line)| line.split(' ').next().unwrap().eq_ignore_ascii_case(&key));
+                let Some((line_start, _)) = line else {
+                    return Err(FluxError::new_dyn(
+                        format!("No rule with name {name}"),
+                        first_use,
+                        None,
+                    )
+                    .in_chars(source));
+                };
+                self.pos = line_start;
+                self.indent = 0;
+                self.parse_rule()?;
+            }
+        }
+    }
+
+    fn parse_rulename(&mut self) -> Result<String> {
+        if !self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            return Err(self.create_error("Expected a rule name".into()));
+        }
+        let mut name = String::new();
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            name.push(self.advance().unwrap());
+        }
+        Ok(name)
+    }
+
+    fn parse_alternation(&mut self) -> Result<MatcherType> {
+        let start = self.pos;
+        let mut alternatives = vec![(start, self.parse_concatenation()?)];
+        loop {
+            let before = self.pos;
+            self.consume_c_wsp();
+            if !self.check_char('/') {
+                self.pos = before;
+                break;
+            }
+            self.consume_c_wsp();
+            let start = self.pos;
+            alternatives.push((start, self.parse_concatenation()?));
+        }
+        if alternatives.len() == 1 {
+            return Ok(alternatives.remove(0).1);
+        }
+        let children = alternatives
+            .into_iter()
+            .map(|(pos, alternative)| self.add_located_matcher(alternative, pos))
+            .collect();
+        Ok(MatcherType::Choice(children, None))
+    }
+
+    fn parse_concatenation(&mut self) -> Result<MatcherType> {
+        let mut items = vec![self.parse_repetition()?];
+        loop {
+            let before = self.pos;
+            if !(self.consume_c_wsp() && self.starts_element()) {
+                self.pos = before;
+                break;
+            }
+            items.push(self.parse_repetition()?);
+        }
+        if items.len() == 1 {
+            return Ok(items.remove(0));
+        }
+        let children = items.into_iter().map(|m| self.add_matcher(m)).collect();
+        Ok(MatcherType::List(children))
+    }
+
+    fn starts_element(&self) -> bool {
+        matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "([\"%<*".contains(c))
+    }
+
+    /// Parse an element with an optional repeat prefix, like `*DIGIT`, `1*3ALPHA` or `2HEXDIG`
+    fn parse_repetition(&mut self) -> Result<MatcherType> {
+        let start = self.pos;
+        let min = self.parse_number()?;
+        let range = if self.check_char('*') {
+            Some(min.unwrap_or(0)..=self.parse_number()?.unwrap_or(usize::MAX))
+        } else {
+            min.map(|n| n..=n)
+        };
+        let element = self.parse_element()?;
+        match range {
+            None => Ok(element),
+            Some(range) if range == (1..=1) => Ok(element),
+            Some(range) if range.is_empty() => {
+                Err(FluxError::new("Invalid repetition", start, None).in_chars(&self.source))
+            }
+            Some(range) => {
+                let child = self.add_located_matcher(element, start);
+                Ok(MatcherType::Repeating(child, range, None))
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Option<usize>> {
+        let mut out = String::new();
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            out.push(self.advance().unwrap());
+        }
+        if out.is_empty() {
+            return Ok(None);
+        }
+        out.parse()
+            .map(Some)
+            .map_err(|_| FluxError::new("Invalid number", self.pos, None).in_chars(&self.source))
+    }
+
+    fn parse_element(&mut self) -> Result<MatcherType> {
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self.parse_rulename()?;
+                Ok(MatcherType::Wrapper(self.rule_id(&name, start)))
+            }
+            Some('(') => {
+                self.advance();
+                let group = self.parse_group()?;
+                self.assert_char(')')?;
+                Ok(group)
+            }
+            Some('[') => {
+                self.advance();
+                let option = self.parse_group()?;
+                self.assert_char(']')?;
+                let child = self.add_located_matcher(option, start);
+                Ok(MatcherType::Repeating(child, 0..=1, None))
+            }
+            Some('"') => self.parse_char_val(false),
+            Some('%') => {
+                self.advance();
+                match self.advance() {
+                    Some('s' | 'S') => self.parse_char_val(true),
+                    Some('i' | 'I') => self.parse_char_val(false),
+                    Some('x' | 'X') => self.parse_num_val(16),
+                    Some('d' | 'D') => self.parse_num_val(10),
+                    Some('b' | 'B') => self.parse_num_val(2),
+                    _ => Err(FluxError::new("Expected %x, %d, %b, %s or %i", start, None)
+                        .in_chars(&self.source)),
+                }
+            }
+            Some('<') => Err(self.create_error("Prose values like <...> can't be parsed".into())),
+            _ => {
+                Err(self
+                    .create_error("Expected a rule name, group, option, string or value".into()))
+            }
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<MatcherType> {
+        self.consume_c_wsp();
+        let alternation = self.parse_alternation()?;
+        self.consume_c_wsp();
+        Ok(alternation)
+    }
+
+    /// Parse a quoted string, which ABNF matches case-insensitively unless it has a `%s` prefix
+    fn parse_char_val(&mut self, case_sensitive: bool) -> Result<MatcherType> {
+        self.assert_char('"')?;
+        let mut chars = Vec::new();
+        loop {
+            match self.advance() {
+                Some('"') => {
+                    // Without letters, matching case-insensitively would change nothing
+                    let case_sensitive =
+                        case_sensitive || !chars.iter().any(char::is_ascii_alphabetic);
+                    return Ok(MatcherType::String(chars, case_sensitive));
+                }
+                Some('\n' | '\r') | None => {
+                    return Err(self.create_error("Unterminated string".into()));
+                }
+                Some(c) => chars.push(c),
+            }
+        }
+    }
+
+    /// Parse a numeric value after its `%x`, `%d` or `%b`: a single character, a range like
+    /// `%x30-39`, or a string of characters like `%d13.10`
+    fn parse_num_val(&mut self, radix: u32) -> Result<MatcherType> {
+        let first = self.parse_value(radix)?;
+        if self.check_char('-') {
+            let last = self.parse_value(radix)?;
+            if last < first {
+                return Err(self.create_error("Invalid character range".into()));
+            }
+            return Ok(MatcherType::CharClass(CharClass::new(
+                vec![first..=last],
+                false,
+            )));
+        }
+        let mut chars = vec![first];
+        while self.check_char('.') {
+            chars.push(self.parse_value(radix)?);
+        }
+        Ok(MatcherType::String(chars, true))
+    }
+
+    fn parse_value(&mut self, radix: u32) -> Result<char> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_digit(radix)) {
+            self.advance();
+        }
+        let digits: String = self.source[start..self.pos].iter().collect();
+        u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| {
+                FluxError::new("Invalid character value", start, None).in_chars(&self.source)
+            })
+    }
+
+    /// Consume whitespace, comments, and line breaks followed by lines which continue the
+    /// rule, returning whether anything was consumed
+    fn consume_c_wsp(&mut self) -> bool {
+        let start = self.pos;
+        loop {
+            while matches!(self.peek(), Some(' ' | '\t')) {
+                self.advance();
+            }
+            let line_end = self.pos;
+            if self.peek() == Some(COMMENT_SYMBOL) {
+                while !matches!(self.peek(), None | Some('\n' | '\r')) {
+                    self.advance();
+                }
+            }
+            if !matches!(self.peek(), Some('\n' | '\r')) {
+                self.pos = line_end;
+                break;
+            }
+            self.skip_blank_lines();
+            let line_start = self.pos;
+            while matches!(self.peek(), Some(' ' | '\t')) {
+                self.advance();
+            }
+            if self.pos - line_start <= self.indent || self.pos >= self.source.len() {
+                self.pos = line_end;
+                break;
+            }
+        }
+        self.pos > start
+    }
+
+    /// Skip past lines with nothing but whitespace and comments, stopping at the start of the
+    /// next line with anything else on it
+    fn skip_blank_lines(&mut self) {
+        loop {
+            let line_start = self.pos;
+            while matches!(self.peek(), Some(' ' | '\t')) {
+                self.advance();
+            }
+            if self.peek() == Some(COMMENT_SYMBOL) {
+                while !matches!(self.peek(), None | Some('\n' | '\r')) {
+                    self.advance();
+                }
+            }
+            match self.peek() {
+                Some('\r') => {
+                    self.advance();
+                    self.check_char('\n');
+                }
+                Some('\n') => {
+                    self.advance();
+                }
+                None => return,
+                Some(_) => {
+                    self.pos = line_start;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn add_matcher(&mut self, matcher_type: MatcherType) -> usize {
+        let id = self.matchers.len();
+        self.matchers.push(Matcher {
+            name: None.into(),
+            id,
+            matcher_type,
+            cull_strategy: CullStrategy::None,
+            show_in_errors: false,
+            left_recursive: false,
+            memoize: false,
+            recover: None,
+            location: None,
+        });
+        id
+    }
+
+    fn add_located_matcher(&mut self, matcher_type: MatcherType, pos: usize) -> usize {
+        let location = self.location(pos);
+        let id = self.add_matcher(matcher_type);
+        self.matchers[id].location = Some(location);
+        id
+    }
+
+    fn location(&self, pos: usize) -> Location {
+        Location {
+            pos,
+            source: self.source.clone(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source.get(self.pos).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn check_char(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn check_str(&mut self, s: &str) -> bool {
+        let len = s.chars().count();
+        let matches = self
+            .source
+            .get(self.pos..self.pos + len)
+            .is_some_and(|found| found.iter().copied().eq(s.chars()));
+        if matches {
+            self.pos += len;
+        }
+        matches
+    }
+
+    fn assert_char(&mut self, c: char) -> Result<()> {
+        if self.check_char(c) {
+            Ok(())
+        } else {
+            Err(self.create_error(format!("Expected '{c}'")))
+        }
+    }
+
+    fn create_error(&self, msg: String) -> FluxError {
+        FluxError::new_dyn(msg, self.pos, None).in_chars(&self.source)
+    }
+}
@@ -0,0 +1,245 @@
+use std::ops::RangeInclusive;
+
+use crate::unicode_tables::{GENERAL_CATEGORIES, SCRIPTS};
+
+/// A set of characters made of any number of ranges, compiled into a bitmap for ASCII
+/// characters and a sorted list of disjoint ranges for everything else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClass {
+    ascii: u128,
+    ranges: Box<[RangeInclusive<char>]>,
+    inverted: bool,
+}
+
+impl CharClass {
+    /// Create a class matching any character in `ranges`, or any character not in them if
+    /// `inverted` is set. Single characters are given as ranges of one, like `'a'..='a'`.
+    pub fn new(ranges: impl IntoIterator<Item = RangeInclusive<char>>, inverted: bool) -> Self {
+        let mut ascii = 0u128;
+        let mut rest = Vec::new();
+        for range in normalize(ranges.into_iter().collect()) {
+            let (low, high) = (*range.start(), *range.end());
+            for c in (low as u32)..=(high as u32).min(127) {
+                ascii |= 1 << c;
+            }
+            if high as u32 >= 128 {
+                rest.push(low.max('\u{80}')..=high);
+            }
+        }
+        CharClass {
+            ascii,
+            ranges: rest.into(),
+            inverted,
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let found = if c.is_ascii() {
+            self.ascii & (1 << c as u32) != 0
+        } else {
+            self.ranges
+                .binary_search_by(|range| {
+                    if *range.end() < c {
+                        std::cmp::Ordering::Less
+                    } else if *range.start() > c {
+                        std::cmp::Ordering::Greater
+                    } else {
+                        std::cmp::Ordering::Equal
+                    }
+                })
+                .is_ok()
+        };
+        found ^ self.inverted
+    }
+
+    pub fn is_inverted(&self) -> bool {
+        self.inverted
+    }
+
+    /// Get the sorted, disjoint ranges making up the class, ignoring inversion
+    pub fn ranges(&self) -> Vec<RangeInclusive<char>> {
+        let mut ranges: Vec<RangeInclusive<char>> = Vec::new();
+        for c in (0..128u8)
+            .filter(|c| self.ascii & (1 << c) != 0)
+            .map(char::from)
+        {
+            match ranges.last_mut() {
+                Some(last) if next_char(*last.end()) == Some(c) => *last = *last.start()..=c,
+                _ => ranges.push(c..=c),
+            }
+        }
+        ranges.extend(self.ranges.iter().cloned());
+        normalize(ranges)
+    }
+
+    /// Split the class into the Unicode categories and scripts it fully covers, and the
+    /// ranges left over outside of them, if that takes fewer ranges to write out
+    pub(crate) fn unicode_properties(
+        &self,
+    ) -> Option<(Vec<&'static str>, Vec<RangeInclusive<char>>)> {
+        let ranges = self.ranges();
+        let groups = GENERAL_CATEGORIES.iter().map(|(cat, _)| &cat[..1]);
+        let candidates = groups
+            .chain(GENERAL_CATEGORIES.iter().map(|(cat, _)| *cat))
+            .chain(SCRIPTS.iter().map(|(sc, _)| *sc));
+        let mut properties: Vec<&'static str> = Vec::new();
+        let mut covered = Vec::new();
+        for name in candidates {
+            if properties.contains(&name) {
+                continue;
+            }
+            let Some(property) = unicode_property(name) else {
+                continue;
+            };
+            let within_class = property.iter().all(|r| contains_range(&ranges, r));
+            if within_class && !property.iter().all(|r| contains_range(&covered, r)) {
+                properties.push(name);
+                covered = normalize([covered, property].concat());
+            }
+        }
+        let rest = complement([complement(ranges.clone()), covered].concat());
+        (properties.len() + rest.len() < ranges.len()).then_some((properties, rest))
+    }
+}
+
+/// Check if sorted, disjoint `ranges` cover all of `range`
+fn contains_range(ranges: &[RangeInclusive<char>], range: &RangeInclusive<char>) -> bool {
+    let i = ranges.partition_point(|r| r.end() < range.start());
+    ranges
+        .get(i)
+        .is_some_and(|r| r.start() <= range.start() && r.end() >= range.end())
+}
+
+/// Long names of the general categories, mapped to the short names used in the tables
+const CATEGORY_NAMES: &[(&str, &str)] = &[
+    ("Other", "C"),
+    ("Control", "Cc"),
+    ("Format", "Cf"),
+    ("Unassigned", "Cn"),
+    ("Private_Use", "Co"),
+    ("Letter", "L"),
+    ("Cased_Letter", "LC"),
+    ("Lowercase_Letter", "Ll"),
+    ("Modifier_Letter", "Lm"),
+    ("Other_Letter", "Lo"),
+    ("Titlecase_Letter", "Lt"),
+    ("Uppercase_Letter", "Lu"),
+    ("Mark", "M"),
+    ("Spacing_Mark", "Mc"),
+    ("Enclosing_Mark", "Me"),
+    ("Nonspacing_Mark", "Mn"),
+    ("Number", "N"),
+    ("Decimal_Number", "Nd"),
+    ("Letter_Number", "Nl"),
+    ("Other_Number", "No"),
+    ("Punctuation", "P"),
+    ("Connector_Punctuation", "Pc"),
+    ("Dash_Punctuation", "Pd"),
+    ("Close_Punctuation", "Pe"),
+    ("Final_Punctuation", "Pf"),
+    ("Initial_Punctuation", "Pi"),
+    ("Other_Punctuation", "Po"),
+    ("Open_Punctuation", "Ps"),
+    ("Symbol", "S"),
+    ("Currency_Symbol", "Sc"),
+    ("Modifier_Symbol", "Sk"),
+    ("Math_Symbol", "Sm"),
+    ("Other_Symbol", "So"),
+    ("Separator", "Z"),
+    ("Line_Separator", "Zl"),
+    ("Paragraph_Separator", "Zp"),
+    ("Space_Separator", "Zs"),
+];
+
+/// Get the ranges of a Unicode general category or script, as written inside `\p{...}`.
+///
+/// Categories can be given by their short or long names, like `L`, `Lu` or
+/// `Uppercase_Letter`, and scripts by name, like `Greek`. Either can also be qualified as
+/// `gc=Lu` / `General_Category=Lu` or `sc=Greek` / `Script=Greek`.
+pub fn unicode_property(name: &str) -> Option<Vec<RangeInclusive<char>>> {
+    let (category, script) = match name.split_once('=') {
+        Some(("gc" | "General_Category", value)) => (Some(value), None),
+        Some(("sc" | "Script", value)) => (None, Some(value)),
+        Some(_) => return None,
+        None => (Some(name), Some(name)),
+    };
+    let category = category.map(|name| {
+        CATEGORY_NAMES
+            .iter()
+            .find(|(long, _)| *long == name)
+            .map_or(name, |(_, short)| short)
+    });
+    let tables: Vec<&[(u32, u32)]> = match category {
+        Some("LC") => GENERAL_CATEGORIES
+            .iter()
+            .filter(|(cat, _)| matches!(*cat, "Lu" | "Ll" | "Lt"))
+            .map(|(_, ranges)| *ranges)
+            .collect(),
+        Some(group) if group.len() == 1 => GENERAL_CATEGORIES
+            .iter()
+            .filter(|(cat, _)| cat.starts_with(group))
+            .map(|(_, ranges)| *ranges)
+            .collect(),
+        _ => category
+            .and_then(|name| GENERAL_CATEGORIES.iter().find(|(cat, _)| *cat == name))
+            .or_else(|| script.and_then(|name| SCRIPTS.iter().find(|(sc, _)| *sc == name)))
+            .map(|(_, ranges)| *ranges)
+            .into_iter()
+            .collect(),
+    };
+    if tables.is_empty() {
+        return None;
+    }
+    let ranges = tables
+        .into_iter()
+        .flatten()
+        .filter_map(|(low, high)| Some(char::from_u32(*low)?..=char::from_u32(*high)?))
+        .collect();
+    Some(normalize(ranges))
+}
+
+/// Get the ranges of every character not covered by `ranges`
+pub(crate) fn complement(ranges: Vec<RangeInclusive<char>>) -> Vec<RangeInclusive<char>> {
+    let mut gaps = Vec::new();
+    let mut next = Some('\0');
+    for range in normalize(ranges) {
+        if let Some(low) = next.filter(|c| c < range.start()) {
+            gaps.extend(prev_char(*range.start()).map(|high| low..=high));
+        }
+        next = next_char(*range.end());
+    }
+    gaps.extend(next.map(|low| low..=char::MAX));
+    gaps
+}
+
+/// Sort ranges and merge any that overlap or touch, dropping empty ones
+pub(crate) fn normalize(mut ranges: Vec<RangeInclusive<char>>) -> Vec<RangeInclusive<char>> {
+    ranges.retain(|r| r.start() <= r.end());
+    ranges.sort_by_key(|r| *r.start());
+    let mut merged: Vec<RangeInclusive<char>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if next_char(*last.end()).is_none_or(|c| c >= *range.start()) => {
+                if range.end() > last.end() {
+                    *last = *last.start()..=*range.end();
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => char::from_u32((c as u32).checked_sub(1)?),
+    }
+}
@@ -0,0 +1,262 @@
+//! Compile grammars into Rust source at build time, so they don't need to be parsed at
+//! runtime.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use crate::bnf;
+use crate::char_class::CharClass;
+use crate::lexer::{CullStrategy, Lexer};
+use crate::matchers::{Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity, Operator, OperatorTable};
+
+/// Compile the BNF grammar at `path` into `<name>.rs` in `OUT_DIR`, where `<name>` is the
+/// grammar's file name without its extension. Meant to be called from a build script, it
+/// panics with the grammar's errors so they fail the build.
+///
+/// The generated file defines `pub fn lexer() -> &'static Lexer`, and is used with
+/// `include!(concat!(env!("OUT_DIR"), "/<name>.rs"))`.
+pub fn compile_grammar(path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR should be set by cargo");
+    let dir = path.parent().unwrap_or(Path::new(""));
+    println!("cargo:rerun-if-changed={}", path.display());
+    let input = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Couldn't read {}: {e}", path.display()));
+    let loader = |import: &str| {
+        let import = dir.join(import);
+        println!("cargo:rerun-if-changed={}", import.display());
+        std::fs::read_to_string(import)
+    };
+    let lexer = bnf::parse_with_loader(&input, &loader)
+        .unwrap_or_else(|e| panic!("Invalid grammar {}\n{e}", path.display()));
+    let name = path.file_stem().expect("grammar path should be a file");
+    let out = Path::new(&out_dir).join(name).with_extension("rs");
+    std::fs::write(&out, lexer.to_rust())
+        .unwrap_or_else(|e| panic!("Couldn't write {}: {e}", out.display()));
+}
+
+/// A matcher in the table of a compiled grammar
+#[doc(hidden)]
+pub struct StaticMatcher {
+    pub name: Option<&'static str>,
+    pub cull_strategy: CullStrategy,
+    pub show_in_errors: bool,
+    pub memoize: bool,
+    pub recover: Option<usize>,
+    pub matcher_type: StaticMatcherType,
+}
+
+#[doc(hidden)]
+pub enum StaticMatcherType {
+    String(&'static str, bool),
+    CharClass(&'static [(char, char)], bool),
+    List(&'static [usize]),
+    Choice(&'static [usize]),
+    Repeating(usize, usize, usize),
+    Inverted(usize),
+    Lookahead(usize),
+    Labeled(&'static str, usize),
+    Precedence(usize, Option<usize>, &'static [(usize, usize, Fixity)]),
+    Wrapper(usize),
+    Cut,
+    Eof,
+    Newline,
+}
+
+/// Build the lexer of a compiled grammar from its table
+#[doc(hidden)]
+pub fn load(
+    root: usize,
+    retain_empty: bool,
+    names: &[(&str, usize)],
+    matchers: &[StaticMatcher],
+) -> Lexer {
+    let matchers = matchers
+        .iter()
+        .enumerate()
+        .map(|(id, matcher)| Matcher {
+            name: matcher.name.map(String::from).into(),
+            id,
+            matcher_type: match matcher.matcher_type {
+                StaticMatcherType::String(s, case_sensitive) => {
+                    MatcherType::String(s.chars().collect(), case_sensitive)
+                }
+                StaticMatcherType::CharClass(ranges, inverted) => MatcherType::CharClass(
+                    CharClass::new(ranges.iter().map(|(low, high)| *low..=*high), inverted),
+                ),
+                StaticMatcherType::List(children) => MatcherType::List(children.to_vec()),
+                StaticMatcherType::Choice(children) => MatcherType::Choice(children.to_vec(), None),
+                StaticMatcherType::Repeating(child, min, max) => {
+                    MatcherType::Repeating(child, min..=max, None)
+                }
+                StaticMatcherType::Inverted(child) => MatcherType::Inverted(child),
+                StaticMatcherType::Lookahead(child) => MatcherType::Lookahead(child),
+                StaticMatcherType::Labeled(label, child) => {
+                    MatcherType::Labeled(label.into(), child)
+                }
+                StaticMatcherType::Precedence(operand, separator, operators) => {
+                    let operators = operators
+                        .iter()
+                        .map(|&(matcher, precedence, fixity)| Operator {
+                            matcher,
+                            precedence,
+                            fixity,
+                        })
+                        .collect();
+                    let table = OperatorTable::new(operand, separator, operators);
+                    MatcherType::Precedence(table.into())
+                }
+                StaticMatcherType::Wrapper(child) => MatcherType::Wrapper(child),
+                StaticMatcherType::Cut => MatcherType::Cut,
+                StaticMatcherType::Eof => MatcherType::Eof,
+                StaticMatcherType::Newline => MatcherType::Newline,
+            },
+            cull_strategy: matcher.cull_strategy,
+            show_in_errors: matcher.show_in_errors,
+            left_recursive: false,
+            memoize: matcher.memoize,
+            recover: matcher.recover,
+            location: None,
+        })
+        .collect();
+    let names = names
+        .iter()
+        .map(|(name, id)| (name.to_string(), *id))
+        .collect();
+    let mut lexer = Lexer::new(root, names, matchers);
+    lexer.set_retain_empty(retain_empty);
+    lexer
+}
+
+/// Write the Rust source of a compiled grammar, defining a `lexer` function which builds the
+/// lexer from a table of its matchers the first time it's called
+pub(crate) fn write_rust(
+    f: &mut impl Write,
+    root: usize,
+    retain_empty: bool,
+    names: &HashMap<String, usize>,
+    matchers: &[Matcher],
+) -> fmt::Result {
+    writeln!(f, "// Generated by flux_bnf. Don't edit it by hand.")?;
+    writeln!(f)?;
+    writeln!(f, "pub fn lexer() -> &'static ::flux_bnf::lexer::Lexer {{")?;
+    writeln!(
+        f,
+        "    use ::flux_bnf::codegen::{{StaticMatcher as M, StaticMatcherType as T}};"
+    )?;
+    writeln!(f, "    use ::flux_bnf::lexer::CullStrategy as C;")?;
+    if matchers
+        .iter()
+        .any(|m| matches!(m.matcher_type, MatcherType::Precedence(_)))
+    {
+        writeln!(
+            f,
+            "    use ::flux_bnf::precedence::{{Associativity as A, Fixity as F}};"
+        )?;
+    }
+    writeln!(f)?;
+    let mut names: Vec<_> = names.iter().collect();
+    names.sort_by_key(|(_, id)| **id);
+    writeln!(f, "    static NAMES: &[(&str, usize)] = &[")?;
+    for (name, id) in names {
+        writeln!(f, "        ({name:?}, {id}),")?;
+    }
+    writeln!(f, "    ];")?;
+    writeln!(f, "    static MATCHERS: &[M] = &[")?;
+    for matcher in matchers {
+        write!(f, "        M {{ name: {:?}, ", matcher.name.as_deref())?;
+        write!(f, "cull_strategy: ")?;
+        match matcher.cull_strategy {
+            CullStrategy::None => write!(f, "C::None"),
+            CullStrategy::DeleteAll => write!(f, "C::DeleteAll"),
+            CullStrategy::DeleteChildren => write!(f, "C::DeleteChildren"),
+            CullStrategy::LiftChildren => write!(f, "C::LiftChildren"),
+            CullStrategy::LiftAtMost(n) => write!(f, "C::LiftAtMost({n})"),
+        }?;
+        write!(f, ", show_in_errors: {}, ", matcher.show_in_errors)?;
+        write!(f, "memoize: {}, ", matcher.memoize)?;
+        write!(f, "recover: {:?}, ", matcher.recover)?;
+        write!(f, "matcher_type: ")?;
+        write_matcher_type(f, &matcher.matcher_type)?;
+        writeln!(f, " }},")?;
+    }
+    writeln!(f, "    ];")?;
+    writeln!(f)?;
+    writeln!(
+        f,
+        "    static LEXER: ::std::sync::OnceLock<::flux_bnf::lexer::Lexer> = \
+         ::std::sync::OnceLock::new();"
+    )?;
+    writeln!(
+        f,
+        "    LEXER.get_or_init(|| ::flux_bnf::codegen::load({root}, {retain_empty}, NAMES, MATCHERS))"
+    )?;
+    writeln!(f, "}}")
+}
+
+fn write_matcher_type(f: &mut impl Write, matcher_type: &MatcherType) -> fmt::Result {
+    match matcher_type {
+        MatcherType::String(s, case_sensitive) => {
+            write!(
+                f,
+                "T::String({:?}, {case_sensitive})",
+                s.iter().collect::<String>()
+            )
+        }
+        MatcherType::CharClass(class) => {
+            write!(f, "T::CharClass(&[")?;
+            for (i, range) in class.ranges().into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "({:?}, {:?})", range.start(), range.end())?;
+            }
+            write!(f, "], {})", class.is_inverted())
+        }
+        MatcherType::List(children) => write!(f, "T::List(&{children:?})"),
+        MatcherType::Choice(children, _) => write!(f, "T::Choice(&{children:?})"),
+        MatcherType::Repeating(child, range, _) => {
+            write!(f, "T::Repeating({child}, {}, ", range.start())?;
+            match *range.end() {
+                usize::MAX => write!(f, "usize::MAX)"),
+                max => write!(f, "{max})"),
+            }
+        }
+        MatcherType::Inverted(child) => write!(f, "T::Inverted({child})"),
+        MatcherType::Lookahead(child) => write!(f, "T::Lookahead({child})"),
+        MatcherType::Labeled(label, child) => write!(f, "T::Labeled({label:?}, {child})"),
+        MatcherType::Precedence(table) => {
+            write!(
+                f,
+                "T::Precedence({}, {:?}, &[",
+                table.operand, table.separator
+            )?;
+            for (i, operator) in table.operators.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                let fixity = match operator.fixity {
+                    Fixity::Infix(Associativity::Left) => "F::Infix(A::Left)",
+                    Fixity::Infix(Associativity::Right) => "F::Infix(A::Right)",
+                    Fixity::Infix(Associativity::None) => "F::Infix(A::None)",
+                    Fixity::Prefix => "F::Prefix",
+                    Fixity::Postfix => "F::Postfix",
+                };
+                write!(
+                    f,
+                    "({}, {}, {fixity})",
+                    operator.matcher, operator.precedence
+                )?;
+            }
+            write!(f, "])")
+        }
+        MatcherType::Wrapper(child) => write!(f, "T::Wrapper({child})"),
+        MatcherType::Cut => write!(f, "T::Cut"),
+        MatcherType::Eof => write!(f, "T::Eof"),
+        MatcherType::Newline => write!(f, "T::Newline"),
+        // Parsing fails if any rule is left undefined
+        MatcherType::Placeholder => panic!("grammar should have every rule defined"),
+    }
+}
@@ -4,17 +4,18 @@ use std::{
 };
 
 use crate::matchers::MatcherName;
+use crate::position::{self, LineIndex};
 
 pub type Result<T> = std::result::Result<T, FluxError>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ErrorMessage {
     Constant(&'static str),
     Dynamic(String),
 }
 
 impl ErrorMessage {
-    fn get_message(&self) -> &str {
+    pub(crate) fn get_message(&self) -> &str {
         match self {
             Self::Constant(s) => s,
             Self::Dynamic(s) => s,
@@ -22,20 +23,21 @@ impl ErrorMessage {
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct FluxError {
     pub description: ErrorMessage,
     pub location: usize,
     pub depth: usize,
     pub matcher_name: MatcherName,
-    pub src_text: Option<Arc<[char]>>,
+    pub src_text: Option<Arc<str>>,
+    expected: Vec<String>,
 }
 
 impl FluxError {
     pub fn new(
         description: &'static str,
         location: usize,
-        src_text: Option<Arc<[char]>>,
+        src_text: Option<Arc<str>>,
     ) -> FluxError {
         FluxError {
             description: ErrorMessage::Constant(description),
@@ -43,6 +45,7 @@ impl FluxError {
             depth: 0,
             matcher_name: Arc::new(None),
             src_text,
+            expected: Vec::new(),
         }
     }
 
@@ -51,7 +54,7 @@ impl FluxError {
         location: usize,
         depth: usize,
         matcher_name: MatcherName,
-        src_text: Option<Arc<[char]>>,
+        src_text: Option<Arc<str>>,
     ) -> FluxError {
         FluxError {
             description: ErrorMessage::Constant(description),
@@ -59,24 +62,56 @@ impl FluxError {
             depth,
             matcher_name,
             src_text,
+            expected: Vec::new(),
         }
     }
 
-    pub fn new_dyn(
-        description: String,
-        location: usize,
-        src_text: Option<Arc<[char]>>,
-    ) -> FluxError {
+    pub fn new_dyn(description: String, location: usize, src_text: Option<Arc<str>>) -> FluxError {
         FluxError {
             description: ErrorMessage::Dynamic(description),
             location,
             depth: 0,
             matcher_name: Arc::new(None),
             src_text,
+            expected: Vec::new(),
         }
     }
 }
 
+impl FluxError {
+    /// Get every rule and literal that could have matched at the error, in the order they
+    /// were tried. Rules are left out when something more specific in them is listed.
+    pub fn expected(&self) -> &[String] {
+        &self.expected
+    }
+
+    pub(crate) fn with_expected(mut self, expected: Vec<String>) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    /// Describe what was expected at the error, if anything
+    fn expected_message(&self) -> Option<String> {
+        match (self.expected.as_slice(), &*self.matcher_name) {
+            ([], None) => None,
+            ([], Some(name)) | ([name], _) => Some(format!("expected `{name}`")),
+            (expected, _) => {
+                let expected: Vec<_> = expected.iter().map(|e| format!("`{e}`")).collect();
+                Some(format!("expected one of {}", expected.join(", ")))
+            }
+        }
+    }
+
+    /// Point the error into `source`, taking its location as a char offset, for the errors
+    /// of the grammar parsers, which work on chars
+    pub(crate) fn in_chars(mut self, source: &[char]) -> Self {
+        let (source, location) = position::from_chars(source, self.location);
+        self.location = location;
+        self.src_text = Some(source);
+        self
+    }
+}
+
 impl Ord for FluxError {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         match (&*self.matcher_name, &*other.matcher_name) {
@@ -97,19 +132,7 @@ impl Ord for FluxError {
 
 impl PartialOrd for FluxError {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (&*self.matcher_name, &*other.matcher_name) {
-            (Some(_), None) => Some(std::cmp::Ordering::Greater),
-            (None, Some(_)) => Some(std::cmp::Ordering::Less),
-            _ => {
-                if self.location > other.location {
-                    Some(std::cmp::Ordering::Greater)
-                } else if other.location > self.location || other.depth < self.depth {
-                    Some(std::cmp::Ordering::Less)
-                } else {
-                    Some(std::cmp::Ordering::Greater)
-                }
-            }
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -121,35 +144,22 @@ impl Debug for FluxError {
             .field("description", &self.description.get_message())
             .field("location", &self.location)
             .field("match_ref", &self.matcher_name)
+            .field("expected", &self.expected)
             .finish()
     }
 }
 
 impl Display for FluxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let src_highlight = match self.src_text.clone() {
+        let src_highlight = match &self.src_text {
             Some(source) => {
-                let (line_start, mut line_num) = source
-                    .iter()
-                    .take(self.location)
-                    .enumerate()
-                    .filter(|(_, c)| **c == '\n')
-                    .map(|(i, _)| (i, 1))
-                    .reduce(|(a, b), (x, y)| (a.max(x), b + y))
-                    .unwrap_or((0, 0));
-                let col = self.location - line_start;
-                line_num += 1;
-
-                let mut output = format!("at line {line_num} col {col}");
+                let index = LineIndex::new(source);
+                let line_col = index.line_col(self.location);
+                let col = line_col.column;
+
+                let mut output = format!("at line {} col {col}", line_col.line);
                 output.push_str("\n\n");
-                output.push_str(
-                    source
-                        .iter()
-                        .collect::<String>()
-                        .lines()
-                        .nth(line_num - 1)
-                        .unwrap_or_default(),
-                );
+                output.push_str(source.lines().nth(line_col.line - 1).unwrap_or_default());
                 output.push('\n');
 
                 let num_spaces = ((col as i32).max(1) - 6).max(0) as usize;
@@ -172,10 +182,9 @@ impl Display for FluxError {
                 write!(f, "FluxError ")?;
             }
 
-            let expected = match &*self.matcher_name {
-                Some(m) => format!("expected `{}`", m),
-                None => "unexpected token".into(),
-            };
+            let expected = self
+                .expected_message()
+                .unwrap_or_else(|| "unexpected token".into());
 
             write!(f, "{} {}", expected, src_highlight)
         } else {
@@ -185,8 +194,8 @@ impl Display for FluxError {
 
             writeln!(f, "at position {}", self.location)?;
 
-            match &*self.matcher_name {
-                Some(name) => writeln!(f, "expected `{}`", name),
+            match self.expected_message() {
+                Some(expected) => writeln!(f, "{expected}"),
                 None => writeln!(f, "unexpected token error"),
             }?;
 
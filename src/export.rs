@@ -0,0 +1,624 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use crate::analysis;
+use crate::char_class::CharClass;
+use crate::matchers::{Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity, OperatorTable};
+
+/// A grammar exported to another format by `Lexer::to_ebnf` or `Lexer::to_antlr`
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub text: String,
+    /// Everything that had no equivalent in the format, and was left as a comment or
+    /// approximated instead
+    pub untranslated: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ebnf,
+    Antlr,
+}
+
+/// How tightly an expression binds, deciding where parentheses are needed
+const CHOICE: u8 = 1;
+const LIST: u8 = 2;
+const PREFIX: u8 = 3;
+const POSTFIX: u8 = 4;
+const ATOM: u8 = 5;
+
+/// Names ANTLR or the code it generates can't use for rules
+#[rustfmt::skip]
+const ANTLR_RESERVED: &[&str] = &[
+    "abstract", "assert", "boolean", "break", "byte", "case", "catch", "channels", "char",
+    "class", "const", "continue", "default", "do", "double", "else", "enum", "EOF",
+    "extends", "false", "final", "finally", "float", "for", "fragment", "goto", "grammar",
+    "if", "implements", "import", "instanceof", "int", "interface", "lexer", "locals",
+    "long", "mode", "native", "new", "null", "options", "package", "parser", "private",
+    "protected", "public", "return", "returns", "short", "static", "super", "switch",
+    "synchronized", "this", "throw", "throws", "tokens", "transient", "true", "try", "void",
+    "volatile", "while",
+];
+
+/// Write out W3C-style EBNF, with the root rule first
+pub(crate) fn to_ebnf(root: usize, matchers: &[Matcher]) -> Export {
+    let mut exporter = Exporter::new(Format::Ebnf, root, matchers);
+    let rules = std::iter::once(root).chain(exporter.rules().filter(|id| *id != root));
+    let mut text = String::new();
+    for id in rules.collect::<Vec<_>>() {
+        exporter.rule = id;
+        let (body, _) = exporter.body(id);
+        text += &format!("{} ::= {body}\n", exporter.names[&id]);
+    }
+    exporter.finish(text)
+}
+
+/// Write out an ANTLR4 grammar. Rules that only match text become lexer rules, and the rest
+/// become parser rules, with lexer rules made for any character classes they use.
+pub(crate) fn to_antlr(grammar_name: &str, root: usize, matchers: &[Matcher]) -> Export {
+    let mut exporter = Exporter::new(Format::Antlr, root, matchers);
+    let rules: Vec<_> = exporter.rules().collect();
+    let (lexer_rules, parser_rules): (Vec<_>, Vec<_>) = rules
+        .into_iter()
+        .partition(|id| exporter.lexical.contains(id));
+    let tokens = exporter.tokens(&parser_rules);
+
+    let mut text = format!("grammar {grammar_name};\n\n");
+    let parser_rules =
+        std::iter::once(root).chain(parser_rules.into_iter().filter(|id| *id != root));
+    for id in parser_rules.collect::<Vec<_>>() {
+        exporter.rule = id;
+        let mut body = exporter.body(id);
+        if id == root && !body.0.ends_with("EOF") {
+            body = exporter.wrap(body, LIST);
+            body.0 += " EOF";
+        }
+        text += &format!("{} : {} ;\n", exporter.names[&id], body.0);
+    }
+    text.push('\n');
+    exporter.in_lexer = true;
+    for id in lexer_rules {
+        exporter.rule = id;
+        let body = exporter.body(id);
+        let fragment = if tokens.contains(&id) {
+            ""
+        } else {
+            "fragment "
+        };
+        text += &format!("{fragment}{} : {} ;\n", exporter.names[&id], body.0);
+    }
+    for (name, definition) in std::mem::take(&mut exporter.tokens) {
+        text += &format!("{name} : {definition} ;\n");
+    }
+    exporter.finish(text)
+}
+
+struct Exporter<'m> {
+    format: Format,
+    root: usize,
+    matchers: &'m [Matcher],
+    names: HashMap<usize, String>,
+    used_names: HashSet<String>,
+    /// Rules written as ANTLR lexer rules
+    lexical: HashSet<usize>,
+    /// Lexer rules made for the parts of parser rules ANTLR only allows in lexer rules, with
+    /// their definitions
+    tokens: Vec<(String, String)>,
+    untranslated: Vec<String>,
+    /// The rule being written
+    rule: usize,
+    in_lexer: bool,
+}
+
+impl<'m> Exporter<'m> {
+    fn new(format: Format, root: usize, matchers: &'m [Matcher]) -> Self {
+        let mut exporter = Exporter {
+            format,
+            root,
+            matchers,
+            names: HashMap::new(),
+            used_names: HashSet::new(),
+            lexical: HashSet::new(),
+            tokens: Vec::new(),
+            untranslated: Vec::new(),
+            rule: root,
+            in_lexer: false,
+        };
+        if format == Format::Antlr {
+            exporter.find_lexical();
+        }
+        for id in exporter.rules().collect::<Vec<_>>() {
+            let name = matchers[id].name.as_deref().unwrap_or("rule");
+            let name = match format {
+                Format::Ebnf => name.to_string(),
+                Format::Antlr => antlr_name(name, exporter.lexical.contains(&id)),
+            };
+            let name = exporter.unique_name(name);
+            exporter.names.insert(id, name);
+        }
+        exporter
+    }
+
+    fn rules(&self) -> impl Iterator<Item = usize> + 'm {
+        self.matchers
+            .iter()
+            .filter(|m| m.name.is_some())
+            .map(|m| m.id)
+    }
+
+    fn unique_name(&mut self, mut name: String) -> String {
+        while self.used_names.contains(&name)
+            || (self.format == Format::Antlr && ANTLR_RESERVED.contains(&name.as_str()))
+        {
+            name.push('_');
+        }
+        self.used_names.insert(name.clone());
+        name
+    }
+
+    fn finish(self, text: String) -> Export {
+        Export {
+            text,
+            untranslated: self.untranslated,
+        }
+    }
+
+    /// Find the rules which only match text, and so can be ANTLR lexer rules. Lexer rules
+    /// can't be recursive or match nothing, and the root rule has to be a parser rule.
+    fn find_lexical(&mut self) {
+        let nullable = analysis::nullable(self.matchers);
+        let mut found = HashMap::new();
+        for id in self.rules().collect::<Vec<_>>() {
+            self.is_lexical_rule(id, &nullable, &mut found);
+        }
+        self.lexical = found
+            .into_iter()
+            .filter_map(|(id, lexical)| (lexical == Some(true)).then_some(id))
+            .collect();
+    }
+
+    /// Check if a rule can be a lexer rule, where `found` holds `None` for the rules still
+    /// being checked further up, so recursive rules are found to be parser rules
+    fn is_lexical_rule(
+        &self,
+        id: usize,
+        nullable: &[bool],
+        found: &mut HashMap<usize, Option<bool>>,
+    ) -> bool {
+        if let Some(lexical) = found.get(&id) {
+            return lexical.unwrap_or(false);
+        }
+        found.insert(id, None);
+        let lexical = id != self.root && !nullable[id] && self.is_lexical(id, nullable, found);
+        found.insert(id, Some(lexical));
+        lexical
+    }
+
+    fn is_lexical(
+        &self,
+        id: usize,
+        nullable: &[bool],
+        found: &mut HashMap<usize, Option<bool>>,
+    ) -> bool {
+        let matcher = &self.matchers[id];
+        let allowed = matches!(
+            matcher.matcher_type,
+            MatcherType::String(_, _)
+                | MatcherType::CharClass(_)
+                | MatcherType::List(_)
+                | MatcherType::Choice(_, _)
+                | MatcherType::Repeating(_, _, _)
+                | MatcherType::Wrapper(_)
+                | MatcherType::Newline
+        );
+        allowed
+            && matcher.child_ids().into_iter().all(|child| {
+                if self.matchers[child].name.is_some() {
+                    self.is_lexical_rule(child, nullable, found)
+                } else {
+                    self.is_lexical(child, nullable, found)
+                }
+            })
+    }
+
+    /// Find the lexer rules used by parser rules, which have to be tokens rather than
+    /// fragments
+    fn tokens(&self, parser_rules: &[usize]) -> HashSet<usize> {
+        let mut tokens = HashSet::new();
+        let mut stack = parser_rules.to_vec();
+        let mut visited = HashSet::new();
+        while let Some(id) = stack.pop() {
+            for child in self.matchers[id].child_ids() {
+                if self.lexical.contains(&child) {
+                    tokens.insert(child);
+                } else if self.matchers[child].name.is_none() && visited.insert(child) {
+                    stack.push(child);
+                }
+            }
+        }
+        tokens
+    }
+
+    fn report(&mut self, message: String) {
+        if !self.untranslated.contains(&message) {
+            self.untranslated.push(message);
+        }
+    }
+
+    fn rule_name(&self) -> &str {
+        self.matchers[self.rule].name.as_deref().unwrap_or("")
+    }
+
+    /// Write the definition of a rule
+    fn body(&mut self, id: usize) -> (String, u8) {
+        match &self.matchers[id].matcher_type {
+            MatcherType::Precedence(table) => self.precedence(table),
+            _ => self.matcher(id),
+        }
+    }
+
+    /// Write a reference to a rule, or an unnamed matcher inline, in parentheses if it
+    /// binds looser than `min_level`
+    fn expr(&mut self, id: usize, min_level: u8) -> String {
+        let part = self.part(id);
+        self.wrap(part, min_level).0
+    }
+
+    fn part(&mut self, id: usize) -> (String, u8) {
+        match self.names.get(&id) {
+            Some(name) => (name.clone(), ATOM),
+            None => self.matcher(id),
+        }
+    }
+
+    fn wrap(&self, (text, level): (String, u8), min_level: u8) -> (String, u8) {
+        if level < min_level {
+            (format!("({text})"), ATOM)
+        } else {
+            (text, level)
+        }
+    }
+
+    fn matcher(&mut self, id: usize) -> (String, u8) {
+        match &self.matchers[id].matcher_type {
+            MatcherType::String(s, case_sensitive) => self.string(s, *case_sensitive),
+            MatcherType::CharClass(class) => self.char_class(class),
+            MatcherType::List(children) => (self.join(children, " ", LIST + 1), LIST),
+            MatcherType::Choice(children, _) => (self.join(children, " | ", CHOICE + 1), CHOICE),
+            MatcherType::Repeating(child, range, _) => self.repeating(*child, range),
+            MatcherType::Inverted(child) => self.untranslated(*child, "!", "Negative lookahead"),
+            MatcherType::Lookahead(child) => self.untranslated(*child, "&", "Lookahead"),
+            MatcherType::Labeled(label, child) => match self.format {
+                Format::Antlr if !self.in_lexer => {
+                    (format!("{label}={}", self.expr(*child, ATOM)), PREFIX)
+                }
+                _ => self.part(*child),
+            },
+            MatcherType::Precedence(table) => self.precedence(table),
+            MatcherType::Wrapper(child) => self.part(*child),
+            MatcherType::Cut => {
+                self.report(format!("Cut in rule {} was left out", self.rule_name()));
+                (String::new(), ATOM)
+            }
+            MatcherType::Eof if self.format == Format::Antlr && !self.in_lexer => {
+                ("EOF".into(), ATOM)
+            }
+            MatcherType::Eof => {
+                let rule = self.rule_name().to_string();
+                self.report(format!("End of input in rule {rule} was left as a comment"));
+                ("/* <eof> */".into(), ATOM)
+            }
+            MatcherType::Newline => match self.format {
+                Format::Ebnf => ("(#xD #xA? | #xA)".into(), ATOM),
+                Format::Antlr => ("('\\r' '\\n'? | '\\n')".into(), ATOM),
+            },
+            MatcherType::Placeholder => (String::new(), ATOM),
+        }
+    }
+
+    fn join(&mut self, children: &[usize], separator: &str, min_level: u8) -> String {
+        let parts: Vec<_> = children.iter().map(|c| self.expr(*c, min_level)).collect();
+        match separator {
+            // Leave out the gaps left by cuts and empty strings
+            " " => sequence(&parts.iter().map(String::as_str).collect::<Vec<_>>()),
+            _ => parts.join(separator),
+        }
+    }
+
+    /// Write a matcher with no equivalent as a comment
+    fn untranslated(&mut self, child: usize, operator: &str, description: &str) -> (String, u8) {
+        let rule = self.rule_name().to_string();
+        self.report(format!(
+            "{description} in rule {rule} was left as a comment"
+        ));
+        let child = self.expr(child, POSTFIX).replace("*/", "* /");
+        (format!("/* {operator}{child} */"), ATOM)
+    }
+
+    fn repeating(&mut self, child: usize, range: &RangeInclusive<usize>) -> (String, u8) {
+        let child = self.expr(child, ATOM);
+        let (min, max) = (*range.start(), *range.end());
+        match (min, max) {
+            (0, usize::MAX) => return (child + "*", POSTFIX),
+            (1, usize::MAX) => return (child + "+", POSTFIX),
+            (0, 1) => return (child + "?", POSTFIX),
+            _ => (),
+        }
+        // Neither format has bounded repetition, so it's written out in full
+        let mut parts = vec![child.clone(); min];
+        if max == usize::MAX {
+            parts.push(child + "*");
+        } else if max > min {
+            let mut optional = format!("{child}?");
+            for _ in min + 1..max {
+                optional = format!("({child} {optional})?");
+            }
+            parts.push(optional);
+        }
+        match parts.len() {
+            1 => (parts.remove(0), POSTFIX),
+            _ => (parts.join(" "), LIST),
+        }
+    }
+
+    fn string(&mut self, s: &[char], case_sensitive: bool) -> (String, u8) {
+        if s.is_empty() {
+            return match self.format {
+                Format::Ebnf => ("\"\"".into(), ATOM),
+                Format::Antlr => (String::new(), ATOM),
+            };
+        }
+        if self.format == Format::Antlr && case_sensitive {
+            return (antlr_literal(s), ATOM);
+        }
+        // Case-insensitive letters become classes of both cases, since matching ignores
+        // case for ASCII only
+        let mut parts = Vec::new();
+        let mut run: Vec<char> = Vec::new();
+        for c in s {
+            let caseless = !case_sensitive && c.is_ascii_alphabetic();
+            let special = self.format == Format::Ebnf && c.is_control();
+            let both_quotes = self.format == Format::Ebnf
+                && matches!(c, '"' | '\'')
+                && run.iter().any(|r| matches!(r, '"' | '\'') && r != c);
+            if caseless || special || both_quotes {
+                parts.extend(self.literal(&std::mem::take(&mut run)));
+            }
+            if caseless {
+                let (upper, lower) = (c.to_ascii_uppercase(), c.to_ascii_lowercase());
+                parts.push(format!("[{upper}{lower}]"));
+            } else if special {
+                parts.push(format!("#x{:X}", *c as u32));
+            } else {
+                run.push(*c);
+            }
+        }
+        parts.extend(self.literal(&run));
+        let text = parts.join(" ");
+        match self.format {
+            Format::Antlr if !self.in_lexer => (self.token(text), ATOM),
+            _ if parts.len() > 1 => (text, LIST),
+            _ => (text, ATOM),
+        }
+    }
+
+    fn literal(&self, s: &[char]) -> Option<String> {
+        if s.is_empty() {
+            return None;
+        }
+        let text: String = s.iter().collect();
+        Some(match self.format {
+            Format::Ebnf if s.contains(&'"') => format!("'{text}'"),
+            Format::Ebnf => format!("\"{text}\""),
+            Format::Antlr => antlr_literal(s),
+        })
+    }
+
+    fn char_class(&mut self, class: &CharClass) -> (String, u8) {
+        let ranges = class.ranges();
+        let text = match self.format {
+            Format::Ebnf if ranges.is_empty() && class.is_inverted() => "[#x0-#x10FFFF]".into(),
+            Format::Ebnf => {
+                let negation = if class.is_inverted() { "^" } else { "" };
+                let ranges = ranges.iter().map(|r| class_range(r, ebnf_class_char));
+                format!("[{negation}{}]", ranges.collect::<String>())
+            }
+            Format::Antlr if ranges.is_empty() && class.is_inverted() => ".".into(),
+            Format::Antlr => {
+                let negation = if class.is_inverted() { "~" } else { "" };
+                let (properties, ranges) =
+                    class.unicode_properties().unwrap_or((Vec::new(), ranges));
+                let ranges = ranges.iter().map(|r| class_range(r, antlr_class_char));
+                let properties = properties.iter().map(|p| format!("\\p{{{p}}}"));
+                let set: String = ranges.chain(properties).collect();
+                format!("{negation}[{set}]")
+            }
+        };
+        match self.format {
+            Format::Antlr if !self.in_lexer => (self.token(text), ATOM),
+            _ => (text, ATOM),
+        }
+    }
+
+    /// Get a lexer rule for something only allowed in lexer rules, making one if needed
+    fn token(&mut self, definition: String) -> String {
+        if let Some((name, _)) = self.tokens.iter().find(|(_, d)| *d == definition) {
+            return name.clone();
+        }
+        let name = self.unique_name(format!("TOKEN_{}", self.tokens.len() + 1));
+        self.tokens.push((name.clone(), definition));
+        name
+    }
+
+    fn precedence(&mut self, table: &OperatorTable) -> (String, u8) {
+        let operand = self.expr(table.operand, LIST + 1);
+        let separator = match table.separator {
+            Some(separator) => self.expr(separator, ATOM) + "?",
+            None => String::new(),
+        };
+        let mut rows: Vec<(Fixity, Vec<String>)> = Vec::new();
+        let mut precedence = 0;
+        for operator in &table.operators {
+            let text = self.expr(operator.matcher, LIST + 1);
+            match rows.last_mut() {
+                Some((_, row)) if operator.precedence == precedence => row.push(text),
+                _ => rows.push((operator.fixity, vec![text])),
+            }
+            precedence = operator.precedence;
+        }
+        let rule = self.rule_name().to_string();
+        match self.format {
+            Format::Ebnf => {
+                self.report(format!(
+                    "Precedence and associativity of operators in rule {rule} were left out"
+                ));
+                ebnf_operations(&operand, &separator, &rows)
+            }
+            Format::Antlr => {
+                if rows
+                    .iter()
+                    .any(|(f, _)| *f == Fixity::Infix(Associativity::None))
+                {
+                    self.report(format!(
+                        "Non-associative operators in rule {rule} were made left-associative"
+                    ));
+                }
+                let name = &self.names[&self.rule];
+                antlr_operations(name, &operand, &separator, &rows)
+            }
+        }
+    }
+}
+
+/// Write an operator table as a flat sequence of operands and operators, matching the same
+/// text without grouping the operations
+fn ebnf_operations(operand: &str, separator: &str, rows: &[(Fixity, Vec<String>)]) -> (String, u8) {
+    let operators = |fixity: fn(Fixity) -> bool| {
+        let found: Vec<_> = rows
+            .iter()
+            .filter(|(f, _)| fixity(*f))
+            .flat_map(|(_, row)| row.iter().cloned())
+            .collect();
+        match found.len() {
+            0 => None,
+            1 => Some(found[0].clone()),
+            _ => Some(format!("({})", found.join(" | "))),
+        }
+    };
+    let prefix = operators(|f| f == Fixity::Prefix);
+    let postfix = operators(|f| f == Fixity::Postfix);
+    let infix = operators(|f| matches!(f, Fixity::Infix(_)));
+    let unit = match prefix {
+        Some(prefix) => sequence(&[&format!("({})*", sequence(&[&prefix, separator])), operand]),
+        None => operand.to_string(),
+    };
+    let infix = infix.map(|infix| sequence(&[&infix, separator, &unit]));
+    let tails: Vec<_> = postfix.into_iter().chain(infix).collect();
+    let text = match tails.len() {
+        0 => unit.clone(),
+        1 => sequence(&[&unit, &format!("({})*", sequence(&[separator, &tails[0]]))]),
+        _ => {
+            let tails = format!("({})", tails.join(" | "));
+            sequence(&[&unit, &format!("({})*", sequence(&[separator, &tails]))])
+        }
+    };
+    (text, LIST)
+}
+
+/// Write an operator table as the left-recursive alternatives ANTLR turns into precedence
+/// climbing, from the tightest binding operators to the loosest
+fn antlr_operations(
+    name: &str,
+    operand: &str,
+    separator: &str,
+    rows: &[(Fixity, Vec<String>)],
+) -> (String, u8) {
+    let mut alternatives = Vec::new();
+    for (fixity, row) in rows.iter().rev() {
+        let operators = match row.len() {
+            1 => row[0].clone(),
+            _ => format!("({})", row.join(" | ")),
+        };
+        alternatives.push(match fixity {
+            Fixity::Prefix => sequence(&[&operators, separator, name]),
+            Fixity::Postfix => sequence(&[name, separator, &operators]),
+            Fixity::Infix(Associativity::Right) => {
+                let operation = sequence(&[name, separator, &operators, separator, name]);
+                format!("<assoc=right> {operation}")
+            }
+            Fixity::Infix(_) => sequence(&[name, separator, &operators, separator, name]),
+        });
+    }
+    alternatives.push(operand.to_string());
+    (alternatives.join("\n    | "), CHOICE)
+}
+
+fn sequence(parts: &[&str]) -> String {
+    let parts: Vec<_> = parts.iter().filter(|p| !p.is_empty()).copied().collect();
+    parts.join(" ")
+}
+
+fn antlr_name(name: &str, lexical: bool) -> String {
+    let name = name.replace(['.', '-'], "_");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() => {
+            let first = match lexical {
+                true => first.to_ascii_uppercase(),
+                false => first.to_ascii_lowercase(),
+            };
+            std::iter::once(first).chain(chars).collect()
+        }
+        _ if lexical => format!("R{name}"),
+        _ => format!("r{name}"),
+    }
+}
+
+fn antlr_literal(s: &[char]) -> String {
+    let mut out = String::from("'");
+    for c in s {
+        match c {
+            '\'' => out.push_str("\\'"),
+            c => out.push_str(&antlr_char(*c)),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+fn antlr_char(c: char) -> String {
+    match c {
+        '\\' => "\\\\".into(),
+        '\n' => "\\n".into(),
+        '\r' => "\\r".into(),
+        '\t' => "\\t".into(),
+        c if c.is_control() => format!("\\u{:04X}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+fn antlr_class_char(c: char) -> String {
+    match c {
+        ']' | '-' => format!("\\{c}"),
+        c => antlr_char(c),
+    }
+}
+
+fn ebnf_class_char(c: char) -> String {
+    match c {
+        c if c.is_ascii_graphic() && !matches!(c, ']' | '-' | '^' | '[' | '#' | '\\') => {
+            c.to_string()
+        }
+        c => format!("#x{:X}", c as u32),
+    }
+}
+
+fn class_range(range: &RangeInclusive<char>, write_char: fn(char) -> String) -> String {
+    let (start, end) = (*range.start(), *range.end());
+    match end as u32 - start as u32 {
+        0 => write_char(start),
+        1 => write_char(start) + &write_char(end),
+        _ => format!("{}-{}", write_char(start), write_char(end)),
+    }
+}
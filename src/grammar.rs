@@ -0,0 +1,413 @@
+//! Build grammars in code instead of parsing them from BNF. Templates are just Rust functions
+//! which take and return `Expr`s.
+
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::sync::Arc;
+
+use crate::analysis::Diagnostic;
+use crate::bnf::{flatten_wrappers, without_errors};
+use crate::char_class::CharClass;
+use crate::error::{FluxError, Result};
+use crate::lexer::{CullStrategy, Lexer};
+use crate::matchers::{Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity, Operator, OperatorTable};
+
+/// A set of named rules, which is turned into a `Lexer` with `build`. Like a BNF grammar, it
+/// needs a rule called `root`, and rules can refer to rules which are added after them.
+#[derive(Debug, Clone, Default)]
+pub struct Grammar {
+    rules: Vec<Rule>,
+    unnamed_rule: Option<CullStrategy>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    expr: Expr,
+    cull_strategy: CullStrategy,
+    show_in_errors: bool,
+    memoize: bool,
+    recover: Option<Expr>,
+}
+
+/// An expression matching some input, made with the functions in this module. Strings
+/// convert into expressions matching them exactly.
+#[derive(Debug, Clone)]
+pub struct Expr(Node);
+
+#[derive(Debug, Clone)]
+enum Node {
+    String(Vec<char>, bool),
+    CharClass(CharClass),
+    Rule(String),
+    Sequence(Vec<Expr>),
+    Choice(Vec<Expr>),
+    Repeat(Box<Expr>, RangeInclusive<usize>),
+    Not(Box<Expr>),
+    Lookahead(Box<Expr>),
+    Label(Arc<str>, Box<Expr>),
+    Precedence(Box<Precedence>),
+    Cut,
+    Eof,
+    Newline,
+}
+
+impl Grammar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule, which other rules can refer to with `rule(name)`
+    pub fn rule(mut self, name: impl Into<String>, expr: impl Into<Expr>) -> Self {
+        self.rules.push(Rule {
+            name: name.into(),
+            expr: expr.into(),
+            cull_strategy: CullStrategy::None,
+            show_in_errors: true,
+            memoize: false,
+            recover: None,
+        });
+        self
+    }
+
+    /// Set the cull strategy of the last rule added, like an `@annotation` on a BNF rule
+    pub fn cull(mut self, cull_strategy: CullStrategy) -> Self {
+        self.last_rule().cull_strategy = cull_strategy;
+        self
+    }
+
+    /// Leave the last rule added out of error messages, like `name!` in BNF
+    pub fn hidden(mut self) -> Self {
+        self.last_rule().show_in_errors = false;
+        self
+    }
+
+    /// Memoize the last rule added, like `@memo` in BNF
+    pub fn memo(mut self) -> Self {
+        self.last_rule().memoize = true;
+        self
+    }
+
+    /// Make the last rule added skip ahead to where `sync` matches when it fails while
+    /// tokenizing with error recovery, like `@recover(sync)` in BNF
+    pub fn recover(mut self, sync: impl Into<Expr>) -> Self {
+        self.last_rule().recover = Some(sync.into());
+        self
+    }
+
+    /// Set the cull strategy of every unnamed matcher, like `@unnamed` in BNF
+    pub fn unnamed(mut self, cull_strategy: CullStrategy) -> Self {
+        self.unnamed_rule = Some(cull_strategy);
+        self
+    }
+
+    fn last_rule(&mut self) -> &mut Rule {
+        self.rules
+            .last_mut()
+            .expect("a rule must be added before it can be configured")
+    }
+
+    /// Build the grammar, failing on the same errors as `bnf::parse`
+    pub fn build(&self) -> Result<Lexer> {
+        without_errors(self.compile()?)
+    }
+
+    /// Check the grammar for likely mistakes, returning every warning and error found, like
+    /// `bnf::analyze`. Fails only if the grammar can't be built at all.
+    pub fn analyze(&self) -> Result<Vec<Diagnostic>> {
+        self.compile().map(|(_, diagnostics)| diagnostics)
+    }
+
+    fn compile(&self) -> Result<(Lexer, Vec<Diagnostic>)> {
+        let mut compiler = Compiler {
+            matchers: Vec::new(),
+            ids: HashMap::new(),
+        };
+        for rule in &self.rules {
+            if compiler.ids.contains_key(&rule.name) {
+                return Err(error(format!(
+                    "Rule {} is defined more than once",
+                    rule.name
+                )));
+            }
+            let id = compiler.add_matcher(MatcherType::Placeholder);
+            compiler.ids.insert(rule.name.clone(), id);
+        }
+        for rule in &self.rules {
+            let id = compiler.ids[&rule.name];
+            let matcher_type = match &rule.expr.0 {
+                Node::Precedence(table) => compiler.compile_table(table)?,
+                _ => compiler.compile(&rule.expr)?,
+            };
+            let recover = match &rule.recover {
+                Some(sync) => Some(compiler.add(sync)?),
+                None => None,
+            };
+            let matcher = &mut compiler.matchers[id];
+            matcher.name = Some(rule.name.clone()).into();
+            matcher.matcher_type = matcher_type;
+            matcher.cull_strategy = rule.cull_strategy;
+            matcher.show_in_errors = rule.show_in_errors;
+            matcher.memoize = rule.memoize;
+            matcher.recover = recover;
+        }
+        if let Some(unnamed_rule) = self.unnamed_rule {
+            for matcher in compiler.matchers.iter_mut().filter(|m| m.name.is_none()) {
+                matcher.cull_strategy = unnamed_rule;
+            }
+        }
+        flatten_wrappers(&mut compiler.matchers);
+        let root = *compiler
+            .ids
+            .get("root")
+            .ok_or_else(|| error("No root matcher specified".into()))?;
+        let lexer = Lexer::new(root, compiler.ids, compiler.matchers);
+        let diagnostics = lexer.validate();
+        Ok((lexer, diagnostics))
+    }
+}
+
+struct Compiler {
+    matchers: Vec<Matcher>,
+    ids: HashMap<String, usize>,
+}
+
+impl Compiler {
+    fn compile(&mut self, expr: &Expr) -> Result<MatcherType> {
+        Ok(match &expr.0 {
+            Node::String(s, case_sensitive) => MatcherType::String(s.clone(), *case_sensitive),
+            Node::CharClass(class) => MatcherType::CharClass(class.clone()),
+            Node::Rule(name) => match self.ids.get(name) {
+                Some(id) => MatcherType::Wrapper(*id),
+                None => return Err(error(format!("No rule with name {name}"))),
+            },
+            Node::Sequence(exprs) => MatcherType::List(self.add_all(exprs)?),
+            Node::Choice(exprs) => MatcherType::Choice(self.add_all(exprs)?, None),
+            Node::Repeat(expr, range) => {
+                if range.is_empty() {
+                    return Err(error(format!(
+                        "Invalid repetition range {}..={}",
+                        range.start(),
+                        range.end()
+                    )));
+                }
+                MatcherType::Repeating(self.add(expr)?, range.clone(), None)
+            }
+            Node::Not(expr) => MatcherType::Inverted(self.add(expr)?),
+            Node::Lookahead(expr) => MatcherType::Lookahead(self.add(expr)?),
+            Node::Label(label, expr) => MatcherType::Labeled(label.clone(), self.add(expr)?),
+            Node::Precedence(_) => {
+                return Err(error(
+                    "A precedence table has to be the whole body of a rule".into(),
+                ))
+            }
+            Node::Cut => MatcherType::Cut,
+            Node::Eof => MatcherType::Eof,
+            Node::Newline => MatcherType::Newline,
+        })
+    }
+
+    fn compile_table(&mut self, table: &Precedence) -> Result<MatcherType> {
+        let operand = self.add(&table.operand)?;
+        let separator = match &table.separator {
+            Some(separator) => Some(self.add(separator)?),
+            None => None,
+        };
+        let mut operators = Vec::new();
+        for (precedence, (fixity, level)) in table.levels.iter().enumerate() {
+            for operator in level {
+                operators.push(Operator {
+                    matcher: self.add(operator)?,
+                    precedence: precedence + 1,
+                    fixity: *fixity,
+                });
+            }
+        }
+        if operators.is_empty() {
+            return Err(error(
+                "A precedence table needs at least one operator".into(),
+            ));
+        }
+        let table = OperatorTable::new(operand, separator, operators);
+        Ok(MatcherType::Precedence(table.into()))
+    }
+
+    fn add(&mut self, expr: &Expr) -> Result<usize> {
+        let matcher_type = self.compile(expr)?;
+        Ok(self.add_matcher(matcher_type))
+    }
+
+    fn add_all(&mut self, exprs: &[Expr]) -> Result<Vec<usize>> {
+        exprs.iter().map(|expr| self.add(expr)).collect()
+    }
+
+    fn add_matcher(&mut self, matcher_type: MatcherType) -> usize {
+        let id = self.matchers.len();
+        self.matchers.push(Matcher {
+            name: None.into(),
+            id,
+            matcher_type,
+            cull_strategy: CullStrategy::None,
+            show_in_errors: false,
+            left_recursive: false,
+            memoize: false,
+            recover: None,
+            location: None,
+        });
+        id
+    }
+}
+
+fn error(message: String) -> FluxError {
+    FluxError::new_dyn(message, 0, None)
+}
+
+impl From<&str> for Expr {
+    fn from(s: &str) -> Self {
+        string(s)
+    }
+}
+
+impl From<Precedence> for Expr {
+    fn from(table: Precedence) -> Self {
+        Expr(Node::Precedence(table.into()))
+    }
+}
+
+/// Match a string exactly, like `"abc"`
+pub fn string(s: &str) -> Expr {
+    Expr(Node::String(s.chars().collect(), true))
+}
+
+/// Match a string ignoring ASCII case, like `i"abc"`
+pub fn istring(s: &str) -> Expr {
+    Expr(Node::String(s.chars().collect(), false))
+}
+
+/// Match one character from `low` to `high`, like `[a-z]`
+pub fn range(low: char, high: char) -> Expr {
+    class(CharClass::new([low..=high], false))
+}
+
+/// Match one character in a character class
+pub fn class(class: CharClass) -> Expr {
+    Expr(Node::CharClass(class))
+}
+
+/// Match the rule called `name`, which can be added to the grammar before or after this
+pub fn rule(name: impl Into<String>) -> Expr {
+    Expr(Node::Rule(name.into()))
+}
+
+/// Match each expression in turn, like `a b c`
+pub fn seq(exprs: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr(Node::Sequence(exprs.into_iter().collect()))
+}
+
+/// Match the first expression that matches, like `a | b | c`
+pub fn choice(exprs: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr(Node::Choice(exprs.into_iter().collect()))
+}
+
+/// Match an expression a number of times in `times`, like `a*` for `0..` or `a{2,3}` for
+/// `2..=3`
+pub fn repeat(expr: impl Into<Expr>, times: impl RangeBounds<usize>) -> Expr {
+    let min = match times.start_bound() {
+        Bound::Included(n) => *n,
+        Bound::Excluded(n) => n + 1,
+        Bound::Unbounded => 0,
+    };
+    let range = match times.end_bound() {
+        Bound::Included(n) => min..=*n,
+        Bound::Excluded(n) => min..=n.saturating_sub(1),
+        Bound::Unbounded => min..=usize::MAX,
+    };
+    Expr(Node::Repeat(Box::new(expr.into()), range))
+}
+
+/// Match an expression or nothing, like `a?`
+pub fn optional(expr: impl Into<Expr>) -> Expr {
+    repeat(expr, 0..=1)
+}
+
+/// Match nothing, only succeeding if the expression doesn't match, like `!a`
+pub fn not(expr: impl Into<Expr>) -> Expr {
+    Expr(Node::Not(Box::new(expr.into())))
+}
+
+/// Match nothing if the expression would match, like `&a`
+pub fn lookahead(expr: impl Into<Expr>) -> Expr {
+    Expr(Node::Lookahead(Box::new(expr.into())))
+}
+
+/// Label the token of an expression, like `label:a`
+pub fn label(label: &str, expr: impl Into<Expr>) -> Expr {
+    Expr(Node::Label(label.into(), Box::new(expr.into())))
+}
+
+/// Commit to the current alternative, like `~`
+pub fn cut() -> Expr {
+    Expr(Node::Cut)
+}
+
+/// Match the end of the input, like `<eof>`
+pub fn eof() -> Expr {
+    Expr(Node::Eof)
+}
+
+/// Match a line break, like `<nl>`
+pub fn newline() -> Expr {
+    Expr(Node::Newline)
+}
+
+/// Start an operator table, like `@precedence(operand)`. Each level of operators added binds
+/// tighter than the ones before it. Like in BNF, the table has to be the whole body of a rule.
+pub fn precedence(operand: impl Into<Expr>) -> Precedence {
+    Precedence {
+        operand: operand.into(),
+        separator: None,
+        levels: Vec::new(),
+    }
+}
+
+/// An operator table made with `precedence`, which converts into an `Expr`
+#[derive(Debug, Clone)]
+pub struct Precedence {
+    operand: Expr,
+    separator: Option<Expr>,
+    levels: Vec<(Fixity, Vec<Expr>)>,
+}
+
+impl Precedence {
+    /// Allow `separator` between operands and operators, like `@precedence(operand, sep)`
+    pub fn separator(mut self, separator: impl Into<Expr>) -> Self {
+        self.separator = Some(separator.into());
+        self
+    }
+
+    pub fn left(self, operators: impl IntoIterator<Item = Expr>) -> Self {
+        self.level(Fixity::Infix(Associativity::Left), operators)
+    }
+
+    pub fn right(self, operators: impl IntoIterator<Item = Expr>) -> Self {
+        self.level(Fixity::Infix(Associativity::Right), operators)
+    }
+
+    pub fn none(self, operators: impl IntoIterator<Item = Expr>) -> Self {
+        self.level(Fixity::Infix(Associativity::None), operators)
+    }
+
+    pub fn prefix(self, operators: impl IntoIterator<Item = Expr>) -> Self {
+        self.level(Fixity::Prefix, operators)
+    }
+
+    pub fn postfix(self, operators: impl IntoIterator<Item = Expr>) -> Self {
+        self.level(Fixity::Postfix, operators)
+    }
+
+    fn level(mut self, fixity: Fixity, operators: impl IntoIterator<Item = Expr>) -> Self {
+        self.levels.push((fixity, operators.into_iter().collect()));
+        self
+    }
+}
@@ -0,0 +1,294 @@
+//! Reparsing a text after edits, reusing the matches of every rule the edits didn't touch
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+
+use bumpalo::Bump;
+
+use crate::error::{FluxError, Result};
+use crate::lexer::Lexer;
+use crate::matchers::{MatcherName, SuccessMark, TokenOutput};
+use crate::tokens::Token;
+
+/// A token kept between parses. Its children are placed relative to where it starts, so a
+/// node is shared by every tree it's in, whatever the edits before it moved it by.
+#[derive(Clone)]
+pub(crate) struct Node {
+    matcher_name: MatcherName,
+    matcher_id: usize,
+    label: Option<Arc<str>>,
+    len: usize,
+    children: Vec<(usize, Arc<Node>)>,
+}
+
+impl Node {
+    /// Make the node of `token`, taking the nodes of tokens standing in for ones in `reused`
+    /// from it rather than copying them
+    pub(crate) fn new(token: &Token, reused: &[Arc<Node>]) -> Arc<Node> {
+        if let Some(shared) = token.shared {
+            let node = &reused[shared];
+            if node.label == token.label {
+                return node.clone();
+            }
+            // The token was labeled after it was reused
+            return Arc::new(Node {
+                label: token.label.clone(),
+                ..(**node).clone()
+            });
+        }
+        let start = token.range.start;
+        Arc::new(Node {
+            matcher_name: token.matcher_name.clone(),
+            matcher_id: token.matcher_id,
+            label: token.label.clone(),
+            len: token.range.len(),
+            children: token
+                .children
+                .iter()
+                .map(|t| (t.range.start - start, Node::new(t, reused)))
+                .collect(),
+        })
+    }
+
+    pub(crate) fn to_token<'a>(
+        &self,
+        source: &Arc<str>,
+        start: usize,
+        alloc: &'a Bump,
+    ) -> Token<'a> {
+        let mut token = self.placeholder(source, start, 0, alloc);
+        token.shared = None;
+        token.children.extend(
+            self.children
+                .iter()
+                .map(|(offset, n)| n.to_token(source, start + offset, alloc)),
+        );
+        token
+    }
+
+    /// Make a token standing in for the node, which is `reused[shared]` while matching
+    pub(crate) fn placeholder<'a>(
+        &self,
+        source: &Arc<str>,
+        start: usize,
+        shared: usize,
+        alloc: &'a Bump,
+    ) -> Token<'a> {
+        Token {
+            matcher_name: self.matcher_name.clone(),
+            matcher_id: self.matcher_id,
+            children: bumpalo::collections::Vec::new_in(alloc),
+            label: self.label.clone(),
+            source: source.clone(),
+            range: start..start + self.len,
+            shared: Some(shared),
+        }
+    }
+}
+
+/// The result of applying a named matcher at some position, kept for the next parse. Every
+/// position in it is relative to where the matcher was applied.
+pub(crate) struct ReuseEntry {
+    pub(crate) len: Option<usize>,
+    /// How far past its position the matcher looked at the input. The entry stays valid as
+    /// long as none of that is edited.
+    pub(crate) examined: usize,
+    /// The nodes of the match with where they start
+    pub(crate) nodes: Vec<(usize, Arc<Node>)>,
+    pub(crate) success: Option<SuccessMark>,
+}
+
+pub(crate) type ReuseTable = HashMap<(usize, usize), ReuseEntry>;
+
+/// A tokenized text which can be edited and tokenized again, matching only the parts of it
+/// around the edit. Every rule matched while tokenizing is kept along with how much of the
+/// text it looked at, and an edit throws away only those that looked at the edited text, so
+/// the rest are reused when the text is tokenized again.
+pub struct IncrementalTree<'l> {
+    lexer: &'l Lexer,
+    text: String,
+    reuse: ReuseTable,
+    tree: std::result::Result<Vec<(usize, Arc<Node>)>, FluxError>,
+    rematched: usize,
+}
+
+impl<'l> IncrementalTree<'l> {
+    pub(crate) fn new(lexer: &'l Lexer, text: &str) -> Self {
+        let mut tree = IncrementalTree {
+            lexer,
+            text: text.into(),
+            reuse: HashMap::new(),
+            tree: Ok(Vec::new()),
+            rematched: 0,
+        };
+        tree.reparse();
+        tree
+    }
+
+    /// Get the current text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the error the current text failed to tokenize with, if any
+    pub fn error(&self) -> Option<&FluxError> {
+        self.tree.as_ref().err()
+    }
+
+    /// Get how many times a rule had to be matched rather than reused the last time the text
+    /// was tokenized
+    pub fn rematched_rules(&self) -> usize {
+        self.rematched
+    }
+
+    /// Replace `range` of the text with `text` and tokenize it again. Returns the ranges in
+    /// the new text of the tokens that changed: every token that didn't exist before the edit
+    /// and wasn't only rebuilt around unchanged children, merged where they touch. The whole
+    /// text is returned if it either didn't tokenize before the edit or doesn't after.
+    ///
+    /// Panics if `range` doesn't lie on char boundaries, like `String::replace_range`.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Vec<Range<usize>> {
+        self.text.replace_range(range.clone(), text);
+        let edit = Edit {
+            range,
+            len: text.len(),
+        };
+        self.reuse = std::mem::take(&mut self.reuse)
+            .into_iter()
+            .filter_map(|((matcher, pos), entry)| {
+                let pos = edit.shift(pos, pos + entry.examined)?;
+                Some(((matcher, pos), entry))
+            })
+            .collect();
+        let old_tree = std::mem::replace(&mut self.tree, Ok(Vec::new()));
+        self.reparse();
+        match (&old_tree, &self.tree) {
+            (Ok(old), Ok(new)) => changed_ranges(old, new, &edit),
+            _ => std::iter::once(0..self.text.len()).collect(),
+        }
+    }
+
+    /// Call `processor` with the root token of the current text, like `Lexer::tokenize`
+    pub fn tokenize<T>(&self, processor: impl FnOnce(&mut Token) -> T) -> Result<T> {
+        let nodes = self.tree.as_ref().map_err(Clone::clone)?;
+        let (start, node) = &nodes[0];
+        let bump = Bump::new();
+        let mut root = node.to_token(&self.text.as_str().into(), *start, &bump);
+        Ok(processor(&mut root))
+    }
+
+    fn reparse(&mut self) {
+        let bump = Bump::new();
+        let mut output = TokenOutput::new(&bump);
+        let kept = self.reuse.len();
+        output.reuse = Some(std::mem::take(&mut self.reuse));
+        // Tokens share the text they match, so it's copied once to be matched
+        let result = self
+            .lexer
+            .apply_root(self.text.as_str().into(), &mut output, &bump);
+        self.tree = result.map(|_| {
+            let nodes = output.tokens.iter();
+            nodes
+                .map(|t| (t.range.start, Node::new(t, &output.reused)))
+                .collect()
+        });
+        self.reuse = output.reuse.take().unwrap_or_default();
+        self.rematched = self.reuse.len() - kept;
+    }
+}
+
+/// A range of the text replaced by `len` bytes
+struct Edit {
+    range: Range<usize>,
+    len: usize,
+}
+
+impl Edit {
+    /// Move the span `start..end` of the text before the edit to where it is after it, if the
+    /// edit didn't change it
+    fn shift(&self, start: usize, end: usize) -> Option<usize> {
+        if end <= self.range.start {
+            Some(start)
+        } else if start >= self.range.end {
+            Some(start - self.range.end + self.range.start + self.len)
+        } else {
+            None
+        }
+    }
+}
+
+/// A token identified by everything that makes it look the same after an edit, to find the
+/// tokens that changed
+type TokenKey = (usize, Option<Arc<str>>, Range<usize>, usize);
+
+fn changed_ranges(
+    old: &[(usize, Arc<Node>)],
+    new: &[(usize, Arc<Node>)],
+    edit: &Edit,
+) -> Vec<Range<usize>> {
+    fn collect_old(node: &Node, start: usize, edit: &Edit, keys: &mut HashSet<TokenKey>) {
+        if let Some(start) = edit.shift(start, start + node.len) {
+            let range = start..start + node.len;
+            keys.insert((
+                node.matcher_id,
+                node.label.clone(),
+                range,
+                node.children.len(),
+            ));
+        }
+        for (offset, child) in &node.children {
+            collect_old(child, start + offset, edit, keys);
+        }
+    }
+
+    /// Add the ranges of the outermost tokens under `node` that changed all the way down,
+    /// returning whether `node` changed, and whether it did all the way down
+    fn collect_changed(
+        node: &Node,
+        start: usize,
+        keys: &HashSet<TokenKey>,
+        ranges: &mut Vec<Range<usize>>,
+    ) -> (bool, bool) {
+        let first = ranges.len();
+        let mut any_changed = false;
+        let mut all_rebuilt = true;
+        for (offset, child) in &node.children {
+            let (changed, rebuilt) = collect_changed(child, start + offset, keys, ranges);
+            any_changed |= changed;
+            all_rebuilt &= rebuilt;
+        }
+        let range = start..start + node.len;
+        let key = (
+            node.matcher_id,
+            node.label.clone(),
+            range.clone(),
+            node.children.len(),
+        );
+        let changed = any_changed || !keys.contains(&key);
+        let rebuilt = changed && all_rebuilt;
+        if rebuilt {
+            ranges.truncate(first);
+            ranges.push(range);
+        }
+        (changed, rebuilt)
+    }
+
+    let mut keys = HashSet::new();
+    for (start, node) in old {
+        collect_old(node, *start, edit, &mut keys);
+    }
+    let mut ranges = Vec::new();
+    for (start, node) in new {
+        collect_changed(node, *start, &keys, &mut ranges);
+    }
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
@@ -1,9 +1,21 @@
 use bumpalo::Bump;
 
-use crate::error::Result;
+use crate::analysis::{self, Diagnostic};
+use crate::codegen;
+use crate::error::{FluxError, Result};
+use crate::export::{self, Export};
+use crate::incremental::IncrementalTree;
 use crate::matchers::{Matcher, MatcherType, TokenOutput};
+use crate::memo::{self, MemoStats};
+use crate::printer;
+use crate::profile::{Profile, ProfileCollector};
+use crate::railroad;
+use crate::recovery::Recovery;
+use crate::serialize;
 use crate::tokens::Token;
+use crate::trace::Tracer;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -37,10 +49,44 @@ impl Lexer {
             names,
             matchers,
         };
+        lexer.init_left_recursion();
         lexer.init_caches();
         lexer
     }
 
+    /// Find the cycles of matchers that can be applied again at the same position without
+    /// consuming anything, and mark enough of them as left-recursive to break every cycle
+    fn init_left_recursion(&mut self) {
+        let nullable = analysis::nullable(&self.matchers);
+        let mut visited = vec![false; self.matchers.len()];
+        let mut on_stack = vec![false; self.matchers.len()];
+        for start in std::iter::once(self.root).chain(0..self.matchers.len()) {
+            self.visit_left_children(start, &nullable, &mut visited, &mut on_stack);
+        }
+    }
+
+    fn visit_left_children(
+        &mut self,
+        id: usize,
+        nullable: &[bool],
+        visited: &mut [bool],
+        on_stack: &mut [bool],
+    ) {
+        if visited[id] {
+            return;
+        }
+        visited[id] = true;
+        on_stack[id] = true;
+        for child in self.matchers[id].left_children(nullable) {
+            if on_stack[child] {
+                self.matchers[child].left_recursive = true;
+            } else {
+                self.visit_left_children(child, nullable, visited, on_stack);
+            }
+        }
+        on_stack[id] = false;
+    }
+
     fn init_caches(&mut self) {
         for i in 0..self.matchers.len() {
             match &self.matchers[i].matcher_type {
@@ -64,11 +110,89 @@ impl Lexer {
                     self.matchers[i].matcher_type =
                         MatcherType::Repeating(*child, match_range.clone(), Some(cache.into()));
                 }
+                MatcherType::Precedence(table) if table.prefix_cache.is_none() => {
+                    let mut table = table.clone();
+                    let operators_starting_with = |operators: Vec<usize>| {
+                        Box::new(std::array::from_fn(|i| {
+                            operators
+                                .iter()
+                                .cloned()
+                                .filter(|o| {
+                                    self.matchers[table.operators[*o].matcher]
+                                        .can_start_with(i as u8 as char, &self.matchers)
+                                })
+                                .collect()
+                        }))
+                    };
+                    let prefix_cache = operators_starting_with(table.prefix().collect());
+                    let suffix_cache = operators_starting_with(table.suffix().collect());
+                    table.prefix_cache = Some(prefix_cache);
+                    table.suffix_cache = Some(suffix_cache);
+                    self.matchers[i].matcher_type = MatcherType::Precedence(table);
+                }
                 _ => (),
             }
         }
     }
 
+    /// Check the grammar for likely mistakes, like rules that can never match or that can't be
+    /// reached from the root
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        analysis::validate(self.root, &self.matchers)
+    }
+
+    /// Write the grammar back out as BNF, which `bnf::parse` turns into an equivalent lexer
+    pub fn to_bnf(&self) -> String {
+        self.to_string()
+    }
+
+    /// Export the grammar as W3C-style EBNF
+    pub fn to_ebnf(&self) -> Export {
+        export::to_ebnf(self.root, &self.matchers)
+    }
+
+    /// Export the grammar as an ANTLR4 grammar called `grammar_name`
+    pub fn to_antlr(&self, grammar_name: &str) -> Export {
+        export::to_antlr(grammar_name, self.root, &self.matchers)
+    }
+
+    /// Draw a syntax diagram for every rule on a self-contained HTML page, with each rule
+    /// linking to the rules it uses
+    pub fn to_railroad_html(&self, title: &str) -> String {
+        railroad::write_html(title, self.root, &self.matchers)
+    }
+
+    /// Write Rust source defining `pub fn lexer() -> &'static Lexer`, which builds this
+    /// lexer without parsing anything. See `codegen::compile_grammar` for doing it from a
+    /// build script.
+    pub fn to_rust(&self) -> String {
+        let mut out = String::new();
+        codegen::write_rust(
+            &mut out,
+            self.root,
+            self.retain_empty,
+            &self.names,
+            &self.matchers,
+        )
+        .expect("writing to a String can't fail");
+        out
+    }
+
+    /// Serialize the lexer, along with the caches it builds when it's created, into a
+    /// versioned and checksummed binary format which `Lexer::from_bytes` reads back
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serialize::write(self.root, self.retain_empty, &self.names, &self.matchers)
+    }
+
+    /// Read a lexer written by `Lexer::to_bytes`, failing if the data is corrupted or was
+    /// written in a different version of the format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Lexer> {
+        let parts = serialize::read(bytes)?;
+        let mut lexer = Lexer::new(parts.root, parts.names, parts.matchers);
+        lexer.retain_empty = parts.retain_empty;
+        Ok(lexer)
+    }
+
     pub fn set_retain_empty(&mut self, retain_empty: bool) {
         self.retain_empty = retain_empty;
     }
@@ -91,6 +215,56 @@ impl Lexer {
         }
     }
 
+    /// Memoize the named rules in `names`, like annotating them with `@memo`
+    pub fn memoize_rules(&mut self, names: impl IntoIterator<Item = impl AsRef<str>>) {
+        for matcher in names.into_iter().map(|n| self.names[n.as_ref()]) {
+            self.matchers[matcher].memoize = true;
+        }
+    }
+
+    /// Memoize every named rule, so none is ever matched twice at the same position
+    pub fn memoize_all(&mut self) {
+        for matcher in &mut self.matchers {
+            if matcher.name.is_some() {
+                matcher.memoize = true;
+            }
+        }
+    }
+
+    /// Match the root against `input`, counting how often each named rule is applied and
+    /// how much memory the memo table takes
+    pub fn memo_stats(&self, input: impl AsRef<str>) -> MemoStats {
+        let source: Arc<str> = input.as_ref().into();
+        let bump = Bump::new();
+        let mut output = TokenOutput::new(&bump);
+        output.stats = Some(Default::default());
+        let root = &self.matchers[self.root];
+        root.apply(source, &mut output, &self.matchers, 0, 0, &bump);
+        MemoStats::new(&output, &self.matchers)
+    }
+
+    /// Match the root against `input`, timing every matcher applied and counting how much
+    /// it matched, how much it had to look at again after backtracking, and how much its
+    /// first-char cache let it skip
+    pub fn profile(&self, input: impl AsRef<str>) -> Profile {
+        let source: Arc<str> = input.as_ref().into();
+        let bump = Bump::new();
+        let mut output = TokenOutput::new(&bump);
+        output.profile = Some(ProfileCollector::new(self.matchers.len()));
+        let root = &self.matchers[self.root];
+        root.apply(source, &mut output, &self.matchers, 0, 0, &bump);
+        match output.profile.take() {
+            Some(collector) => Profile::new(collector, &self.matchers),
+            None => Profile::default(),
+        }
+    }
+
+    /// Tokenize `text` into a tree which can be edited and tokenized again, reusing every
+    /// match the edits don't affect
+    pub fn incremental(&self, text: &str) -> IncrementalTree<'_> {
+        IncrementalTree::new(self, text)
+    }
+
     pub fn check(&self, input: impl AsRef<str>) -> Result<()> {
         self.tokenize(input, |_| ())
     }
@@ -99,13 +273,26 @@ impl Lexer {
         self.tokenize_with(matcher, input, |_| ())
     }
 
+    /// Tokenize `input` with the root, returning what `processor` returned for the root
+    /// token. The input is copied once into an `Arc<str>` which every token shares.
     pub fn tokenize<T>(
         &self,
         input: impl AsRef<str>,
         processor: impl FnOnce(&mut Token) -> T,
     ) -> Result<T> {
         let root = &self.matchers[self.root];
-        self.do_tokenize(root, input, processor)
+        self.do_tokenize(root, input, None, processor)
+    }
+
+    /// Tokenize `input` like `Lexer::tokenize`, telling `tracer` about every matcher applied
+    pub fn tokenize_traced<T>(
+        &self,
+        input: impl AsRef<str>,
+        tracer: &mut dyn Tracer,
+        processor: impl FnOnce(&mut Token) -> T,
+    ) -> Result<T> {
+        let root = &self.matchers[self.root];
+        self.do_tokenize(root, input, Some(tracer), processor)
     }
 
     pub fn tokenize_with<T>(
@@ -115,32 +302,105 @@ impl Lexer {
         processor: impl FnOnce(&mut Token) -> T,
     ) -> Result<T> {
         let matcher = &self.matchers[self.names[matcher]];
-        self.do_tokenize(matcher, input, processor)
+        self.do_tokenize(matcher, input, None, processor)
+    }
+
+    /// Tokenize `input` without stopping at the first error. Each rule annotated with
+    /// `@recover(sync)` that fails at an error skips ahead to where `sync` matches, and the
+    /// skipped text is covered by a token named `Error`. A grammar without any of them skips
+    /// past the next line break or up to the next closing bracket in a repetition instead.
+    /// Returns what `processor` returned for the root token, along with every error found.
+    pub fn tokenize_recovering<T>(
+        &self,
+        input: impl AsRef<str>,
+        processor: impl FnOnce(&mut Token) -> T,
+    ) -> (T, Vec<FluxError>) {
+        let source: Arc<str> = input.as_ref().into();
+        let root = &self.matchers[self.root];
+        let heuristic = self.matchers.iter().all(|m| m.recover.is_none());
+        let mut recovery = Recovery::new(heuristic);
+        let mut errors = Vec::new();
+        // Every error found is skipped when tokenizing again, until there are no new ones
+        loop {
+            let bump = Bump::new();
+            let mut output = TokenOutput::new(&bump);
+            output.recovery = Some(recovery);
+            output.expected = Some(Default::default());
+            let range = root.apply(source.clone(), &mut output, &self.matchers, 0, 0, &bump);
+            recovery = output.recovery.take().unwrap();
+            let matched = range.filter(|_| !output.tokens.is_empty());
+            if matched.as_ref().is_some_and(|r| r.end == source.len()) {
+                memo::expand_shared(&output.memo_tokens, &mut output.tokens[0]);
+                return (processor(&mut output.tokens[0]), errors);
+            }
+            let error = output.create_error(source.clone(), &self.matchers);
+            if recovery.add_error(error.location) {
+                errors.push(error);
+                continue;
+            }
+            // Nothing could skip past the error, so the rest of the input is
+            let end = matched.map_or(0, |r| r.end);
+            let mut root_token = match end {
+                0 => root.create_token(source.clone(), 0..0, &bump),
+                _ => output.tokens.swap_remove(0),
+            };
+            memo::expand_shared(&output.memo_tokens, &mut root_token);
+            let error = Token::error(source.clone(), end..source.len(), &bump);
+            root_token.children.push(error);
+            root_token.range.end = source.len();
+            return (processor(&mut root_token), errors);
+        }
     }
 
     fn do_tokenize<T>(
         &self,
         root: &Matcher,
         input: impl AsRef<str>,
+        tracer: Option<&mut dyn Tracer>,
         processor: impl FnOnce(&mut Token) -> T,
     ) -> Result<T> {
         let input = input.as_ref();
-        let source: Arc<[char]> = input.chars().collect();
-        let pos = 0;
+        let source: Arc<str> = input.into();
         let bump = Rc::new(Bump::with_capacity(10000));
-        let mut output = TokenOutput {
-            tokens: bumpalo::collections::Vec::new_in(&bump),
-            last_success: Default::default(),
-        };
-        let range = root
-            .apply(source.clone(), &mut output, &self.matchers, pos, 0, &bump)
-            .ok_or_else(|| output.create_error(source.clone(), &self.matchers))?;
-        if output.tokens.is_empty() || range.end != source.len() {
-            Err(output.create_error(source, &self.matchers))
-        } else {
-            let mapped_value = processor(&mut output.tokens[0]);
-            std::mem::forget(output);
-            Ok(mapped_value)
+        let mut output = TokenOutput::new(&bump);
+        output.tracer = tracer.map(|tracer| tracer as _);
+        self.apply_matcher(root, source, &mut output, &bump)?;
+        memo::expand_shared(&output.memo_tokens, &mut output.tokens[0]);
+        Ok(processor(&mut output.tokens[0]))
+    }
+
+    pub(crate) fn matchers(&self) -> &[Matcher] {
+        &self.matchers
+    }
+
+    /// Match the whole of `source` with the root, leaving its token first in the output
+    pub(crate) fn apply_root<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        alloc: &'a Bump,
+    ) -> Result<()> {
+        self.apply_matcher(&self.matchers[self.root], source, output, alloc)
+    }
+
+    fn apply_matcher<'a>(
+        &self,
+        matcher: &Matcher,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        alloc: &'a Bump,
+    ) -> Result<()> {
+        output.expected = Some(Default::default());
+        let range = matcher.apply(source.clone(), output, &self.matchers, 0, 0, alloc);
+        if range.is_some_and(|r| r.end == source.len()) && !output.tokens.is_empty() {
+            return Ok(());
         }
+        Err(output.create_error(source, &self.matchers))
+    }
+}
+
+impl Display for Lexer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        printer::write_grammar(f, self.root, &self.matchers)
     }
 }
@@ -1,8 +1,29 @@
+pub mod analysis;
 pub mod bnf;
+pub mod char_class;
+pub mod codegen;
 pub mod error;
+pub mod export;
+pub mod grammar;
+pub mod incremental;
 pub mod lexer;
+pub mod loader;
 pub mod matchers;
+pub mod memo;
+pub mod position;
+pub mod precedence;
+mod printer;
+pub mod profile;
+mod railroad;
+mod recovery;
+mod serialize;
 pub mod tokens;
+pub mod trace;
+mod unicode_tables;
 
 #[cfg(test)]
 mod tests;
+
+// Lets the tests use code generated for other crates, which refers to `::flux_bnf`
+#[cfg(test)]
+extern crate self as flux_bnf;
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Provides the contents of grammar files named in `@import` directives
+pub trait GrammarLoader {
+    /// Load the grammar at `path`, relative to the root grammar. Imports in an imported
+    /// grammar are relative to it, and are resolved against its path before being loaded.
+    fn load(&self, path: &str) -> io::Result<String>;
+}
+
+impl<F: Fn(&str) -> io::Result<String>> GrammarLoader for F {
+    fn load(&self, path: &str) -> io::Result<String> {
+        self(path)
+    }
+}
+
+/// Loads grammars from the filesystem, relative to a root directory
+#[derive(Debug, Clone)]
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+impl FileLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl GrammarLoader for FileLoader {
+    fn load(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(self.root.join(path))
+    }
+}
+
+/// Loads grammars from a fixed set of in-memory sources, such as ones embedded with
+/// `include_str!`
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a grammar that can be imported as `path`
+    pub fn with(mut self, path: impl Into<String>, grammar: impl Into<String>) -> Self {
+        self.insert(path, grammar);
+        self
+    }
+
+    /// Add a grammar that can be imported as `path`
+    pub fn insert(&mut self, path: impl Into<String>, grammar: impl Into<String>) {
+        self.files.insert(path.into(), grammar.into());
+    }
+}
+
+impl GrammarLoader for MemoryLoader {
+    fn load(&self, path: &str) -> io::Result<String> {
+        self.files.get(path).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{path} was never added"))
+        })
+    }
+}
+
+/// Used when parsing without a loader, so any import fails
+pub(crate) struct NoLoader;
+
+impl GrammarLoader for NoLoader {
+    fn load(&self, _path: &str) -> io::Result<String> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no grammar loader was given, use bnf::parse_with_loader",
+        ))
+    }
+}
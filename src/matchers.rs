@@ -1,16 +1,27 @@
 #![allow(clippy::too_many_arguments)]
+use std::collections::HashMap;
 use std::ops::Range;
 use std::{ops::RangeInclusive, sync::Arc};
 
 use bumpalo::Bump;
 
+use crate::analysis;
+use crate::char_class::CharClass;
 use crate::error::FluxError;
+use crate::incremental::{Node, ReuseEntry, ReuseTable};
 use crate::lexer::CullStrategy;
+use crate::memo::{MemoEntry, StatsCollector};
+use crate::precedence::{Associativity, Fixity, Operator, OperatorTable};
+use crate::printer;
+use crate::profile::ProfileCollector;
+use crate::recovery::{self, Recovery};
 use crate::tokens::Token;
+use crate::trace::{TraceEvent, Tracer};
 
 pub type MatcherName = Arc<Option<String>>;
 pub type TokenResult = Option<Range<usize>>;
 
+#[derive(Clone, Copy)]
 pub struct SuccessMark {
     pub(crate) begin: usize,
     pub(crate) end: usize,
@@ -18,6 +29,24 @@ pub struct SuccessMark {
     pub(crate) matcher: Option<usize>,
 }
 
+impl SuccessMark {
+    /// Record a match of `begin..end` at `depth`, by `matcher` if it should be shown in errors
+    fn mark(&mut self, begin: usize, end: usize, depth: usize, matcher: Option<usize>) {
+        if end < self.end {
+            return;
+        }
+        if end > self.end {
+            self.depth = depth;
+        }
+        self.begin = begin;
+        self.end = end;
+        if matcher.is_some() && depth <= self.depth {
+            self.matcher = matcher;
+            self.depth = depth;
+        }
+    }
+}
+
 impl Default for SuccessMark {
     fn default() -> Self {
         Self {
@@ -29,41 +58,275 @@ impl Default for SuccessMark {
     }
 }
 
+/// The matchers that failed at the furthest position any did, to report as expected there
+#[derive(Default)]
+pub(crate) struct Expected {
+    pos: usize,
+    matchers: Vec<usize>,
+}
+
+impl Expected {
+    fn add(&mut self, pos: usize, matcher: usize) {
+        if pos > self.pos {
+            self.pos = pos;
+            self.matchers.clear();
+        }
+        if pos == self.pos && !self.matchers.contains(&matcher) {
+            self.matchers.push(matcher);
+        }
+    }
+
+    /// Forget every failure before `pos`
+    fn reset(&mut self, pos: usize) {
+        if self.pos < pos {
+            self.pos = pos;
+            self.matchers.clear();
+        }
+    }
+
+    fn merge(&mut self, other: Expected) {
+        for matcher in other.matchers {
+            self.add(other.pos, matcher);
+        }
+    }
+
+    /// Describe the matchers expected at `pos`. A rule that starts with another rule
+    /// expected is left out for the more specific one, and a literal a rule expected starts
+    /// with is left out for the rule.
+    fn describe(&self, pos: usize, matchers: &[Matcher]) -> Vec<String> {
+        if pos != self.pos {
+            return Vec::new();
+        }
+        let nullable = analysis::nullable(matchers);
+        // The matchers each expected one can start with, not counting itself
+        let starts: Vec<Vec<bool>> = self
+            .matchers
+            .iter()
+            .map(|id| {
+                let mut visited = vec![false; matchers.len()];
+                let mut stack = matchers[*id].left_children(&nullable);
+                while let Some(child) = stack.pop() {
+                    if !std::mem::replace(&mut visited[child], true) {
+                        stack.extend(matchers[child].left_children(&nullable));
+                    }
+                }
+                visited
+            })
+            .collect();
+        let starts_with =
+            |outer: usize, inner: usize| outer != inner && starts[outer][self.matchers[inner]];
+        let is_rule = |i: usize| matchers[self.matchers[i]].name.is_some();
+        let shown: Vec<bool> = (0..self.matchers.len())
+            .map(|i| {
+                is_rule(i) && !(0..self.matchers.len()).any(|j| is_rule(j) && starts_with(i, j))
+            })
+            .collect();
+        let mut described: Vec<String> = Vec::new();
+        for (i, id) in self.matchers.iter().enumerate() {
+            let description = match (&*matchers[*id].name, &matchers[*id].matcher_type) {
+                (Some(name), _) if shown[i] => name.clone(),
+                (None, MatcherType::String(s, case_sensitive))
+                    if !(0..self.matchers.len()).any(|j| shown[j] && starts_with(j, i)) =>
+                {
+                    let mut literal = String::new();
+                    let _ = printer::write_string(&mut literal, s, *case_sensitive);
+                    literal
+                }
+                _ => continue,
+            };
+            if !described.contains(&description) {
+                described.push(description);
+            }
+        }
+        described
+    }
+}
+
+/// The match of a left-recursive matcher at some position, as grown so far
+type Seed<'a> = Option<(Range<usize>, Vec<Token<'a>>)>;
+
 pub struct TokenOutput<'a> {
     pub(crate) tokens: bumpalo::collections::Vec<'a, Token<'a>>,
     pub(crate) last_success: SuccessMark,
+    pub(crate) seeds: HashMap<(usize, usize), Seed<'a>>,
+    /// Set once a cut is passed, until the attempt it was passed in is over
+    pub(crate) cut: bool,
+    /// How many cuts have been passed, to tell whether a match depended on one
+    pub(crate) cuts: usize,
+    pub(crate) memo: HashMap<(usize, usize), MemoEntry>,
+    /// The tokens of every memo entry, which tokens standing in for them refer to by index
+    pub(crate) memo_tokens: Vec<Token<'a>>,
+    /// The successes marked by each memoized matcher being applied, innermost last
+    pub(crate) memo_marks: Vec<Option<SuccessMark>>,
+    /// The seeds of left-recursive matchers used while applying memoized matchers
+    pub(crate) seed_uses: Vec<(usize, usize)>,
+    /// How many matchers are being applied that can go back to before a cut after passing
+    /// it, like lookaheads, while nothing before a cut can be thrown away
+    pub(crate) rewinding: usize,
+    pub(crate) stats: Option<StatsCollector>,
+    /// The furthest position any matcher has looked at the input up to
+    pub(crate) examined: usize,
+    /// The matches kept from tokenizing an `IncrementalTree` before it was edited
+    pub(crate) reuse: Option<ReuseTable>,
+    /// The nodes of the matches kept by an `IncrementalTree`, which tokens standing in for
+    /// them refer to by index instead of `memo_tokens`
+    pub(crate) reused: Vec<Arc<Node>>,
+    /// The errors to skip past when tokenizing with error recovery
+    pub(crate) recovery: Option<Recovery>,
+    /// The furthest any match has ended, since it was last reset
+    pub(crate) reached: usize,
+    /// Set to collect what was expected where matching failed. The caches that skip
+    /// matchers which can't match the next char are left unused from the farthest failure
+    /// on, so every matcher that could be reported fails where it would.
+    pub(crate) expected: Option<Expected>,
+    pub(crate) tracer: Option<&'a mut dyn Tracer>,
+    pub(crate) profile: Option<ProfileCollector>,
 }
 
 impl<'a> TokenOutput<'a> {
+    pub(crate) fn new(alloc: &'a Bump) -> Self {
+        Self {
+            tokens: bumpalo::collections::Vec::new_in(alloc),
+            last_success: Default::default(),
+            seeds: HashMap::new(),
+            cut: false,
+            cuts: 0,
+            memo: HashMap::new(),
+            memo_tokens: Vec::new(),
+            memo_marks: Vec::new(),
+            seed_uses: Vec::new(),
+            rewinding: 0,
+            stats: None,
+            examined: 0,
+            reuse: None,
+            reused: Vec::new(),
+            recovery: None,
+            reached: 0,
+            expected: None,
+            tracer: None,
+            profile: None,
+        }
+    }
+
     fn len(&self) -> usize {
         self.tokens.len()
     }
 
-    fn mark_success(&mut self, begin: usize, end: usize, depth: usize, matcher: &Matcher) {
-        if end < self.last_success.end {
-            return;
+    /// Make a token standing in for one in the memo table, without its children
+    fn shared_token(&self, shared: usize, alloc: &'a Bump) -> Token<'a> {
+        let token = &self.memo_tokens[shared];
+        Token {
+            matcher_name: token.matcher_name.clone(),
+            matcher_id: token.matcher_id,
+            children: bumpalo::collections::Vec::new_in(alloc),
+            label: token.label.clone(),
+            source: token.source.clone(),
+            range: token.range.clone(),
+            shared: Some(shared),
         }
-        if end > self.last_success.end {
-            self.last_success.depth = depth;
+    }
+
+    /// Make a token standing in for a node kept by an `IncrementalTree`, without its children
+    fn reused_token(
+        &mut self,
+        source: &Arc<str>,
+        start: usize,
+        node: Arc<Node>,
+        alloc: &'a Bump,
+    ) -> Token<'a> {
+        let token = node.placeholder(source, start, self.reused.len(), alloc);
+        self.reused.push(node);
+        token
+    }
+
+    /// Whether the caches that skip matchers which can't match the char at `pos` can be
+    /// used. While collecting what was expected, that's only before the farthest failure so
+    /// far, where a skipped matcher failing could never be reported. They aren't while
+    /// tracing, so every alternative shows up as rejected.
+    fn caching(&self, pos: usize) -> bool {
+        self.recovery.is_none()
+            && self.tracer.is_none()
+            && self.expected.as_ref().is_none_or(|e| pos < e.pos)
+    }
+
+    /// Note that `matcher` failed at `pos`, if it's a rule shown in errors or a literal
+    fn expect(&mut self, matcher: &Matcher, pos: usize) {
+        let Some(expected) = &mut self.expected else {
+            return;
+        };
+        let shown = match &*matcher.name {
+            Some(_) => matcher.show_in_errors,
+            None => matches!(matcher.matcher_type, MatcherType::String(..)),
+        };
+        if shown {
+            expected.add(pos, matcher.id);
         }
-        self.last_success.begin = begin;
-        self.last_success.end = end;
-        if matcher.show_in_errors && depth <= self.last_success.depth {
-            self.last_success.matcher.replace(matcher.id);
-            self.last_success.depth = depth;
+    }
+
+    /// Note that the input before `end` has been looked at
+    fn examine(&mut self, end: usize) {
+        self.examined = self.examined.max(end);
+    }
+
+    fn mark_success(&mut self, begin: usize, end: usize, depth: usize, matcher: &Matcher) {
+        let matcher = matcher.show_in_errors.then_some(matcher.id);
+        self.mark(begin, end, depth, matcher);
+    }
+
+    fn mark(&mut self, begin: usize, end: usize, depth: usize, matcher: Option<usize>) {
+        self.reached = self.reached.max(end);
+        self.last_success.mark(begin, end, depth, matcher);
+        if let Some(frame) = self.memo_marks.last_mut() {
+            frame
+                .get_or_insert_default()
+                .mark(begin, end, depth, matcher);
         }
     }
 
-    pub(crate) fn create_error(&self, source: Arc<[char]>, matchers: &[Matcher]) -> FluxError {
-        if let Some(matcher) = self.last_success.matcher {
-            let name = matchers[matcher].name.clone();
-            FluxError::new_matcher("expected", self.last_success.end, 0, name, Some(source))
-        } else {
-            FluxError::new("unexpected token", self.last_success.end, Some(source))
+    /// Start applying a matcher that is allowed to fail without failing the matcher applying
+    /// it, like an alternative of a choice. Returns whether a cut had already been passed.
+    fn begin_attempt(&mut self) -> bool {
+        std::mem::take(&mut self.cut)
+    }
+
+    /// Finish an attempt started with `begin_attempt`. Returns false if it failed after
+    /// passing a cut, meaning it can't be backtracked out of and its parent has to fail too.
+    fn end_attempt(&mut self, outer_cut: bool, matched: bool) -> bool {
+        if !matched && self.cut {
+            return false;
         }
+        self.cut |= outer_cut;
+        true
+    }
+
+    pub(crate) fn create_error(&self, source: Arc<str>, matchers: &[Matcher]) -> FluxError {
+        let location = self.last_success.end;
+        let expected = match &self.expected {
+            Some(expected) => expected.describe(location, matchers),
+            None => Vec::new(),
+        };
+        let name = match self.last_success.matcher {
+            Some(matcher) => matchers[matcher].name.clone(),
+            None if expected.is_empty() => {
+                return FluxError::new("unexpected token", location, Some(source));
+            }
+            None => Arc::new(None),
+        };
+        let expected = match (expected.is_empty(), &*name) {
+            (true, Some(name)) => vec![name.clone()],
+            _ => expected,
+        };
+        FluxError::new_matcher("expected", location, 0, name, Some(source)).with_expected(expected)
     }
 }
 
+/// Where a matcher was defined, as a position in the source of its grammar
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub(crate) pos: usize,
+    pub(crate) source: Arc<[char]>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Matcher {
     pub(crate) cull_strategy: CullStrategy,
@@ -71,18 +334,30 @@ pub struct Matcher {
     pub(crate) id: usize,
     pub(crate) matcher_type: MatcherType,
     pub(crate) show_in_errors: bool,
+    /// Set on the matchers that grow their match to break cycles of left recursion
+    pub(crate) left_recursive: bool,
+    /// Set on the matchers whose results are kept for each position they're applied at, so
+    /// they're never matched twice at the same position
+    pub(crate) memoize: bool,
+    /// What to skip to when the matcher fails while tokenizing with error recovery, from
+    /// `@recover(sync)`
+    pub(crate) recover: Option<usize>,
+    pub(crate) location: Option<Location>,
 }
 
 #[derive(Debug, Clone)]
 pub enum MatcherType {
     String(Vec<char>, bool),
-    CharSet(Vec<char>, bool),
-    CharRange(RangeInclusive<char>, bool),
+    CharClass(CharClass),
     List(Vec<usize>),
     Choice(Vec<usize>, Option<Box<[Vec<usize>; 256]>>),
     Repeating(usize, RangeInclusive<usize>, Option<Box<[bool; 256]>>),
     Inverted(usize),
+    Lookahead(usize),
+    Labeled(Arc<str>, usize),
+    Precedence(Box<OperatorTable>),
     Wrapper(usize),
+    Cut,
     Eof,
     Newline,
     Placeholder,
@@ -91,7 +366,124 @@ pub enum MatcherType {
 impl Matcher {
     pub fn apply<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        matchers: &[Matcher],
+        pos: usize,
+        depth: usize,
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        if let Some(stats) = &mut output.stats {
+            if self.name.is_some() {
+                stats.applied(self.id, pos);
+            }
+        }
+        let event = TraceEvent {
+            matcher_id: self.id,
+            name: self.name.as_deref(),
+            pos,
+            depth,
+            source: &source,
+        };
+        if let Some(tracer) = &mut output.tracer {
+            tracer.enter(&event);
+        }
+        let examined = output.examined;
+        let entered = output.profile.as_mut().map(|profile| {
+            output.examined = pos;
+            profile.enter(self.id, examined)
+        });
+        let range = if self.memoize || output.reuse.is_some() && self.name.is_some() {
+            self.apply_memoized(source.clone(), output, matchers, pos, depth, alloc)
+        } else {
+            self.apply_unmemoized(source.clone(), output, matchers, pos, depth, alloc)
+        };
+        if range.is_none() {
+            output.expect(self, pos);
+        }
+        let range = match (range, self.recover) {
+            (None, Some(sync)) if output.recovery.is_some() => {
+                self.recover(source.clone(), output, matchers, pos, depth, sync, alloc)
+            }
+            (range, _) => range,
+        };
+        if let (Some(profile), Some(entered)) = (&mut output.profile, entered) {
+            let end = output.examined;
+            profile.exit(self.id, entered, &source, pos, range.clone(), end);
+            output.examine(examined);
+        }
+        if let Some(tracer) = &mut output.tracer {
+            match &range {
+                Some(range) => tracer.success(&event, range.clone()),
+                None => tracer.fail(&event),
+            }
+            tracer.exit(&event);
+        }
+        range
+    }
+
+    /// Skip from `pos` to where `sync` next matches, covering the skipped text with an error
+    /// token, if an earlier attempt failed somewhere in it
+    fn recover<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        matchers: &[Matcher],
+        pos: usize,
+        depth: usize,
+        sync: usize,
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        let recovery = output.recovery.as_ref()?;
+        if !recovery.has_error(pos, source.len()) {
+            return None;
+        }
+        let last_success = output.last_success;
+        let memo_mark = output.memo_marks.last().copied().flatten();
+        let expected = output.expected.take();
+        let end = (pos..=source.len())
+            .filter(|i| source.is_char_boundary(*i))
+            .find(|i| {
+                Self::apply_lookahead(source.clone(), output, *i, depth, sync, matchers, alloc)
+                    .is_some()
+            })
+            .unwrap_or(source.len());
+        output.last_success = last_success;
+        output.expected = expected;
+        if let Some(frame) = output.memo_marks.last_mut() {
+            *frame = memo_mark;
+        }
+        if end == pos || !output.recovery.as_ref()?.has_error(pos, end) {
+            return None;
+        }
+        let output_start = output.len();
+        push_error(source.clone(), pos..end, output, alloc);
+        match self.cull_strategy {
+            CullStrategy::DeleteAll => output.tokens.truncate(output_start),
+            _ => self.process_children(source, pos..end, output, output_start, alloc),
+        }
+        Some(pos..end)
+    }
+
+    fn apply_unmemoized<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        matchers: &[Matcher],
+        pos: usize,
+        depth: usize,
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        if self.left_recursive {
+            self.apply_left_recursive(source, output, matchers, pos, depth, alloc)
+        } else {
+            self.apply_matcher_type(source, output, matchers, pos, depth, alloc)
+        }
+    }
+
+    fn apply_matcher_type<'a>(
+        &self,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         matchers: &[Matcher],
         pos: usize,
@@ -102,22 +494,24 @@ impl Matcher {
             MatcherType::String(to_match, case_sensitive) => {
                 self.apply_string(source, output, pos, depth, to_match, *case_sensitive, alloc)
             }
-            MatcherType::CharSet(chars, inverted) => {
-                self.apply_char_set(source, output, pos, chars, *inverted, alloc)
-            }
-            MatcherType::CharRange(range, inverted) => {
-                self.apply_char_range(source, output, pos, range, *inverted, alloc)
+            MatcherType::CharClass(class) => {
+                self.apply_char_class(source, output, pos, class, alloc)
             }
             MatcherType::List(children) => {
                 self.apply_list(source, output, pos, depth, children, matchers, alloc)
             }
             MatcherType::Choice(children, cache) => {
-                let choice_children = source
+                output.examine(pos + 1);
+                let cached = source
+                    .as_bytes()
                     .get(pos)
-                    .filter(|c| c.is_ascii())
-                    .map(|c| *c as u32 as usize)
-                    .and_then(|c| cache.as_ref().map(|cache| &cache[c]))
-                    .unwrap_or(children);
+                    .filter(|b| b.is_ascii() && output.caching(pos))
+                    .map(|b| *b as usize)
+                    .and_then(|c| cache.as_ref().map(|cache| &cache[c]));
+                if let (Some(profile), Some(cached)) = (&mut output.profile, cached) {
+                    profile.cache_lookup(self.id, children.len() - cached.len());
+                }
+                let choice_children = cached.unwrap_or(children);
                 self.apply_choice(source, output, pos, depth, choice_children, matchers, alloc)
             }
             MatcherType::Repeating(child, range, cache) => self.apply_repeating(
@@ -126,10 +520,20 @@ impl Matcher {
             MatcherType::Inverted(child) => {
                 self.apply_inverted(source, output, pos, depth, *child, matchers, alloc)
             }
+            MatcherType::Lookahead(child) => {
+                Self::apply_lookahead(source, output, pos, depth, *child, matchers, alloc)
+            }
+            MatcherType::Labeled(label, child) => {
+                self.apply_labeled(source, output, pos, depth, label, *child, matchers, alloc)
+            }
+            MatcherType::Precedence(table) => {
+                self.apply_precedence(source, output, pos, depth, table, matchers, alloc)
+            }
             MatcherType::Wrapper(child) => {
                 Self::apply_wrapper(source, output, pos, depth, *child, matchers, alloc)
             }
-            MatcherType::Eof => Self::apply_eof(source, pos),
+            MatcherType::Cut => Self::apply_cut(output, pos),
+            MatcherType::Eof => Self::apply_eof(output, source, pos),
             MatcherType::Newline => self.apply_newline(output, source, pos, alloc),
             MatcherType::Placeholder => unreachable!(),
         }
@@ -138,13 +542,22 @@ impl Matcher {
     pub fn children(&mut self) -> Option<Vec<&mut usize>> {
         match &mut self.matcher_type {
             MatcherType::String(_, _) => None,
-            MatcherType::CharSet(_, _) => None,
-            MatcherType::CharRange(_, _) => None,
+            MatcherType::CharClass(_) => None,
             MatcherType::List(children) => Some(children.iter_mut().collect()),
             MatcherType::Choice(children, _) => Some(children.iter_mut().collect()),
             MatcherType::Repeating(child, _, _) => Some(vec![child]),
             MatcherType::Inverted(child) => Some(vec![child]),
+            MatcherType::Lookahead(child) => Some(vec![child]),
+            MatcherType::Labeled(_, child) => Some(vec![child]),
+            MatcherType::Precedence(table) => Some(
+                [&mut table.operand]
+                    .into_iter()
+                    .chain(&mut table.separator)
+                    .chain(table.operators.iter_mut().map(|o| &mut o.matcher))
+                    .collect(),
+            ),
             MatcherType::Wrapper(child) => Some(vec![child]),
+            MatcherType::Cut => None,
             MatcherType::Eof => None,
             MatcherType::Placeholder => None,
             MatcherType::Newline => None,
@@ -152,6 +565,31 @@ impl Matcher {
     }
 
     pub fn can_start_with(&self, c: char, matchers: &[Matcher]) -> bool {
+        self.starts_with(c, matchers, &mut Vec::new())
+    }
+
+    /// Check if the matcher can start with `c`, where `visiting` holds the left-recursive
+    /// matchers already being checked further up, so cycles between them end
+    fn starts_with(&self, c: char, matchers: &[Matcher], visiting: &mut Vec<usize>) -> bool {
+        if self.left_recursive {
+            if visiting.contains(&self.id) {
+                return false;
+            }
+            visiting.push(self.id);
+            let starts_with = self.matcher_type_starts_with(c, matchers, visiting);
+            visiting.pop();
+            starts_with
+        } else {
+            self.matcher_type_starts_with(c, matchers, visiting)
+        }
+    }
+
+    fn matcher_type_starts_with(
+        &self,
+        c: char,
+        matchers: &[Matcher],
+        visiting: &mut Vec<usize>,
+    ) -> bool {
         match &self.matcher_type {
             MatcherType::String(s, case_sensitive) => {
                 if s.is_empty() {
@@ -163,23 +601,34 @@ impl Matcher {
                     s[0].eq_ignore_ascii_case(&c)
                 }
             }
-            MatcherType::CharSet(chars, inverted) => chars.contains(&c) ^ inverted,
-            MatcherType::CharRange(range, inverted) => range.contains(&c) ^ inverted,
+            MatcherType::CharClass(class) => class.contains(c),
             MatcherType::List(children) => {
                 for child in children {
                     let child = &matchers[*child];
-                    match &child.matcher_type {
+                    match &child.unlabeled(matchers).matcher_type {
                         MatcherType::Repeating(_, r, _) => {
-                            let matches = child.can_start_with(c, matchers);
+                            let matches = child.starts_with(c, matchers, visiting);
                             if *r.start() == 0 && !matches {
                                 continue;
                             }
                             return matches;
                         }
-                        _ => return child.can_start_with(c, matchers),
+                        // Zero-width assertions don't consume `c`, so the next child must match it
+                        // too, unless the lookahead can succeed without looking at it
+                        MatcherType::Lookahead(lookahead) => {
+                            let lookahead = &matchers[*lookahead];
+                            if !lookahead.starts_with(c, matchers, visiting)
+                                && !lookahead.can_be_empty(matchers, &mut Vec::new())
+                            {
+                                return false;
+                            }
+                        }
+                        MatcherType::Inverted(_) | MatcherType::Cut => (),
+                        _ => return child.starts_with(c, matchers, visiting),
                     }
                 }
-                false
+                // Every child can match without consuming anything
+                true
             }
             MatcherType::Choice(children, cache) => cache
                 .as_ref()
@@ -189,18 +638,138 @@ impl Matcher {
                     children
                         .iter()
                         .map(|c| &matchers[*c])
-                        .any(|m| m.can_start_with(c, matchers))
+                        .any(|m| m.starts_with(c, matchers, visiting))
                 }),
-            MatcherType::Repeating(child, _, _) | MatcherType::Wrapper(child) => {
-                matchers[*child].can_start_with(c, matchers)
+            MatcherType::Repeating(child, _, _)
+            | MatcherType::Lookahead(child)
+            | MatcherType::Labeled(_, child)
+            | MatcherType::Wrapper(child) => matchers[*child].starts_with(c, matchers, visiting),
+            MatcherType::Inverted(child) => !matchers[*child].starts_with(c, matchers, visiting),
+            MatcherType::Precedence(table) => {
+                matchers[table.operand].starts_with(c, matchers, visiting)
+                    || table
+                        .prefix()
+                        .map(|i| &matchers[table.operators[i].matcher])
+                        .any(|m| m.starts_with(c, matchers, visiting))
             }
-            MatcherType::Inverted(child) => !matchers[*child].can_start_with(c, matchers),
+            MatcherType::Cut => true,
             MatcherType::Eof => false,
             MatcherType::Newline => c == '\n' || c == '\r',
             MatcherType::Placeholder => unreachable!(),
         }
     }
 
+    /// Check if the matcher can succeed without consuming anything, where `visiting` holds the
+    /// matchers being checked already
+    fn can_be_empty(&self, matchers: &[Matcher], visiting: &mut Vec<usize>) -> bool {
+        if visiting.contains(&self.id) {
+            return false;
+        }
+        visiting.push(self.id);
+        let empty = match &self.matcher_type {
+            MatcherType::String(s, _) => s.is_empty(),
+            MatcherType::List(children) => children
+                .iter()
+                .all(|c| matchers[*c].can_be_empty(matchers, visiting)),
+            MatcherType::Choice(children, _) => children
+                .iter()
+                .any(|c| matchers[*c].can_be_empty(matchers, visiting)),
+            MatcherType::Repeating(child, range, _) => {
+                *range.start() == 0 || matchers[*child].can_be_empty(matchers, visiting)
+            }
+            MatcherType::Labeled(_, child) | MatcherType::Wrapper(child) => {
+                matchers[*child].can_be_empty(matchers, visiting)
+            }
+            MatcherType::Inverted(_) | MatcherType::Lookahead(_) | MatcherType::Eof => true,
+            _ => false,
+        };
+        visiting.pop();
+        empty
+    }
+
+    /// Skip past any labels wrapped around this matcher
+    fn unlabeled<'m>(&'m self, matchers: &'m [Matcher]) -> &'m Matcher {
+        match self.matcher_type {
+            MatcherType::Labeled(_, child) => matchers[child].unlabeled(matchers),
+            _ => self,
+        }
+    }
+
+    /// Check if the matcher can succeed without consuming anything, given which of the
+    /// matchers are already known to
+    pub(crate) fn can_match_empty(&self, nullable: &[bool]) -> bool {
+        match &self.matcher_type {
+            MatcherType::String(s, _) => s.is_empty(),
+            MatcherType::CharClass(_) | MatcherType::Newline => false,
+            MatcherType::List(children) => children.iter().all(|c| nullable[*c]),
+            MatcherType::Choice(children, _) => children.iter().any(|c| nullable[*c]),
+            MatcherType::Repeating(child, range, _) => *range.start() == 0 || nullable[*child],
+            MatcherType::Inverted(_)
+            | MatcherType::Lookahead(_)
+            | MatcherType::Cut
+            | MatcherType::Eof => true,
+            MatcherType::Labeled(_, child) | MatcherType::Wrapper(child) => nullable[*child],
+            MatcherType::Precedence(table) => nullable[table.operand],
+            MatcherType::Placeholder => false,
+        }
+    }
+
+    /// Check if the matcher can match any input at all, given which of the matchers are
+    /// already known to
+    pub(crate) fn can_match(&self, productive: &[bool]) -> bool {
+        match &self.matcher_type {
+            MatcherType::List(children) => children.iter().all(|c| productive[*c]),
+            MatcherType::Choice(children, _) => children.iter().any(|c| productive[*c]),
+            MatcherType::Repeating(child, range, _) => *range.start() == 0 || productive[*child],
+            MatcherType::Lookahead(child)
+            | MatcherType::Labeled(_, child)
+            | MatcherType::Wrapper(child) => productive[*child],
+            MatcherType::Precedence(table) => productive[table.operand],
+            MatcherType::Placeholder => false,
+            _ => true,
+        }
+    }
+
+    /// Get the ids of every child of the matcher
+    pub(crate) fn child_ids(&self) -> Vec<usize> {
+        match &self.matcher_type {
+            MatcherType::List(children) | MatcherType::Choice(children, _) => children.clone(),
+            MatcherType::Repeating(child, _, _)
+            | MatcherType::Inverted(child)
+            | MatcherType::Lookahead(child)
+            | MatcherType::Labeled(_, child)
+            | MatcherType::Wrapper(child) => vec![*child],
+            MatcherType::Precedence(table) => std::iter::once(table.operand)
+                .chain(table.separator)
+                .chain(table.operators.iter().map(|o| o.matcher))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the children that can be applied at the same position as the matcher itself
+    pub(crate) fn left_children(&self, nullable: &[bool]) -> Vec<usize> {
+        match &self.matcher_type {
+            MatcherType::List(children) => {
+                let end = children
+                    .iter()
+                    .position(|c| !nullable[*c])
+                    .map_or(children.len(), |i| i + 1);
+                children[..end].to_vec()
+            }
+            MatcherType::Choice(children, _) => children.clone(),
+            MatcherType::Repeating(child, _, _)
+            | MatcherType::Inverted(child)
+            | MatcherType::Lookahead(child)
+            | MatcherType::Labeled(_, child)
+            | MatcherType::Wrapper(child) => vec![*child],
+            MatcherType::Precedence(table) => std::iter::once(table.operand)
+                .chain(table.prefix().map(|i| table.operators[i].matcher))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     fn push_token<'a>(&self, output: &mut TokenOutput<'a>, token: Token<'a>) {
         match self.cull_strategy {
             CullStrategy::DeleteAll | CullStrategy::LiftChildren => (),
@@ -210,7 +779,7 @@ impl Matcher {
 
     fn process_children<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         range: Range<usize>,
         output: &mut TokenOutput<'a>,
         start: usize,
@@ -236,7 +805,7 @@ impl Matcher {
 
     fn create_parent<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         range: Range<usize>,
         output: &mut bumpalo::collections::Vec<'a, Token<'a>>,
         start: usize,
@@ -249,9 +818,9 @@ impl Matcher {
         output.push(token);
     }
 
-    fn create_token<'a>(
+    pub(crate) fn create_token<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         range: Range<usize>,
         alloc: &'a Bump,
     ) -> Token<'a> {
@@ -259,8 +828,10 @@ impl Matcher {
             matcher_name: self.name.clone(),
             matcher_id: self.id,
             children: bumpalo::collections::Vec::new_in(alloc),
+            label: None,
             source,
             range,
+            shared: None,
         }
     }
 }
@@ -273,6 +844,47 @@ fn char_matches(first: &char, second: &char, case_sensitive: bool) -> bool {
     }
 }
 
+/// Cover `range` with an error token. Nothing is reported as expected up to its end, and
+/// nothing matched around it is memoized, since it would fail without error recovery.
+fn push_error<'a>(
+    source: Arc<str>,
+    range: Range<usize>,
+    output: &mut TokenOutput<'a>,
+    alloc: &'a Bump,
+) {
+    output.cuts += 1;
+    if output.last_success.end <= range.end {
+        output.last_success = SuccessMark {
+            begin: range.start,
+            end: range.end,
+            ..Default::default()
+        };
+    }
+    if let Some(expected) = &mut output.expected {
+        expected.reset(range.end);
+    }
+    output.tokens.push(Token::error(source, range, alloc));
+}
+
+/// Skip from `pos` to a heuristic synchronization point in a repetition that can't match
+/// any further, if an earlier attempt failed before it. The failed attempt at `pos` has to
+/// have gotten as far as the error, so the repetition closest to it is the one skipping it.
+fn skip_error<'a>(
+    source: &Arc<str>,
+    output: &mut TokenOutput<'a>,
+    pos: usize,
+    reached: usize,
+    alloc: &'a Bump,
+) -> Option<usize> {
+    let recovery = output.recovery.as_ref().filter(|r| r.heuristic)?;
+    let (end, error_end) = recovery::heuristic_sync(source, pos);
+    if end == pos || !recovery.has_error(pos, error_end.min(reached.max(pos))) {
+        return None;
+    }
+    push_error(source.clone(), pos..end, output, alloc);
+    Some(end)
+}
+
 fn next_depth(matcher: &Matcher, depth: usize) -> usize {
     match &*matcher.name {
         Some(_) => depth + 1,
@@ -281,65 +893,221 @@ fn next_depth(matcher: &Matcher, depth: usize) -> usize {
 }
 
 impl Matcher {
-    fn apply_string<'a>(
+    /// Applies a matcher at most once at each position, replaying its tokens and the
+    /// progress it marked for errors afterwards. Matches that passed a cut or used the seed
+    /// of a left-recursive matcher still being grown depend on more than the position, so
+    /// they aren't kept.
+    fn apply_memoized<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
+        matchers: &[Matcher],
         pos: usize,
         depth: usize,
-        to_match: &[char],
-        case_sensitive: bool,
         alloc: &'a Bump,
     ) -> TokenResult {
-        let matched_chars = to_match
-            .iter()
-            .zip(&source[pos..])
-            .take_while(|(a, b)| char_matches(a, b, case_sensitive))
-            .count();
-
-        if matched_chars > 0 {
-            output.mark_success(pos, pos + matched_chars, depth, self);
+        let key = (self.id, pos);
+        if let Some(reuse) = &output.reuse {
+            if let Some(entry) = reuse.get(&key) {
+                let end = pos + entry.examined;
+                let range = entry.len.map(|len| pos..pos + len);
+                let success = entry.success;
+                for (offset, node) in entry.nodes.clone() {
+                    let token = output.reused_token(&source, pos + offset, node, alloc);
+                    output.tokens.push(token);
+                }
+                output.examine(end);
+                if let Some(mark) = success {
+                    let depth = mark.depth.saturating_add(depth);
+                    output.mark(pos + mark.begin, pos + mark.end, depth, mark.matcher);
+                }
+                return range;
+            }
+        } else if let Some(entry) = output.memo.get(&key) {
+            let range = entry.range.clone();
+            let success = entry.success;
+            for shared in entry.tokens.clone() {
+                let token = output.shared_token(shared, alloc);
+                output.tokens.push(token);
+            }
+            if let Some(mark) = success {
+                let depth = mark.depth.saturating_add(depth);
+                output.mark(mark.begin, mark.end, depth, mark.matcher);
+            }
+            if let Some(stats) = &mut output.stats {
+                stats.memo_hit(self.id);
+            }
+            return range;
         }
-        if matched_chars == to_match.len() {
-            let range = pos..pos + to_match.len();
-            self.push_token(output, self.create_token(source, range.clone(), alloc));
-            Some(range)
+        let output_start = output.len();
+        let cuts = output.cuts;
+        let seed_uses = output.seed_uses.len();
+        let outer_examined = std::mem::replace(&mut output.examined, pos);
+        output.memo_marks.push(None);
+        let range = self.apply_unmemoized(source.clone(), output, matchers, pos, depth, alloc);
+        let success = output.memo_marks.pop().flatten();
+        let examined = output.examined;
+        output.examine(outer_examined);
+        if let Some(mark) = success {
+            if let Some(frame) = output.memo_marks.last_mut() {
+                frame
+                    .get_or_insert_default()
+                    .mark(mark.begin, mark.end, mark.depth, mark.matcher);
+            }
+        }
+        // Passing a cut can throw away seed uses, so it's checked for first
+        if output.cuts != cuts
+            || output.seed_uses[seed_uses..]
+                .iter()
+                .any(|key| output.seeds.contains_key(key))
+        {
+            return range;
+        }
+        output.seed_uses.truncate(seed_uses);
+        let success = success.map(|mark| SuccessMark {
+            depth: match mark.depth {
+                usize::MAX => usize::MAX,
+                mark_depth => mark_depth.saturating_sub(depth),
+            },
+            ..mark
+        });
+        // The tokens are moved into the memo table and the output gets tokens standing in
+        // for them, so neither storing nor reusing the entry copies them
+        if output.reuse.is_some() {
+            let nodes: Vec<_> = output
+                .tokens
+                .drain(output_start..)
+                .map(|t| (t.range.start - pos, Node::new(&t, &output.reused)))
+                .collect();
+            for (offset, node) in nodes.iter().cloned() {
+                let token = output.reused_token(&source, pos + offset, node, alloc);
+                output.tokens.push(token);
+            }
+            let entry = ReuseEntry {
+                len: range.as_ref().map(|range| range.len()),
+                examined: examined - pos,
+                nodes,
+                success: success.map(|mark| SuccessMark {
+                    begin: mark.begin.saturating_sub(pos),
+                    end: mark.end.saturating_sub(pos),
+                    ..mark
+                }),
+            };
+            output.reuse.as_mut().unwrap().insert(key, entry);
         } else {
-            None
+            let shared_start = output.memo_tokens.len();
+            output
+                .memo_tokens
+                .extend(output.tokens.drain(output_start..));
+            for shared in shared_start..output.memo_tokens.len() {
+                let token = output.shared_token(shared, alloc);
+                output.tokens.push(token);
+            }
+            let entry = MemoEntry {
+                range: range.clone(),
+                tokens: shared_start..output.memo_tokens.len(),
+                success,
+            };
+            output.memo.insert(key, entry);
         }
+        range
     }
 
-    fn apply_char_set<'a>(
+    /// Grows the match of a left-recursive matcher. Applying it again at the same position
+    /// gives the match from the previous attempt, starting with a failure, so every attempt
+    /// can extend the last one. This stops once an attempt doesn't get any longer, which
+    /// leaves left-recursive rules nested left-associatively.
+    fn apply_left_recursive<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
+        matchers: &[Matcher],
         pos: usize,
+        depth: usize,
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        let key = (self.id, pos);
+        if let Some(seed) = output.seeds.get(&key) {
+            if !output.memo_marks.is_empty() {
+                output.seed_uses.push(key);
+            }
+            let (range, tokens) = seed.clone()?;
+            output.tokens.extend(tokens);
+            return Some(range);
+        }
+        output.seeds.insert(key, None);
+        let output_start = output.len();
+        let mut end = None;
+        loop {
+            let outer_cut = output.begin_attempt();
+            output.rewinding += 1;
+            let matched =
+                self.apply_matcher_type(source.clone(), output, matchers, pos, depth, alloc);
+            output.rewinding -= 1;
+            let tokens: Vec<Token<'a>> = output.tokens.drain(output_start..).collect();
+            if !output.end_attempt(outer_cut, matched.is_some()) {
+                output.seeds.remove(&key);
+                return None;
+            }
+            match matched {
+                Some(range) if end.is_none_or(|end| range.end > end) => {
+                    end = Some(range.end);
+                    output.seeds.insert(key, Some((range, tokens)));
+                }
+                _ => break,
+            }
+        }
+        let (range, tokens) = output.seeds.remove(&key).flatten()?;
+        output.tokens.extend(tokens);
+        Some(range)
+    }
+
+    fn apply_string<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
         to_match: &[char],
-        inverted: bool,
+        case_sensitive: bool,
         alloc: &'a Bump,
     ) -> TokenResult {
-        match source.get(pos) {
-            Some(c) if to_match.contains(c) ^ inverted => {
-                let range = pos..pos + 1;
-                self.push_token(output, self.create_token(source, range.clone(), alloc));
-                Some(range)
+        let mut matched_chars = 0;
+        let mut end = pos;
+        for (expected, c) in to_match.iter().zip(source[pos..].chars()) {
+            if !char_matches(expected, &c, case_sensitive) {
+                break;
             }
-            _ => None,
+            matched_chars += 1;
+            end += c.len_utf8();
+        }
+
+        if matched_chars > 0 {
+            output.mark_success(pos, end, depth, self);
+        }
+        if matched_chars == to_match.len() {
+            output.examine(end);
+            let range = pos..end;
+            self.push_token(output, self.create_token(source, range.clone(), alloc));
+            Some(range)
+        } else {
+            output.examine(end + 1);
+            None
         }
     }
 
-    fn apply_char_range<'a>(
+    fn apply_char_class<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         pos: usize,
-        range: &RangeInclusive<char>,
-        inverted: bool,
+        class: &CharClass,
         alloc: &'a Bump,
     ) -> TokenResult {
-        match source.get(pos) {
-            Some(c) if range.contains(c) ^ inverted => {
-                let range = pos..pos + 1;
+        output.examine(pos + 1);
+        match source[pos..].chars().next() {
+            Some(c) if class.contains(c) => {
+                let range = pos..pos + c.len_utf8();
                 self.push_token(output, self.create_token(source, range.clone(), alloc));
                 Some(range)
             }
@@ -349,7 +1117,7 @@ impl Matcher {
 
     fn apply_list<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         pos: usize,
         depth: usize,
@@ -386,7 +1154,7 @@ impl Matcher {
 
     fn apply_choice<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         pos: usize,
         depth: usize,
@@ -398,6 +1166,7 @@ impl Matcher {
         output.mark_success(pos, pos, depth, self);
         for child in children {
             let child = &matchers[*child];
+            let outer_cut = output.begin_attempt();
             let matched = child.apply(
                 source.clone(),
                 output,
@@ -406,6 +1175,9 @@ impl Matcher {
                 next_depth(self, depth),
                 alloc,
             );
+            if !output.end_attempt(outer_cut, matched.is_some()) {
+                return None;
+            }
             if let Some(range) = matched {
                 self.process_children(source, range.clone(), output, output_start, alloc);
                 return Some(range);
@@ -416,7 +1188,7 @@ impl Matcher {
 
     fn apply_repeating<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         pos: usize,
         depth: usize,
@@ -431,14 +1203,20 @@ impl Matcher {
         let mut cursor = pos;
         let mut child_count = 0;
         while child_count < *range.end() {
-            if source
-                .get(pos)
-                .filter(|c| c.is_ascii())
-                .and_then(|c| cache.as_ref().map(|cache| cache[*c as u32 as usize]))
-                == Some(false)
-            {
+            output.examine(cursor + 1);
+            let cached = source
+                .as_bytes()
+                .get(cursor)
+                .filter(|b| b.is_ascii() && output.caching(cursor))
+                .and_then(|b| cache.as_ref().map(|cache| cache[*b as usize]));
+            if let (Some(profile), Some(cached)) = (&mut output.profile, cached) {
+                profile.cache_lookup(self.id, usize::from(!cached));
+            }
+            if cached == Some(false) {
                 break;
             }
+            let reached = std::mem::take(&mut output.reached);
+            let outer_cut = output.begin_attempt();
             let matched = child.apply(
                 source.clone(),
                 output,
@@ -447,6 +1225,12 @@ impl Matcher {
                 next_depth(self, depth),
                 alloc,
             );
+            let child_reached = std::mem::replace(&mut output.reached, reached);
+            output.reached = output.reached.max(child_reached);
+            if !output.end_attempt(outer_cut, matched.is_some()) {
+                output.tokens.truncate(output_start);
+                return None;
+            }
             match matched {
                 Some(child_token) => {
                     cursor = child_token.end;
@@ -456,7 +1240,13 @@ impl Matcher {
                         break;
                     }
                 }
-                None => break,
+                None => match skip_error(&source, output, cursor, child_reached, alloc) {
+                    Some(end) => {
+                        cursor = end;
+                        child_count += 1;
+                    }
+                    None => break,
+                },
             }
         }
 
@@ -470,13 +1260,40 @@ impl Matcher {
         }
     }
 
-    fn apply_eof(source: Arc<[char]>, pos: usize) -> TokenResult {
-        (pos == source.len()).then(|| pos..pos)
+    fn apply_cut(output: &mut TokenOutput, pos: usize) -> TokenResult {
+        output.cut = true;
+        output.cuts += 1;
+        // Unless something can still go back past the cut, nothing will be matched before it
+        // again. Seeds only exist while something is rewinding, so none are left to drop.
+        if output.rewinding == 0 {
+            output.memo.retain(|(_, memo_pos), _| *memo_pos >= pos);
+            output.seed_uses.clear();
+        }
+        // Nothing before the cut will be retried, so errors are reported from here on
+        if output.last_success.end > pos {
+            output.last_success = SuccessMark {
+                begin: pos,
+                end: pos,
+                ..Default::default()
+            };
+        }
+        if let Some(expected) = output.expected.as_mut().filter(|e| e.pos > pos) {
+            *expected = Expected {
+                pos,
+                matchers: Vec::new(),
+            };
+        }
+        Some(pos..pos)
+    }
+
+    fn apply_eof(output: &mut TokenOutput, source: Arc<str>, pos: usize) -> TokenResult {
+        output.examine(pos + 1);
+        (pos == source.len()).then_some(pos..pos)
     }
 
     fn apply_inverted<'a>(
         &self,
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         pos: usize,
         depth: usize,
@@ -485,6 +1302,12 @@ impl Matcher {
         alloc: &'a Bump,
     ) -> TokenResult {
         let child = &matchers[child];
+        // Cuts can't commit to anything from inside an assertion, and the child failing
+        // isn't an error
+        let outer_cut = output.begin_attempt();
+        let output_start = output.len();
+        output.rewinding += 1;
+        let outer_expected = output.expected.take();
         let matched = child.apply(
             source.clone(),
             output,
@@ -493,12 +1316,13 @@ impl Matcher {
             next_depth(self, depth),
             alloc,
         );
-        let output_start = output.len();
+        output.rewinding -= 1;
+        output.cut = outer_cut;
+        // Nothing the child matched is kept, whether or not it succeeded
+        output.tokens.truncate(output_start);
+        output.expected = outer_expected;
         match matched {
-            Some(_) => {
-                output.tokens.truncate(output_start);
-                None
-            }
+            Some(_) => None,
             None => {
                 let range = pos..pos;
                 self.push_token(output, self.create_token(source, range.clone(), alloc));
@@ -507,16 +1331,420 @@ impl Matcher {
         }
     }
 
+    fn apply_lookahead<'a>(
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        child: usize,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        let child = &matchers[child];
+        let output_start = output.len();
+        let last_success = output.last_success;
+        let memo_mark = output.memo_marks.last().copied().flatten();
+        let outer_expected = output.expected.as_mut().map(std::mem::take);
+        let outer_cut = output.begin_attempt();
+        output.rewinding += 1;
+        let matched = child.apply(source, output, matchers, pos, depth, alloc);
+        output.rewinding -= 1;
+        output.cut = outer_cut;
+        output.tokens.truncate(output_start);
+        // What the child expected is only kept if it failed, since it isn't consumed
+        if let (Some(expected), Some(outer)) = (&mut output.expected, outer_expected) {
+            let inner = std::mem::replace(expected, outer);
+            if matched.is_none() {
+                expected.merge(inner);
+            }
+        }
+        // The input checked by the lookahead hasn't been consumed yet, so it isn't progress
+        matched.map(|_| {
+            output.last_success = last_success;
+            if let Some(frame) = output.memo_marks.last_mut() {
+                *frame = memo_mark;
+            }
+            pos..pos
+        })
+    }
+
+    /// Labels the tokens produced by the child. Its cull strategy is ignored, so the label
+    /// always ends up on exactly one token covering the whole match.
+    fn apply_labeled<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        label: &Arc<str>,
+        child: usize,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        let output_start = output.len();
+        let range = matchers[child].apply(source.clone(), output, matchers, pos, depth, alloc)?;
+        self.label_tokens(source, range.clone(), output, output_start, label, alloc);
+        Some(range)
+    }
+
+    /// Label the tokens in the output after `start`, which matched `range`: the token itself
+    /// if there's just one covering the whole range, or else a new unnamed token wrapping them
+    fn label_tokens<'a>(
+        &self,
+        source: Arc<str>,
+        range: Range<usize>,
+        output: &mut TokenOutput<'a>,
+        start: usize,
+        label: &Arc<str>,
+        alloc: &'a Bump,
+    ) {
+        let reuse_token = output.len() - start == 1
+            && output.tokens[start].range == range
+            && output.tokens[start].label.is_none();
+        if !reuse_token {
+            let mut children =
+                bumpalo::collections::Vec::with_capacity_in(output.len() - start, alloc);
+            children.extend(output.tokens.drain(start..));
+            output.tokens.push(Token {
+                matcher_name: None.into(),
+                matcher_id: self.id,
+                children,
+                label: Some(label.clone()),
+                source,
+                range,
+                shared: None,
+            });
+        } else if let Some(token) = output.tokens.last_mut() {
+            token.label = Some(label.clone());
+        }
+    }
+
+    fn apply_precedence<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        table: &OperatorTable,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> TokenResult {
+        let output_start = output.len();
+        let depth = next_depth(self, depth);
+        let (range, operations) = self.apply_operation(
+            source.clone(),
+            output,
+            pos,
+            depth,
+            table,
+            Some(0),
+            matchers,
+            alloc,
+        )?;
+        // Operations always make their own tokens, so the cull strategy only applies to an
+        // operand matched on its own
+        if !operations {
+            self.process_children(source, range.clone(), output, output_start, alloc);
+        } else if let CullStrategy::DeleteAll = self.cull_strategy {
+            output.tokens.truncate(output_start);
+        }
+        Some(range)
+    }
+
+    /// Match an operand, along with every operator after it binding at least as tightly as
+    /// `min_precedence`, or none at all if it's `None`. Returns the range matched and whether
+    /// any operator was matched.
+    fn apply_operation<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        table: &OperatorTable,
+        min_precedence: Option<usize>,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> Option<(Range<usize>, bool)> {
+        let lhs_start = output.len();
+        let mut operations = true;
+        let outer_cut = output.begin_attempt();
+        let prefix_operation =
+            self.apply_prefix_operation(source.clone(), output, pos, depth, table, matchers, alloc);
+        if !output.end_attempt(outer_cut, prefix_operation.is_some()) {
+            return None;
+        }
+        let mut cursor = match prefix_operation {
+            Some(end) => end,
+            None => {
+                operations = false;
+                let operand = &matchers[table.operand];
+                operand
+                    .apply(source.clone(), output, matchers, pos, depth, alloc)?
+                    .end
+            }
+        };
+        let mut precedence = min_precedence.map(|min| min..=usize::MAX);
+        while let Some(range) = precedence.clone() {
+            let outer_cut = output.begin_attempt();
+            let op_start = output.len();
+            let op_pos = self.skip_separator(
+                source.clone(),
+                output,
+                cursor,
+                depth,
+                table,
+                matchers,
+                alloc,
+            );
+            let Some((operator, op_end)) = self.apply_operator(
+                source.clone(),
+                output,
+                op_pos,
+                depth,
+                table,
+                false,
+                range.clone(),
+                matchers,
+                alloc,
+            ) else {
+                if !output.end_attempt(outer_cut, false) {
+                    return None;
+                }
+                break;
+            };
+            let mut parts = vec![
+                (lhs_start, pos..cursor, &table.labels[0]),
+                (op_start, op_pos..op_end, &table.labels[1]),
+            ];
+            let end = match operator.fixity {
+                Fixity::Infix(associativity) => {
+                    let rhs_start = output.len();
+                    let rhs_pos = self.skip_separator(
+                        source.clone(),
+                        output,
+                        op_end,
+                        depth,
+                        table,
+                        matchers,
+                        alloc,
+                    );
+                    let rhs_precedence = match associativity {
+                        Associativity::Right => Some(operator.precedence),
+                        Associativity::Left | Associativity::None => {
+                            operator.precedence.checked_add(1)
+                        }
+                    };
+                    let Some((rhs, _)) = self.apply_operation(
+                        source.clone(),
+                        output,
+                        rhs_pos,
+                        depth,
+                        table,
+                        rhs_precedence,
+                        matchers,
+                        alloc,
+                    ) else {
+                        output.tokens.truncate(op_start);
+                        if !output.end_attempt(outer_cut, false) {
+                            return None;
+                        }
+                        break;
+                    };
+                    if associativity == Associativity::None {
+                        // Nothing of the same precedence can follow, and nothing at all if
+                        // there's no lower precedence
+                        precedence = operator
+                            .precedence
+                            .checked_sub(1)
+                            .map(|max| *range.start()..=max);
+                    }
+                    parts.push((rhs_start, rhs.clone(), &table.labels[2]));
+                    rhs.end
+                }
+                _ => op_end,
+            };
+            output.end_attempt(outer_cut, true);
+            self.create_operation(source.clone(), pos..end, output, &parts, alloc);
+            cursor = end;
+            operations = true;
+            output.mark_success(pos, cursor, depth, self);
+        }
+        Some((pos..cursor, operations))
+    }
+
+    /// Match a prefix operator and its operand, returning the end of the operand
+    fn apply_prefix_operation<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        table: &OperatorTable,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> Option<usize> {
+        let op_start = output.len();
+        let (operator, op_end) = self.apply_operator(
+            source.clone(),
+            output,
+            pos,
+            depth,
+            table,
+            true,
+            0..=usize::MAX,
+            matchers,
+            alloc,
+        )?;
+        let rhs_start = output.len();
+        let rhs_pos = self.skip_separator(
+            source.clone(),
+            output,
+            op_end,
+            depth,
+            table,
+            matchers,
+            alloc,
+        );
+        let Some((rhs, _)) = self.apply_operation(
+            source.clone(),
+            output,
+            rhs_pos,
+            depth,
+            table,
+            Some(operator.precedence),
+            matchers,
+            alloc,
+        ) else {
+            output.tokens.truncate(op_start);
+            return None;
+        };
+        let parts = [
+            (op_start, pos..op_end, &table.labels[1]),
+            (rhs_start, rhs.clone(), &table.labels[2]),
+        ];
+        self.create_operation(source, pos..rhs.end, output, &parts, alloc);
+        Some(rhs.end)
+    }
+
+    /// Match the longest prefix, or infix or postfix, operator at `pos` with a precedence in
+    /// `precedence`, leaving just its tokens in the output
+    fn apply_operator<'a, 't>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        table: &'t OperatorTable,
+        prefix: bool,
+        precedence: RangeInclusive<usize>,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> Option<(&'t Operator, usize)> {
+        let cache = match prefix {
+            true => &table.prefix_cache,
+            false => &table.suffix_cache,
+        };
+        let all: Vec<usize>;
+        output.examine(pos + 1);
+        let candidates = match source
+            .as_bytes()
+            .get(pos)
+            .filter(|b| b.is_ascii() && output.caching(pos))
+            .and_then(|b| cache.as_ref().map(|cache| &cache[*b as usize]))
+        {
+            Some(candidates) => candidates,
+            None => {
+                all = match prefix {
+                    true => table.prefix().collect(),
+                    false => table.suffix().collect(),
+                };
+                &all
+            }
+        };
+        let output_start = output.len();
+        let mut longest: Option<(&Operator, usize, Vec<Token<'a>>)> = None;
+        for operator in candidates.iter().map(|i| &table.operators[*i]) {
+            if !precedence.contains(&operator.precedence) {
+                continue;
+            }
+            let matcher = &matchers[operator.matcher];
+            let outer_cut = output.begin_attempt();
+            // Every operator is tried, even after one passes a cut
+            output.rewinding += 1;
+            let matched = matcher.apply(source.clone(), output, matchers, pos, depth, alloc);
+            output.rewinding -= 1;
+            let tokens: Vec<Token<'a>> = output.tokens.drain(output_start..).collect();
+            if !output.end_attempt(outer_cut, matched.is_some()) {
+                return None;
+            }
+            // Operators have to consume something, or they could be matched endlessly
+            match matched {
+                Some(range)
+                    if range.end > pos
+                        && longest.as_ref().is_none_or(|(_, end, _)| range.end > *end) =>
+                {
+                    longest = Some((operator, range.end, tokens));
+                }
+                _ => (),
+            }
+        }
+        let (operator, end, tokens) = longest?;
+        output.tokens.extend(tokens);
+        Some((operator, end))
+    }
+
+    /// Apply the separator of an operator table, discarding its tokens, and return the
+    /// position after it
+    fn skip_separator<'a>(
+        &self,
+        source: Arc<str>,
+        output: &mut TokenOutput<'a>,
+        pos: usize,
+        depth: usize,
+        table: &OperatorTable,
+        matchers: &[Matcher],
+        alloc: &'a Bump,
+    ) -> usize {
+        let Some(separator) = table.separator else {
+            return pos;
+        };
+        let output_start = output.len();
+        let matched = matchers[separator].apply(source, output, matchers, pos, depth, alloc);
+        output.tokens.truncate(output_start);
+        matched.map_or(pos, |range| range.end)
+    }
+
+    /// Turn the tokens of each part of an operation into a single token labeled with the
+    /// part's role, and wrap them in a token named after the rule. Parts are given as the
+    /// index their tokens start at in the output, their range and their label.
+    fn create_operation<'a>(
+        &self,
+        source: Arc<str>,
+        range: Range<usize>,
+        output: &mut TokenOutput<'a>,
+        parts: &[(usize, Range<usize>, &Arc<str>)],
+        alloc: &'a Bump,
+    ) {
+        let mut labeled = Vec::with_capacity(parts.len());
+        for (start, range, label) in parts.iter().rev() {
+            self.label_tokens(source.clone(), range.clone(), output, *start, label, alloc);
+            labeled.extend(output.tokens.pop());
+        }
+        output.tokens.extend(labeled.into_iter().rev());
+        self.create_parent(source, range, &mut output.tokens, parts[0].0, alloc);
+    }
+
     fn apply_newline<'a>(
         &self,
         output: &mut TokenOutput<'a>,
-        source: Arc<[char]>,
+        source: Arc<str>,
         pos: usize,
         alloc: &'a Bump,
     ) -> TokenResult {
-        match source.get(pos) {
-            Some('\r') => {
-                let range = if let Some('\n') = source.get(pos + 1) {
+        output.examine(pos + 2);
+        match source.as_bytes().get(pos) {
+            Some(b'\r') => {
+                let range = if let Some(b'\n') = source.as_bytes().get(pos + 1) {
                     pos..pos + 2
                 } else {
                     pos..pos + 1
@@ -524,7 +1752,7 @@ impl Matcher {
                 self.push_token(output, self.create_token(source, range.clone(), alloc));
                 Some(range)
             }
-            Some('\n') => {
+            Some(b'\n') => {
                 let range = pos..pos + 1;
                 self.push_token(output, self.create_token(source, range.clone(), alloc));
                 Some(range)
@@ -534,7 +1762,7 @@ impl Matcher {
     }
 
     fn apply_wrapper<'a>(
-        source: Arc<[char]>,
+        source: Arc<str>,
         output: &mut TokenOutput<'a>,
         pos: usize,
         depth: usize,
@@ -0,0 +1,133 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::matchers::{Matcher, SuccessMark, TokenOutput, TokenResult};
+use crate::tokens::Token;
+
+/// The result of applying a memoized matcher at some position
+pub(crate) struct MemoEntry {
+    pub(crate) range: TokenResult,
+    /// Where the tokens of the match are in `TokenOutput::memo_tokens`
+    pub(crate) tokens: Range<usize>,
+    /// The furthest the matcher got, to report errors from, with its depth relative to the
+    /// matcher's own
+    pub(crate) success: Option<SuccessMark>,
+}
+
+/// Give `token` and every token under it that stands in for one in the memo table the
+/// children of the token in `memo_tokens` it stands in for. Only the tokens that end up in the
+/// result are copied, once each.
+pub(crate) fn expand_shared<'a>(memo_tokens: &[Token<'a>], token: &mut Token<'a>) {
+    while let Some(shared) = token.shared.take() {
+        // The memoized token can stand in for another one itself
+        token.children = memo_tokens[shared].children.clone();
+        token.shared = memo_tokens[shared].shared;
+    }
+    for child in token.children.iter_mut() {
+        expand_shared(memo_tokens, child);
+    }
+}
+
+/// Counts of how each named rule was applied while collecting `MemoStats`
+#[derive(Default)]
+pub(crate) struct StatsCollector {
+    rules: HashMap<usize, Applications>,
+}
+
+#[derive(Default)]
+struct Applications {
+    count: usize,
+    positions: HashSet<usize>,
+    memo_hits: usize,
+}
+
+impl StatsCollector {
+    pub(crate) fn applied(&mut self, matcher: usize, pos: usize) {
+        let applications = self.rules.entry(matcher).or_default();
+        applications.count += 1;
+        applications.positions.insert(pos);
+    }
+
+    pub(crate) fn memo_hit(&mut self, matcher: usize) {
+        self.rules.entry(matcher).or_default().memo_hits += 1;
+    }
+}
+
+/// How often the rules of a grammar were applied while matching some input, and how much
+/// memory memoizing them took, from `Lexer::memo_stats`
+#[derive(Debug, Clone, Default)]
+pub struct MemoStats {
+    /// Every named rule that was applied, in the order they were defined
+    pub rules: Vec<RuleStats>,
+}
+
+/// How one named rule was applied, in `MemoStats`
+#[derive(Debug, Clone)]
+pub struct RuleStats {
+    pub name: String,
+    pub memoized: bool,
+    /// How many times the rule was applied, including the times its result was taken from
+    /// the memo table
+    pub applications: usize,
+    /// How many different positions the rule was applied at. Any applications past this
+    /// matched the rule again somewhere it already had, which memoizing it would avoid.
+    pub positions: usize,
+    pub memo_hits: usize,
+    pub memo_entries: usize,
+    /// Roughly how much memory the rule's memo entries took, in bytes
+    pub memo_bytes: usize,
+}
+
+impl MemoStats {
+    pub(crate) fn new(output: &TokenOutput, matchers: &[Matcher]) -> Self {
+        let Some(collector) = &output.stats else {
+            return Self::default();
+        };
+        let mut entries: HashMap<usize, (usize, usize)> = HashMap::new();
+        for ((matcher, _), entry) in &output.memo {
+            let (count, bytes) = entries.entry(*matcher).or_default();
+            *count += 1;
+            *bytes += size_of::<((usize, usize), MemoEntry)>()
+                + output.memo_tokens[entry.tokens.clone()]
+                    .iter()
+                    .map(token_bytes)
+                    .sum::<usize>();
+        }
+        let mut rules: Vec<_> = collector.rules.iter().collect();
+        rules.sort_by_key(|(matcher, _)| **matcher);
+        let rules = rules
+            .into_iter()
+            .filter_map(|(matcher, applications)| {
+                let name = matchers[*matcher].name.as_deref()?;
+                let (memo_entries, memo_bytes) = entries.get(matcher).copied().unwrap_or_default();
+                Some(RuleStats {
+                    name: name.to_string(),
+                    memoized: matchers[*matcher].memoize,
+                    applications: applications.count,
+                    positions: applications.positions.len(),
+                    memo_hits: applications.memo_hits,
+                    memo_entries,
+                    memo_bytes,
+                })
+            })
+            .collect();
+        MemoStats { rules }
+    }
+
+    /// Get the stats of the rule called `name`, if it was applied
+    pub fn rule(&self, name: &str) -> Option<&RuleStats> {
+        self.rules.iter().find(|rule| rule.name == name)
+    }
+
+    pub fn memo_entries(&self) -> usize {
+        self.rules.iter().map(|rule| rule.memo_entries).sum()
+    }
+
+    pub fn memo_bytes(&self) -> usize {
+        self.rules.iter().map(|rule| rule.memo_bytes).sum()
+    }
+}
+
+fn token_bytes(token: &Token) -> usize {
+    size_of::<Token>() + token.children.iter().map(token_bytes).sum::<usize>()
+}
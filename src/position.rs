@@ -0,0 +1,89 @@
+//! Positions in the input are byte offsets into it. These convert them to char offsets, and
+//! to lines and columns for showing to people.
+
+use std::sync::Arc;
+
+/// A line and column in some text, both counted from 1. Columns are counted in chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The start of every line in a text, to find the line and column of many positions in it
+/// without scanning the text from the start each time
+#[derive(Debug, Clone)]
+pub struct LineIndex<'s> {
+    text: &'s str,
+    line_starts: Vec<usize>,
+}
+
+impl<'s> LineIndex<'s> {
+    pub fn new(text: &'s str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { text, line_starts }
+    }
+
+    /// Get the line and column of the byte offset `pos`
+    pub fn line_col(&self, pos: usize) -> LineCol {
+        let pos = floor_char_boundary(self.text, pos);
+        let line = self.line_starts.partition_point(|start| *start <= pos);
+        let line_start = self.line_starts[line - 1];
+        LineCol {
+            line,
+            column: self.text[line_start..pos].chars().count() + 1,
+        }
+    }
+
+    /// Get the byte offset of a line and column, if the text has them. The column just past
+    /// the end of a line is its line break.
+    pub fn offset(&self, line_col: LineCol) -> Option<usize> {
+        let line_start = *self.line_starts.get(line_col.line.checked_sub(1)?)?;
+        let line_end = self
+            .line_starts
+            .get(line_col.line)
+            .map_or(self.text.len(), |next| next - 1);
+        let line = &self.text[line_start..line_end];
+        let column = line_col.column.checked_sub(1)?;
+        char_to_byte(line, column).map(|pos| line_start + pos)
+    }
+}
+
+/// Get the line and column of the byte offset `pos` in `text`
+pub fn line_col(text: &str, pos: usize) -> LineCol {
+    LineIndex::new(text).line_col(pos)
+}
+
+/// Count the chars in `text` before the byte offset `pos`
+pub fn byte_to_char(text: &str, pos: usize) -> usize {
+    text[..floor_char_boundary(text, pos)].chars().count()
+}
+
+/// Get the byte offset of the char at offset `pos` in `text`, or the length of `text` if
+/// `pos` is just past its last char
+pub fn char_to_byte(text: &str, pos: usize) -> Option<usize> {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .nth(pos)
+}
+
+fn floor_char_boundary(text: &str, pos: usize) -> usize {
+    let mut pos = pos.min(text.len());
+    while !text.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
+/// Turn the text and char offset of a position in a grammar, which is parsed as chars, into
+/// a text and byte offset like the positions in the input
+pub(crate) fn from_chars(source: &[char], pos: usize) -> (Arc<str>, usize) {
+    let pos = source[..pos.min(source.len())]
+        .iter()
+        .map(|c| c.len_utf8())
+        .sum();
+    (source.iter().collect::<String>().into(), pos)
+}
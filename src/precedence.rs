@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+/// How an operator combines with its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    /// Before its operand, like `-x`
+    Prefix,
+    /// Between two operands, like `x + y`
+    Infix(Associativity),
+    /// After its operand, like `x?`
+    Postfix,
+}
+
+/// How a chain of infix operators with the same precedence is grouped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    /// `a - b - c` is grouped as `(a - b) - c`
+    Left,
+    /// `a ^ b ^ c` is grouped as `a ^ (b ^ c)`
+    Right,
+    /// `a == b == c` doesn't match
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub(crate) matcher: usize,
+    /// Operators with a higher precedence bind tighter, starting from 1
+    pub(crate) precedence: usize,
+    pub(crate) fixity: Fixity,
+}
+
+/// The operand and operators of a `@precedence` rule, along with caches of which operators
+/// can start with each ASCII character
+#[derive(Debug, Clone)]
+pub struct OperatorTable {
+    pub(crate) operand: usize,
+    pub(crate) separator: Option<usize>,
+    pub(crate) operators: Vec<Operator>,
+    /// The labels given to the left operand, operator and right operand of an operation
+    pub(crate) labels: [Arc<str>; 3],
+    pub(crate) prefix_cache: Option<Box<[Vec<usize>; 256]>>,
+    pub(crate) suffix_cache: Option<Box<[Vec<usize>; 256]>>,
+}
+
+impl OperatorTable {
+    pub(crate) fn new(operand: usize, separator: Option<usize>, operators: Vec<Operator>) -> Self {
+        OperatorTable {
+            operand,
+            separator,
+            operators,
+            labels: ["lhs", "op", "rhs"].map(Arc::from),
+            prefix_cache: None,
+            suffix_cache: None,
+        }
+    }
+
+    /// Get the operators that go before an operand
+    pub(crate) fn prefix(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.operators.len()).filter(|i| self.operators[*i].fixity == Fixity::Prefix)
+    }
+
+    /// Get the operators that go after an operand, being every infix and postfix operator
+    pub(crate) fn suffix(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.operators.len()).filter(|i| self.operators[*i].fixity != Fixity::Prefix)
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Formatter, Write};
+
+use crate::char_class::CharClass;
+use crate::lexer::CullStrategy;
+use crate::matchers::{Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity};
+
+/// How tightly an expression binds, deciding where parentheses are needed
+const CHOICE: u8 = 1;
+const LIST: u8 = 2;
+const PREFIX: u8 = 3;
+const POSTFIX: u8 = 4;
+const ATOM: u8 = 5;
+
+/// Write out matchers as a grammar which parses back into the same matchers. Rules keep
+/// their names, including the dots in the names of imported rules. Only names BNF can't
+/// have, like ABNF's `dec-octet`, are changed, with underscores for the characters it can't
+/// have and as many more added to the end as it takes not to clash with any other rule.
+pub(crate) fn write_grammar(f: &mut Formatter, root: usize, matchers: &[Matcher]) -> fmt::Result {
+    let printer = Printer::new(matchers);
+    if let Some(annotation) = printer.unnamed_annotation() {
+        writeln!(f, "@unnamed {annotation}")?;
+        writeln!(f)?;
+    }
+    if printer.names.get(&root).map(String::as_str) != Some("root") {
+        writeln!(f, "root ::= {}", printer.names[&root])?;
+    }
+    for matcher in matchers.iter().filter(|m| m.name.is_some()) {
+        printer.write_rule(f, matcher)?;
+    }
+    Ok(())
+}
+
+/// Describe every matcher for tracing, as the name of a rule or the BNF of an unnamed
+/// matcher
+pub(crate) fn describe_all(matchers: &[Matcher]) -> Vec<String> {
+    struct Expr<'p>(&'p Printer<'p>, usize);
+
+    impl fmt::Display for Expr<'_> {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            self.0.write_expr(f, self.1, CHOICE)
+        }
+    }
+
+    let printer = Printer::new(matchers);
+    (0..matchers.len())
+        .map(|id| Expr(&printer, id).to_string())
+        .collect()
+}
+
+struct Printer<'m> {
+    matchers: &'m [Matcher],
+    names: HashMap<usize, String>,
+}
+
+impl<'m> Printer<'m> {
+    fn new(matchers: &'m [Matcher]) -> Self {
+        let mut used: HashSet<String> = matchers
+            .iter()
+            .filter_map(|m| m.name.as_ref().as_ref())
+            .filter(|name| is_bnf_name(name))
+            .cloned()
+            .collect();
+        let mut names = HashMap::new();
+        for matcher in matchers {
+            if let Some(name) = &*matcher.name {
+                if is_bnf_name(name) {
+                    names.insert(matcher.id, name.clone());
+                    continue;
+                }
+                let mut name = name.replace(|c: char| !c.is_alphabetic() && c != '_', "_");
+                while used.contains(&name) {
+                    name.push('_');
+                }
+                used.insert(name.clone());
+                names.insert(matcher.id, name);
+            }
+        }
+        Printer { matchers, names }
+    }
+
+    /// Get the annotation for the cull strategy most unnamed matchers have, since only one
+    /// can be given for all of them
+    fn unnamed_annotation(&self) -> Option<String> {
+        let mut counts: Vec<(Option<String>, usize)> = Vec::new();
+        for matcher in self.matchers.iter().filter(|m| m.name.is_none()) {
+            let annotation = annotation(matcher.cull_strategy);
+            match counts.iter_mut().find(|(a, _)| *a == annotation) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((annotation, 1)),
+            }
+        }
+        counts.into_iter().max_by_key(|(_, count)| *count)?.0
+    }
+
+    fn write_rule(&self, f: &mut Formatter, matcher: &Matcher) -> fmt::Result {
+        if matcher.memoize {
+            f.write_str("@memo ")?;
+        }
+        if let Some(sync) = matcher.recover {
+            f.write_str("@recover(")?;
+            self.write_expr(f, sync, PREFIX)?;
+            f.write_str(") ")?;
+        }
+        if let Some(annotation) = annotation(matcher.cull_strategy) {
+            write!(f, "{annotation} ")?;
+        }
+        f.write_str(&self.names[&matcher.id])?;
+        if !matcher.show_in_errors {
+            f.write_char('!')?;
+        }
+        f.write_str(" ::= ")?;
+        self.write_matcher(f, matcher)?;
+        writeln!(f)
+    }
+
+    fn level(&self, id: usize) -> u8 {
+        let matcher = &self.matchers[id];
+        if matcher.name.is_some() {
+            return ATOM;
+        }
+        match &matcher.matcher_type {
+            MatcherType::Choice(_, _) => CHOICE,
+            MatcherType::List(_) => LIST,
+            MatcherType::Inverted(_) | MatcherType::Lookahead(_) | MatcherType::Labeled(_, _) => {
+                PREFIX
+            }
+            MatcherType::Repeating(_, _, _) => POSTFIX,
+            MatcherType::Wrapper(child) => self.level(*child),
+            _ => ATOM,
+        }
+    }
+
+    /// Write a reference to a rule, or an unnamed matcher inline, in parentheses if it
+    /// binds looser than `min_level`
+    fn write_expr(&self, f: &mut Formatter, id: usize, min_level: u8) -> fmt::Result {
+        if let Some(name) = self.names.get(&id) {
+            return f.write_str(name);
+        }
+        if self.level(id) < min_level {
+            f.write_char('(')?;
+            self.write_matcher(f, &self.matchers[id])?;
+            f.write_char(')')
+        } else {
+            self.write_matcher(f, &self.matchers[id])
+        }
+    }
+
+    fn write_matcher(&self, f: &mut Formatter, matcher: &Matcher) -> fmt::Result {
+        match &matcher.matcher_type {
+            MatcherType::String(s, case_sensitive) => write_string(f, s, *case_sensitive),
+            MatcherType::CharClass(class) => write_class(f, class),
+            MatcherType::List(children) if children.is_empty() => f.write_str("\"\""),
+            MatcherType::List(children) => self.write_separated(f, children, " ", LIST + 1),
+            MatcherType::Choice(children, _) => {
+                self.write_separated(f, children, " | ", CHOICE + 1)
+            }
+            MatcherType::Repeating(child, range, _) => {
+                self.write_expr(f, *child, ATOM)?;
+                match (*range.start(), *range.end()) {
+                    (0, usize::MAX) => f.write_char('*'),
+                    (1, usize::MAX) => f.write_char('+'),
+                    (0, 1) => f.write_char('?'),
+                    (min, usize::MAX) => write!(f, "{{{min},}}"),
+                    (min, max) if min == max => write!(f, "{{{min}}}"),
+                    (min, max) => write!(f, "{{{min},{max}}}"),
+                }
+            }
+            MatcherType::Inverted(child) => {
+                f.write_char('!')?;
+                self.write_expr(f, *child, POSTFIX)
+            }
+            MatcherType::Lookahead(child) => {
+                f.write_char('&')?;
+                self.write_expr(f, *child, POSTFIX)
+            }
+            MatcherType::Labeled(label, child) => {
+                write!(f, "{label}:")?;
+                let min_level = match self.matchers[*child].matcher_type {
+                    MatcherType::Labeled(_, _) => POSTFIX,
+                    _ => PREFIX,
+                };
+                self.write_expr(f, *child, min_level)
+            }
+            MatcherType::Precedence(table) => {
+                f.write_str("@precedence(")?;
+                self.write_expr(f, table.operand, PREFIX)?;
+                if let Some(separator) = table.separator {
+                    f.write_str(", ")?;
+                    self.write_expr(f, separator, PREFIX)?;
+                }
+                f.write_char(')')?;
+                let mut precedence = 0;
+                for operator in &table.operators {
+                    if operator.precedence != precedence {
+                        precedence = operator.precedence;
+                        let fixity = match operator.fixity {
+                            Fixity::Infix(Associativity::Left) => "left",
+                            Fixity::Infix(Associativity::Right) => "right",
+                            Fixity::Infix(Associativity::None) => "none",
+                            Fixity::Prefix => "prefix",
+                            Fixity::Postfix => "postfix",
+                        };
+                        write!(f, "\n    {fixity}")?;
+                    }
+                    f.write_char(' ')?;
+                    self.write_expr(f, operator.matcher, PREFIX)?;
+                }
+                Ok(())
+            }
+            MatcherType::Wrapper(child) => self.write_expr(f, *child, 0),
+            MatcherType::Cut => f.write_char('~'),
+            MatcherType::Eof => f.write_str("<eof>"),
+            MatcherType::Newline => f.write_str("<nl>"),
+            MatcherType::Placeholder => f.write_str("\"\""),
+        }
+    }
+
+    fn write_separated(
+        &self,
+        f: &mut Formatter,
+        children: &[usize],
+        separator: &str,
+        min_level: u8,
+    ) -> fmt::Result {
+        for (i, child) in children.iter().enumerate() {
+            if i > 0 {
+                f.write_str(separator)?;
+            }
+            self.write_expr(f, *child, min_level)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if BNF can refer to a rule by `name`, which is words of letters and underscores
+/// joined by dots
+fn is_bnf_name(name: &str) -> bool {
+    name.split('.').enumerate().all(|(i, word)| {
+        word.chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || (i == 0 && c == '_'))
+            && word.chars().all(|c| c.is_alphabetic() || c == '_')
+    })
+}
+
+fn annotation(cull_strategy: CullStrategy) -> Option<String> {
+    match cull_strategy {
+        CullStrategy::None => None,
+        CullStrategy::DeleteAll => Some("@delete".into()),
+        CullStrategy::DeleteChildren => Some("@deleteChildren".into()),
+        CullStrategy::LiftChildren => Some("@lift".into()),
+        CullStrategy::LiftAtMost(n) => Some(format!("@liftAtMost({n})")),
+    }
+}
+
+pub(crate) fn write_string(f: &mut dyn Write, s: &[char], case_sensitive: bool) -> fmt::Result {
+    if !case_sensitive {
+        f.write_char('i')?;
+    }
+    f.write_char('"')?;
+    for c in s {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            c => write_char(f, *c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+pub(crate) fn write_class(f: &mut dyn Write, class: &CharClass) -> fmt::Result {
+    f.write_char('[')?;
+    if class.is_inverted() {
+        f.write_char('^')?;
+    }
+    let (properties, ranges) = class
+        .unicode_properties()
+        .unwrap_or_else(|| (Vec::new(), class.ranges()));
+    for range in ranges {
+        let (start, end) = (*range.start(), *range.end());
+        write_class_char(f, start)?;
+        if end as u32 > start as u32 + 1 {
+            f.write_char('-')?;
+        }
+        if end != start {
+            write_class_char(f, end)?;
+        }
+    }
+    for property in properties {
+        write!(f, "\\p{{{property}}}")?;
+    }
+    f.write_char(']')
+}
+
+/// Write a character, escaping it if it can't be written as it is
+fn write_char(f: &mut dyn Write, c: char) -> fmt::Result {
+    match c {
+        '\\' => f.write_str("\\\\"),
+        '\n' => f.write_str("\\n"),
+        '\t' => f.write_str("\\t"),
+        '\r' => f.write_str("\\r"),
+        c if c.is_control() => write!(f, "\\u{:04x}", c as u32),
+        c => f.write_char(c),
+    }
+}
+
+fn write_class_char(f: &mut dyn Write, c: char) -> fmt::Result {
+    match c {
+        ']' | '-' | '^' | '[' => write!(f, "\\{c}"),
+        c => write_char(f, c),
+    }
+}
@@ -0,0 +1,280 @@
+//! Timing each matcher while matching some input, and counting how much of the input it
+//! matched and how much it had to look at again after backtracking
+
+use std::fmt::{Display, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::matchers::{Matcher, MatcherType};
+use crate::printer;
+
+/// Counts of how each matcher was applied while collecting a `Profile`
+pub(crate) struct ProfileCollector {
+    matchers: Vec<Counts>,
+}
+
+#[derive(Default, Clone)]
+struct Counts {
+    invocations: usize,
+    successes: usize,
+    failures: usize,
+    consumed: usize,
+    rescanned: usize,
+    time: Duration,
+    /// How many applications of the matcher are under way, so time spent in a matcher
+    /// applied inside itself isn't counted twice
+    active: usize,
+    lookups: usize,
+    skips: usize,
+}
+
+/// Where an application of a matcher started, to measure it once it's over
+pub(crate) struct Entered {
+    start: Instant,
+    /// The furthest any matcher had looked at the input before this one was applied
+    examined: usize,
+}
+
+impl ProfileCollector {
+    pub(crate) fn new(matchers: usize) -> Self {
+        ProfileCollector {
+            matchers: vec![Counts::default(); matchers],
+        }
+    }
+
+    pub(crate) fn enter(&mut self, matcher: usize, examined: usize) -> Entered {
+        self.matchers[matcher].active += 1;
+        Entered {
+            start: Instant::now(),
+            examined,
+        }
+    }
+
+    /// Note that an application of `matcher` at `pos` is over, having looked at the input
+    /// up to `examined`
+    pub(crate) fn exit(
+        &mut self,
+        matcher: usize,
+        entered: Entered,
+        source: &str,
+        pos: usize,
+        range: Option<Range<usize>>,
+        examined: usize,
+    ) {
+        let counts = &mut self.matchers[matcher];
+        counts.active -= 1;
+        counts.invocations += 1;
+        match range {
+            Some(range) => {
+                counts.successes += 1;
+                counts.consumed += chars_in(source, range);
+            }
+            None => counts.failures += 1,
+        }
+        let rescanned = pos..examined.min(entered.examined).max(pos);
+        counts.rescanned += chars_in(source, rescanned);
+        if counts.active == 0 {
+            counts.time += entered.start.elapsed();
+        }
+    }
+
+    /// Note that `matcher` looked up the next char in its cache, which let it skip applying
+    /// `skips` alternatives or repetitions
+    pub(crate) fn cache_lookup(&mut self, matcher: usize, skips: usize) {
+        let counts = &mut self.matchers[matcher];
+        counts.lookups += 1;
+        counts.skips += skips;
+    }
+}
+
+/// Count the chars in `range` of `source`, which doesn't have to lie on char boundaries
+fn chars_in(source: &str, range: Range<usize>) -> usize {
+    source.as_bytes()[range.start..range.end.min(source.len())]
+        .iter()
+        .filter(|b| !(0x80..0xC0).contains(*b))
+        .count()
+}
+
+/// How long each matcher took to match some input, and how it was applied, from
+/// `Lexer::profile`
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Every matcher that was applied, slowest first
+    pub rules: Vec<RuleProfile>,
+}
+
+/// How one matcher was applied, in `Profile`
+#[derive(Debug, Clone)]
+pub struct RuleProfile {
+    pub matcher_id: usize,
+    /// The name of the rule, or the BNF of an unnamed matcher
+    pub name: String,
+    pub named: bool,
+    pub invocations: usize,
+    pub successes: usize,
+    pub failures: usize,
+    /// How many chars the matcher matched, over every time it succeeded
+    pub consumed: usize,
+    /// How many chars the matcher looked at that had already been looked at before it was
+    /// applied, which is the work backtracking makes it do again
+    pub rescanned: usize,
+    /// The time spent applying the matcher, including the matchers it applied
+    pub time: Duration,
+    /// How the first-char cache was used, for choices and repetitions
+    pub cache: Option<CacheProfile>,
+}
+
+/// How a choice or repetition used the cache of what can match each next char
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheProfile {
+    /// Whether the matcher has a cache at all
+    pub present: bool,
+    /// How many times the next char was looked up in the cache. It can only be for ASCII
+    /// chars.
+    pub lookups: usize,
+    /// How many alternatives of a choice, or repetitions, the cache skipped applying
+    pub skips: usize,
+}
+
+impl CacheProfile {
+    /// Check if the cache skipped anything at all. One that never does only costs time.
+    pub fn is_effective(&self) -> bool {
+        self.present && self.skips > 0
+    }
+
+    fn describe(&self) -> &'static str {
+        match (self.present, self.is_effective()) {
+            (false, _) => "missing",
+            (true, false) => "ineffective",
+            (true, true) => "ok",
+        }
+    }
+}
+
+impl Profile {
+    pub(crate) fn new(collector: ProfileCollector, matchers: &[Matcher]) -> Self {
+        let descriptions = printer::describe_all(matchers);
+        let mut rules: Vec<_> = collector
+            .matchers
+            .into_iter()
+            .zip(descriptions)
+            .enumerate()
+            .filter(|(_, (counts, _))| counts.invocations > 0)
+            .map(|(id, (counts, name))| {
+                let present = match &matchers[id].matcher_type {
+                    MatcherType::Choice(_, cache) => Some(cache.is_some()),
+                    MatcherType::Repeating(_, _, cache) => Some(cache.is_some()),
+                    _ => None,
+                };
+                RuleProfile {
+                    matcher_id: id,
+                    name,
+                    named: matchers[id].name.is_some(),
+                    invocations: counts.invocations,
+                    successes: counts.successes,
+                    failures: counts.failures,
+                    consumed: counts.consumed,
+                    rescanned: counts.rescanned,
+                    time: counts.time,
+                    cache: present.map(|present| CacheProfile {
+                        present,
+                        lookups: counts.lookups,
+                        skips: counts.skips,
+                    }),
+                }
+            })
+            .collect();
+        rules.sort_by(|a, b| b.time.cmp(&a.time).then(a.matcher_id.cmp(&b.matcher_id)));
+        Profile { rules }
+    }
+
+    /// Get the profile of the rule called `name`, if it was applied
+    pub fn rule(&self, name: &str) -> Option<&RuleProfile> {
+        self.rules
+            .iter()
+            .find(|rule| rule.named && rule.name == name)
+    }
+
+    /// Write the profile as JSON, with times in nanoseconds
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"rules\": [");
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i > 0 {
+                json.push_str(", ");
+            }
+            let _ = write!(
+                json,
+                "{{\"matcher_id\": {}, \"name\": {}, \"named\": {}, \"invocations\": {}, \
+                 \"successes\": {}, \"failures\": {}, \"consumed\": {}, \"rescanned\": {}, \
+                 \"time_ns\": {}, \"cache\": ",
+                rule.matcher_id,
+                json_string(&rule.name),
+                rule.named,
+                rule.invocations,
+                rule.successes,
+                rule.failures,
+                rule.consumed,
+                rule.rescanned,
+                rule.time.as_nanos(),
+            );
+            let _ = match &rule.cache {
+                Some(cache) => write!(
+                    json,
+                    "{{\"present\": {}, \"lookups\": {}, \"skips\": {}, \"effective\": {}}}}}",
+                    cache.present,
+                    cache.lookups,
+                    cache.skips,
+                    cache.is_effective()
+                ),
+                None => write!(json, "null}}"),
+            };
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Writes the profile as a table, slowest first, marking caches that are missing or
+/// didn't skip anything
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>12} {:>9} {:>9} {:>9} {:>9} {:>9} {:<11} rule",
+            "time (ms)", "calls", "matched", "failed", "consumed", "rescanned", "cache"
+        )?;
+        for rule in &self.rules {
+            writeln!(
+                f,
+                "{:>12.3} {:>9} {:>9} {:>9} {:>9} {:>9} {:<11} {}",
+                rule.time.as_secs_f64() * 1000.0,
+                rule.invocations,
+                rule.successes,
+                rule.failures,
+                rule.consumed,
+                rule.rescanned,
+                rule.cache.as_ref().map_or("", CacheProfile::describe),
+                rule.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,493 @@
+use std::fmt::Write;
+
+use crate::matchers::{Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity, OperatorTable};
+use crate::printer;
+
+/// Radius of the curves where tracks split and join
+const RADIUS: i32 = 10;
+/// Horizontal space between items in a sequence, and vertical space between tracks
+const GAP: i32 = 10;
+const BOX_HEIGHT: i32 = 22;
+const CHAR_WIDTH: i32 = 8;
+const LABEL_HEIGHT: i32 = 16;
+/// Space around the diagram, and around the contents of a group
+const PADDING: i32 = 8;
+/// Longest text shown in a box, with the full text shown when hovering over it
+const MAX_TEXT: usize = 40;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+h2 { font-family: monospace; margin-bottom: 0.2em; }
+p.used-by { margin-top: 0; color: #555; }
+svg path { fill: none; stroke: #333; stroke-width: 1.5; }
+svg rect { stroke: #333; stroke-width: 1.5; }
+svg text { font: 13px monospace; text-anchor: middle; dominant-baseline: central; }
+svg .literal rect { fill: #fff7d6; }
+svg .rule rect { fill: #dbe9ff; }
+svg a:hover rect { fill: #b7d1ff; }
+svg .class rect { fill: #e3f5e1; }
+svg .special rect { fill: #eee; }
+svg .group > rect { fill: none; stroke: #999; stroke-dasharray: 4 3; }
+svg .label { font-size: 11px; fill: #555; text-anchor: start; }
+svg .bounds { font-size: 11px; fill: #555; }
+";
+
+/// Draw a syntax diagram for every rule, linked to the diagrams of the rules it uses, on a
+/// self-contained HTML page
+pub(crate) fn write_html(title: &str, root: usize, matchers: &[Matcher]) -> String {
+    let title = escape(title);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    let rules = std::iter::once(root).chain(
+        matchers
+            .iter()
+            .filter(|m| m.name.is_some() && m.id != root)
+            .map(|m| m.id),
+    );
+    for id in rules {
+        let name = matchers[id].name.as_deref().unwrap_or_default();
+        let diagram = Builder { matchers, rule: id }.node(id, true);
+        let _ = write!(
+            html,
+            "<section id=\"{}\">\n<h2>{}</h2>\n",
+            anchor(name),
+            escape(name)
+        );
+        let users = users(id, matchers);
+        if !users.is_empty() {
+            let links: Vec<_> = users.iter().map(|name| rule_link(name)).collect();
+            let _ = writeln!(
+                html,
+                "<p class=\"used-by\">Used by {}</p>",
+                links.join(", ")
+            );
+        }
+        html += &svg(&diagram);
+        html += "</section>\n";
+    }
+    html += "</body>\n</html>\n";
+    html
+}
+
+/// Get the names of the rules that use a rule
+fn users(id: usize, matchers: &[Matcher]) -> Vec<&str> {
+    matchers
+        .iter()
+        .filter_map(|m| m.name.as_deref().map(|name| (m.id, name)))
+        .filter(|(rule, _)| *rule != id && uses(*rule, id, matchers))
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Check if `rule` uses `target`, directly or through the unnamed matchers making it up
+fn uses(rule: usize, target: usize, matchers: &[Matcher]) -> bool {
+    matchers[rule].child_ids().into_iter().any(|child| {
+        child == target || (matchers[child].name.is_none() && uses(child, target, matchers))
+    })
+}
+
+fn anchor(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("rule-{name}")
+}
+
+fn rule_link(name: &str) -> String {
+    format!("<a href=\"#{}\">{}</a>", anchor(name), escape(name))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Clone, Copy)]
+enum BoxKind {
+    Literal,
+    Rule,
+    Class,
+    Special,
+}
+
+/// A part of a diagram, drawn around a horizontal track
+enum Node {
+    Box(String, BoxKind),
+    Sequence(Vec<Node>),
+    /// Alternatives, with the first one on the track and the rest below it
+    Choice(Vec<Node>),
+    /// Something repeated, with a track looping back below it, and a label for its bounds
+    /// unless it's repeated one or more times
+    Loop(Box<Node>, Option<String>),
+    /// Something with a dashed outline and a label, like a negation or a labeled part
+    Group(Box<Node>, String),
+    /// A bare track, for skipping something optional
+    Skip,
+}
+
+struct Builder<'m> {
+    matchers: &'m [Matcher],
+    rule: usize,
+}
+
+impl Builder<'_> {
+    fn node(&self, id: usize, top: bool) -> Node {
+        let matcher = &self.matchers[id];
+        if let (Some(name), false) = (&*matcher.name, top) {
+            return Node::Box(name.clone(), BoxKind::Rule);
+        }
+        match &matcher.matcher_type {
+            MatcherType::String(s, case_sensitive) => {
+                let mut text = String::new();
+                let _ = printer::write_string(&mut text, s, *case_sensitive);
+                Node::Box(text, BoxKind::Literal)
+            }
+            MatcherType::CharClass(class) => {
+                let mut text = String::new();
+                let _ = printer::write_class(&mut text, class);
+                Node::Box(text, BoxKind::Class)
+            }
+            MatcherType::List(children) => {
+                Node::Sequence(children.iter().map(|c| self.node(*c, false)).collect())
+            }
+            MatcherType::Choice(children, _) => {
+                Node::Choice(children.iter().map(|c| self.node(*c, false)).collect())
+            }
+            MatcherType::Repeating(child, range, _) => {
+                let child = self.node(*child, false);
+                let (min, max) = (*range.start(), *range.end());
+                let bounds = match (min, max) {
+                    (0 | 1, usize::MAX) | (0 | 1, 1) => None,
+                    (min, usize::MAX) => Some(format!("at least {min} times")),
+                    (min, max) if min == max => Some(format!("{min} times")),
+                    (0, max) => Some(format!("up to {max} times")),
+                    (min, max) => Some(format!("{min} to {max} times")),
+                };
+                let repeated = match max {
+                    0 => Node::Skip,
+                    1 => child,
+                    _ => Node::Loop(Box::new(child), bounds),
+                };
+                match min {
+                    0 => Node::Choice(vec![Node::Skip, repeated]),
+                    _ => repeated,
+                }
+            }
+            MatcherType::Inverted(child) => self.group(*child, "not"),
+            MatcherType::Lookahead(child) => self.group(*child, "followed by"),
+            MatcherType::Labeled(label, child) => self.group(*child, &format!("{label}:")),
+            MatcherType::Precedence(table) => self.operations(table),
+            MatcherType::Wrapper(child) => self.node(*child, false),
+            MatcherType::Cut => Node::Box("~".into(), BoxKind::Special),
+            MatcherType::Eof => Node::Box("<eof>".into(), BoxKind::Special),
+            MatcherType::Newline => Node::Box("<nl>".into(), BoxKind::Special),
+            MatcherType::Placeholder => Node::Skip,
+        }
+    }
+
+    fn group(&self, child: usize, label: &str) -> Node {
+        Node::Group(Box::new(self.node(child, false)), label.to_string())
+    }
+
+    /// Draw an operator table as a choice between an operation for each level, from the
+    /// loosest binding to the tightest, and the operand
+    fn operations(&self, table: &OperatorTable) -> Node {
+        let name = self.matchers[self.rule].name.as_deref().unwrap_or_default();
+        let operation = || Node::Box(name.to_string(), BoxKind::Rule);
+        let separator = || {
+            table
+                .separator
+                .map(|s| Node::Choice(vec![Node::Skip, self.node(s, false)]))
+        };
+        let mut rows: Vec<(Fixity, Vec<Node>)> = Vec::new();
+        let mut precedence = 0;
+        for operator in &table.operators {
+            let node = self.node(operator.matcher, false);
+            match rows.last_mut() {
+                Some((_, row)) if operator.precedence == precedence => row.push(node),
+                _ => rows.push((operator.fixity, vec![node])),
+            }
+            precedence = operator.precedence;
+        }
+        let mut alternatives = Vec::new();
+        for (fixity, operators) in rows {
+            let operators = match operators.len() {
+                1 => operators.into_iter().next().unwrap(),
+                _ => Node::Choice(operators),
+            };
+            let (parts, label) = match fixity {
+                Fixity::Prefix => (
+                    vec![Some(operators), separator(), Some(operation())],
+                    "prefix",
+                ),
+                Fixity::Postfix => (
+                    vec![Some(operation()), separator(), Some(operators)],
+                    "postfix",
+                ),
+                Fixity::Infix(associativity) => {
+                    let parts = vec![
+                        Some(operation()),
+                        separator(),
+                        Some(operators),
+                        separator(),
+                        Some(operation()),
+                    ];
+                    let label = match associativity {
+                        Associativity::Left => "left",
+                        Associativity::Right => "right",
+                        Associativity::None => "non-associative",
+                    };
+                    (parts, label)
+                }
+            };
+            let sequence = Node::Sequence(parts.into_iter().flatten().collect());
+            alternatives.push(Node::Group(Box::new(sequence), label.to_string()));
+        }
+        alternatives.push(self.node(table.operand, false));
+        Node::Choice(alternatives)
+    }
+}
+
+impl Node {
+    fn width(&self) -> i32 {
+        match self {
+            Node::Box(text, _) => shown_text(text).chars().count() as i32 * CHAR_WIDTH + 2 * GAP,
+            Node::Sequence(items) => {
+                let widths: i32 = items.iter().map(Node::width).sum();
+                widths + GAP * (items.len() as i32 - 1).max(0)
+            }
+            Node::Choice(alternatives) => {
+                alternatives.iter().map(Node::width).max().unwrap_or(0) + 4 * RADIUS
+            }
+            Node::Loop(child, _) => child.width() + 2 * RADIUS,
+            Node::Group(child, label) => {
+                let label = label.chars().count() as i32 * CHAR_WIDTH;
+                child.width().max(label) + 2 * PADDING
+            }
+            Node::Skip => 0,
+        }
+    }
+
+    /// Get how far the node reaches above its track
+    fn up(&self) -> i32 {
+        match self {
+            Node::Box(_, _) => BOX_HEIGHT / 2,
+            Node::Sequence(items) => items.iter().map(Node::up).max().unwrap_or(0),
+            Node::Choice(alternatives) => alternatives.first().map_or(0, Node::up),
+            Node::Loop(child, _) => child.up(),
+            Node::Group(child, _) => child.up() + PADDING + LABEL_HEIGHT,
+            Node::Skip => 0,
+        }
+    }
+
+    /// Get how far the node reaches below its track
+    fn down(&self) -> i32 {
+        match self {
+            Node::Box(_, _) => BOX_HEIGHT / 2,
+            Node::Sequence(items) => items.iter().map(Node::down).max().unwrap_or(0),
+            Node::Choice(alternatives) => {
+                let offsets = track_offsets(alternatives);
+                offsets.last().unwrap_or(&0) + alternatives.last().map_or(0, Node::down)
+            }
+            Node::Loop(child, bounds) => {
+                loop_depth(child) + if bounds.is_some() { LABEL_HEIGHT } else { 0 }
+            }
+            Node::Group(child, _) => child.down() + PADDING,
+            Node::Skip => 0,
+        }
+    }
+
+    /// Draw the node with its track starting at `x`, `y`
+    fn render(&self, out: &mut String, x: i32, y: i32) {
+        let width = self.width();
+        match self {
+            Node::Box(text, kind) => {
+                let shown = shown_text(text);
+                let (class, rx) = match kind {
+                    BoxKind::Literal => ("literal", BOX_HEIGHT / 2),
+                    BoxKind::Rule => ("rule", 0),
+                    BoxKind::Class => ("class", 4),
+                    BoxKind::Special => ("special", 4),
+                };
+                let tooltip = match shown.len() < text.len() {
+                    true => format!("<title>{}</title>", escape(text)),
+                    false => String::new(),
+                };
+                let rect = format!(
+                    "<g class=\"{class}\">{tooltip}<rect x=\"{x}\" y=\"{}\" width=\"{width}\" \
+                     height=\"{BOX_HEIGHT}\" rx=\"{rx}\"/><text x=\"{}\" y=\"{y}\">{}</text></g>",
+                    y - BOX_HEIGHT / 2,
+                    x + width / 2,
+                    escape(&shown)
+                );
+                match kind {
+                    BoxKind::Rule => {
+                        let _ = writeln!(out, "<a href=\"#{}\">{rect}</a>", anchor(text));
+                    }
+                    _ => {
+                        let _ = writeln!(out, "{rect}");
+                    }
+                }
+            }
+            Node::Sequence(items) => {
+                let mut x = x;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        line(out, x, y, GAP);
+                        x += GAP;
+                    }
+                    item.render(out, x, y);
+                    x += item.width();
+                }
+            }
+            Node::Choice(alternatives) => {
+                let inner = width - 4 * RADIUS;
+                for (alternative, offset) in alternatives.iter().zip(track_offsets(alternatives)) {
+                    let alt_y = y + offset;
+                    if offset == 0 {
+                        line(out, x, y, 2 * RADIUS);
+                    } else {
+                        let drop = offset - 2 * RADIUS;
+                        let _ = writeln!(
+                            out,
+                            "<path d=\"M{x} {y} a{RADIUS} {RADIUS} 0 0 1 {RADIUS} {RADIUS} v{drop} \
+                             a{RADIUS} {RADIUS} 0 0 0 {RADIUS} {RADIUS}\"/>"
+                        );
+                    }
+                    alternative.render(out, x + 2 * RADIUS, alt_y);
+                    let end = x + 2 * RADIUS + alternative.width();
+                    line(out, end, alt_y, x + 2 * RADIUS + inner - end);
+                    let join_x = x + 2 * RADIUS + inner;
+                    if offset == 0 {
+                        line(out, join_x, y, 2 * RADIUS);
+                    } else {
+                        let rise = offset - 2 * RADIUS;
+                        let _ = writeln!(
+                            out,
+                            "<path d=\"M{join_x} {alt_y} a{RADIUS} {RADIUS} 0 0 0 {RADIUS} -{RADIUS} \
+                             v-{rise} a{RADIUS} {RADIUS} 0 0 1 {RADIUS} -{RADIUS}\"/>"
+                        );
+                    }
+                }
+            }
+            Node::Loop(child, bounds) => {
+                let child_width = child.width();
+                line(out, x, y, RADIUS);
+                child.render(out, x + RADIUS, y);
+                line(out, x + RADIUS + child_width, y, RADIUS);
+                let depth = loop_depth(child);
+                let drop = depth - 2 * RADIUS;
+                let _ = writeln!(
+                    out,
+                    "<path d=\"M{} {y} a{RADIUS} {RADIUS} 0 0 1 {RADIUS} {RADIUS} v{drop} \
+                     a{RADIUS} {RADIUS} 0 0 1 -{RADIUS} {RADIUS} h-{child_width} \
+                     a{RADIUS} {RADIUS} 0 0 1 -{RADIUS} -{RADIUS} v-{drop} \
+                     a{RADIUS} {RADIUS} 0 0 1 {RADIUS} -{RADIUS}\"/>",
+                    x + RADIUS + child_width
+                );
+                if let Some(bounds) = bounds {
+                    let _ = writeln!(
+                        out,
+                        "<text class=\"bounds\" x=\"{}\" y=\"{}\">{}</text>",
+                        x + width / 2,
+                        y + depth + LABEL_HEIGHT / 2,
+                        escape(bounds)
+                    );
+                }
+            }
+            Node::Group(child, label) => {
+                let top = y - self.up();
+                let _ = writeln!(
+                    out,
+                    "<g class=\"group\"><rect x=\"{x}\" y=\"{top}\" width=\"{width}\" \
+                     height=\"{}\" rx=\"4\"/><text class=\"label\" x=\"{}\" y=\"{}\">{}</text></g>",
+                    self.up() + self.down(),
+                    x + PADDING,
+                    top + LABEL_HEIGHT / 2 + 2,
+                    escape(label)
+                );
+                line(out, x, y, PADDING);
+                child.render(out, x + PADDING, y);
+                let end = x + PADDING + child.width();
+                line(out, end, y, x + width - end);
+            }
+            Node::Skip => (),
+        }
+    }
+}
+
+/// Get how far below the track each alternative's track is, keeping them far enough apart
+/// for the curves joining them
+fn track_offsets(alternatives: &[Node]) -> Vec<i32> {
+    let mut offsets: Vec<i32> = Vec::with_capacity(alternatives.len());
+    for (i, alternative) in alternatives.iter().enumerate() {
+        offsets.push(match i {
+            0 => 0,
+            _ => {
+                let previous = offsets[i - 1];
+                let below = previous + alternatives[i - 1].down() + GAP + alternative.up();
+                below.max(previous + 2 * RADIUS)
+            }
+        });
+    }
+    offsets
+}
+
+/// Get how far below the track the track looping back under a node is
+fn loop_depth(child: &Node) -> i32 {
+    (child.down() + GAP).max(2 * RADIUS)
+}
+
+fn line(out: &mut String, x: i32, y: i32, length: i32) {
+    if length > 0 {
+        let _ = writeln!(out, "<path d=\"M{x} {y} h{length}\"/>");
+    }
+}
+
+/// Shorten text too long to show in full
+fn shown_text(text: &str) -> String {
+    match text.chars().count() > MAX_TEXT {
+        true => text.chars().take(MAX_TEXT - 1).chain(['…']).collect(),
+        false => text.to_string(),
+    }
+}
+
+/// Draw a diagram with bars marking where it starts and ends
+fn svg(diagram: &Node) -> String {
+    let marker = 2 * GAP;
+    let width = diagram.width() + 2 * (marker + PADDING);
+    // Leave room for the bars even when nothing reaches above or below the track
+    let (up, down) = (diagram.up(), diagram.down());
+    let (up, down) = (up.max(BOX_HEIGHT / 2), down.max(BOX_HEIGHT / 2));
+    let height = up + down + 2 * PADDING;
+    let y = PADDING + up;
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\">\n"
+    );
+    let end = width - PADDING;
+    let _ = writeln!(
+        out,
+        "<path d=\"M{PADDING} {} v{BOX_HEIGHT} M{} {} v{BOX_HEIGHT} M{PADDING} {y} h{marker}\"/>",
+        y - BOX_HEIGHT / 2,
+        PADDING + GAP / 2,
+        y - BOX_HEIGHT / 2
+    );
+    diagram.render(&mut out, PADDING + marker, y);
+    let _ = writeln!(
+        out,
+        "<path d=\"M{} {y} h{marker} M{end} {} v{BOX_HEIGHT} M{} {} v{BOX_HEIGHT}\"/>",
+        end - marker,
+        y - BOX_HEIGHT / 2,
+        end - GAP / 2,
+        y - BOX_HEIGHT / 2
+    );
+    out += "</svg>\n";
+    out
+}
@@ -0,0 +1,51 @@
+//! Tokenizing past errors, by skipping the text around each one and covering it with an
+//! error token
+
+/// Where tokenizing failed on earlier attempts, which rules can skip past on the next one
+pub(crate) struct Recovery {
+    errors: Vec<usize>,
+    /// Set for grammars without any `@recover` rules, to recover in repetitions instead
+    pub(crate) heuristic: bool,
+}
+
+impl Recovery {
+    pub(crate) fn new(heuristic: bool) -> Self {
+        Recovery {
+            errors: Vec::new(),
+            heuristic,
+        }
+    }
+
+    /// Note an error at `pos`, returning false if there already was one there
+    pub(crate) fn add_error(&mut self, pos: usize) -> bool {
+        match self.errors.binary_search(&pos) {
+            Ok(_) => false,
+            Err(index) => {
+                self.errors.insert(index, pos);
+                true
+            }
+        }
+    }
+
+    /// Whether there's an error between `start` and `end`, inclusive
+    pub(crate) fn has_error(&self, start: usize, end: usize) -> bool {
+        let index = self.errors.partition_point(|pos| *pos < start);
+        self.errors.get(index).is_some_and(|pos| *pos <= end)
+    }
+}
+
+/// Find where to skip to from `pos` without a grammar-declared synchronization point: past
+/// the next line break, or up to the next closing delimiter. Returns the end of the skipped
+/// text along with the furthest an error can be for it to be skipped.
+pub(crate) fn heuristic_sync(source: &str, pos: usize) -> (usize, usize) {
+    let next = source[pos..]
+        .char_indices()
+        .find(|(_, c)| matches!(c, '\n' | ')' | ']' | '}'));
+    match next {
+        Some((i, '\n')) => (pos + i + 1, pos + i),
+        // A closing delimiter that isn't expected is skipped itself
+        Some((0, c)) => (pos + c.len_utf8(), pos),
+        Some((i, _)) => (pos + i, pos + i),
+        None => (source.len(), source.len()),
+    }
+}
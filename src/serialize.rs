@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::char_class::CharClass;
+use crate::error::{FluxError, Result};
+use crate::lexer::CullStrategy;
+use crate::matchers::{Matcher, MatcherType};
+use crate::precedence::{Associativity, Fixity, Operator, OperatorTable};
+
+const MAGIC: &[u8; 4] = b"FLUX";
+/// Bumped whenever the format changes, since older versions can't be read
+const VERSION: u16 = 3;
+const CHECKSUM_LEN: usize = 4;
+
+/// The parts of a lexer that get serialized, in the order they're written
+pub(crate) struct Parts {
+    pub(crate) root: usize,
+    pub(crate) retain_empty: bool,
+    pub(crate) names: HashMap<String, usize>,
+    pub(crate) matchers: Vec<Matcher>,
+}
+
+/// Serialize a lexer as the magic bytes, the format version, the lexer and a CRC-32 of
+/// everything before it. Numbers are written as LEB128 varints. Only the grammar is written,
+/// and what `Lexer::new` works out from it, like which matchers are left-recursive and the
+/// caches, is worked out again when it's read.
+pub(crate) fn write(
+    root: usize,
+    retain_empty: bool,
+    names: &HashMap<String, usize>,
+    matchers: &[Matcher],
+) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.0.extend_from_slice(MAGIC);
+    w.0.extend_from_slice(&VERSION.to_le_bytes());
+    w.usize(root);
+    w.bool(retain_empty);
+    let mut names: Vec<_> = names.iter().collect();
+    names.sort();
+    w.usize(names.len());
+    for (name, id) in names {
+        w.str(name);
+        w.usize(*id);
+    }
+    w.usize(matchers.len());
+    for matcher in matchers {
+        w.matcher(matcher);
+    }
+    let checksum = crc32(&w.0);
+    w.0.extend_from_slice(&checksum.to_le_bytes());
+    w.0
+}
+
+pub(crate) fn read(bytes: &[u8]) -> Result<Parts> {
+    if bytes.len() < MAGIC.len() + 2 + CHECKSUM_LEN || !bytes.starts_with(MAGIC) {
+        return Err(error("Not a serialized lexer", 0));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(error(
+            &format!(
+                "Serialized lexer has format version {version}, but this version of flux_bnf \
+                 only reads version {VERSION}"
+            ),
+            4,
+        ));
+    }
+    let (data, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    if crc32(data).to_le_bytes() != checksum {
+        return Err(error(
+            "Serialized lexer is corrupted, its checksum doesn't match",
+            data.len(),
+        ));
+    }
+    let mut r = Reader {
+        data,
+        pos: MAGIC.len() + 2,
+        matcher_count: 0,
+    };
+    let root = r.usize()?;
+    let retain_empty = r.bool()?;
+    let mut names = HashMap::new();
+    for _ in 0..r.usize()? {
+        names.insert(r.str()?, r.usize()?);
+    }
+    r.matcher_count = r.usize()?;
+    let matchers = (0..r.matcher_count)
+        .map(|id| r.matcher(id))
+        .collect::<Result<Vec<_>>>()?;
+    if root >= matchers.len() || names.values().any(|id| *id >= matchers.len()) {
+        return Err(error(
+            "Serialized lexer refers to a matcher it doesn't have",
+            0,
+        ));
+    }
+    if r.pos != data.len() {
+        return Err(error(
+            "Serialized lexer has unexpected data at the end",
+            r.pos,
+        ));
+    }
+    Ok(Parts {
+        root,
+        retain_empty,
+        names,
+        matchers,
+    })
+}
+
+fn error(message: &str, pos: usize) -> FluxError {
+    FluxError::new_dyn(message.to_string(), pos, None)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn usize(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.0.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.0.push(n as u8);
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.0.push(b as u8);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn ids(&mut self, ids: &[usize]) {
+        self.usize(ids.len());
+        for id in ids {
+            self.usize(*id);
+        }
+    }
+
+    /// Write a repetition range, with an unbounded end written as 0 so it's the same
+    /// whatever the size of `usize`
+    fn range(&mut self, range: &RangeInclusive<usize>) {
+        self.usize(*range.start());
+        self.usize(range.end().wrapping_add(1));
+    }
+
+    fn matcher(&mut self, matcher: &Matcher) {
+        match &*matcher.name {
+            Some(name) => {
+                self.bool(true);
+                self.str(name);
+            }
+            None => self.bool(false),
+        }
+        match matcher.cull_strategy {
+            CullStrategy::None => self.0.push(0),
+            CullStrategy::DeleteAll => self.0.push(1),
+            CullStrategy::DeleteChildren => self.0.push(2),
+            CullStrategy::LiftChildren => self.0.push(3),
+            CullStrategy::LiftAtMost(n) => {
+                self.0.push(4);
+                self.usize(n);
+            }
+        }
+        self.bool(matcher.show_in_errors);
+        self.bool(matcher.memoize);
+        match matcher.recover {
+            Some(sync) => {
+                self.bool(true);
+                self.usize(sync);
+            }
+            None => self.bool(false),
+        }
+        match &matcher.matcher_type {
+            MatcherType::String(s, case_sensitive) => {
+                self.0.push(0);
+                self.str(&s.iter().collect::<String>());
+                self.bool(*case_sensitive);
+            }
+            MatcherType::CharClass(class) => {
+                self.0.push(1);
+                self.bool(class.is_inverted());
+                let ranges = class.ranges();
+                self.usize(ranges.len());
+                for range in ranges {
+                    self.usize(*range.start() as usize);
+                    self.usize(*range.end() as usize);
+                }
+            }
+            MatcherType::List(children) => {
+                self.0.push(2);
+                self.ids(children);
+            }
+            MatcherType::Choice(children, _) => {
+                self.0.push(3);
+                self.ids(children);
+            }
+            MatcherType::Repeating(child, range, _) => {
+                self.0.push(4);
+                self.usize(*child);
+                self.range(range);
+            }
+            MatcherType::Inverted(child) => {
+                self.0.push(5);
+                self.usize(*child);
+            }
+            MatcherType::Lookahead(child) => {
+                self.0.push(6);
+                self.usize(*child);
+            }
+            MatcherType::Labeled(label, child) => {
+                self.0.push(7);
+                self.str(label);
+                self.usize(*child);
+            }
+            MatcherType::Precedence(table) => {
+                self.0.push(8);
+                self.usize(table.operand);
+                match table.separator {
+                    Some(separator) => {
+                        self.bool(true);
+                        self.usize(separator);
+                    }
+                    None => self.bool(false),
+                }
+                self.usize(table.operators.len());
+                for operator in &table.operators {
+                    self.usize(operator.matcher);
+                    self.usize(operator.precedence);
+                    self.0.push(match operator.fixity {
+                        Fixity::Prefix => 0,
+                        Fixity::Infix(Associativity::Left) => 1,
+                        Fixity::Infix(Associativity::Right) => 2,
+                        Fixity::Infix(Associativity::None) => 3,
+                        Fixity::Postfix => 4,
+                    });
+                }
+                for label in &table.labels {
+                    self.str(label);
+                }
+            }
+            MatcherType::Wrapper(child) => {
+                self.0.push(9);
+                self.usize(*child);
+            }
+            MatcherType::Cut => self.0.push(10),
+            MatcherType::Eof => self.0.push(11),
+            MatcherType::Newline => self.0.push(12),
+            // Written so that reading it fails, since a lexer can't have an undefined rule
+            MatcherType::Placeholder => self.0.push(13),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// How many matchers the lexer has, which every id has to be below
+    matcher_count: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| error("Serialized lexer ends unexpectedly", self.pos))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let start = self.pos;
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(error(
+            "Serialized lexer has a number that is too large",
+            start,
+        ))
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.usize()?;
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| error("Serialized lexer ends unexpectedly", self.pos))?;
+        let s = std::str::from_utf8(bytes).map_err(|_| self.invalid())?;
+        self.pos += len;
+        Ok(s.to_string())
+    }
+
+    fn char(&mut self) -> Result<char> {
+        let c = self.usize()?;
+        u32::try_from(c)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.invalid())
+    }
+
+    /// Read the id of a matcher, checking that the lexer has it
+    fn id(&mut self) -> Result<usize> {
+        self.index(self.matcher_count)
+    }
+
+    fn index(&mut self, len: usize) -> Result<usize> {
+        let start = self.pos;
+        let index = self.usize()?;
+        if index >= len {
+            return Err(error(
+                "Serialized lexer refers to a matcher it doesn't have",
+                start,
+            ));
+        }
+        Ok(index)
+    }
+
+    /// Read the precedence of an operator in a table of `count` operators, which is at least 1
+    /// and at most one level per operator
+    fn precedence(&mut self, count: usize) -> Result<usize> {
+        let start = self.pos;
+        let precedence = self.usize()?;
+        if !(1..=count).contains(&precedence) {
+            return Err(error(
+                "Serialized lexer has an operator with an invalid precedence",
+                start,
+            ));
+        }
+        Ok(precedence)
+    }
+
+    fn ids(&mut self) -> Result<Vec<usize>> {
+        (0..self.usize()?).map(|_| self.id()).collect()
+    }
+
+    fn invalid(&self) -> FluxError {
+        error("Serialized lexer is invalid", self.pos)
+    }
+
+    fn matcher(&mut self, id: usize) -> Result<Matcher> {
+        let name = match self.bool()? {
+            true => Some(self.str()?),
+            false => None,
+        };
+        let cull_strategy = match self.byte()? {
+            0 => CullStrategy::None,
+            1 => CullStrategy::DeleteAll,
+            2 => CullStrategy::DeleteChildren,
+            3 => CullStrategy::LiftChildren,
+            4 => CullStrategy::LiftAtMost(self.usize()?),
+            _ => return Err(self.invalid()),
+        };
+        let show_in_errors = self.bool()?;
+        let memoize = self.bool()?;
+        let recover = match self.bool()? {
+            true => Some(self.id()?),
+            false => None,
+        };
+        let matcher_type = match self.byte()? {
+            0 => MatcherType::String(self.str()?.chars().collect(), self.bool()?),
+            1 => {
+                let inverted = self.bool()?;
+                let ranges = (0..self.usize()?)
+                    .map(|_| Ok(self.char()?..=self.char()?))
+                    .collect::<Result<Vec<_>>>()?;
+                MatcherType::CharClass(CharClass::new(ranges, inverted))
+            }
+            2 => MatcherType::List(self.ids()?),
+            3 => MatcherType::Choice(self.ids()?, None),
+            4 => {
+                let child = self.id()?;
+                let range = self.usize()?..=self.usize()?.wrapping_sub(1);
+                MatcherType::Repeating(child, range, None)
+            }
+            5 => MatcherType::Inverted(self.id()?),
+            6 => MatcherType::Lookahead(self.id()?),
+            7 => MatcherType::Labeled(self.str()?.into(), self.id()?),
+            8 => {
+                let operand = self.id()?;
+                let separator = match self.bool()? {
+                    true => Some(self.id()?),
+                    false => None,
+                };
+                let count = self.usize()?;
+                let operators = (0..count)
+                    .map(|_| {
+                        Ok(Operator {
+                            matcher: self.id()?,
+                            precedence: self.precedence(count)?,
+                            fixity: match self.byte()? {
+                                0 => Fixity::Prefix,
+                                1 => Fixity::Infix(Associativity::Left),
+                                2 => Fixity::Infix(Associativity::Right),
+                                3 => Fixity::Infix(Associativity::None),
+                                4 => Fixity::Postfix,
+                                _ => return Err(self.invalid()),
+                            },
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let mut table = OperatorTable::new(operand, separator, operators);
+                for label in table.labels.iter_mut() {
+                    *label = Arc::from(self.str()?);
+                }
+                MatcherType::Precedence(table.into())
+            }
+            9 => MatcherType::Wrapper(self.id()?),
+            10 => MatcherType::Cut,
+            11 => MatcherType::Eof,
+            12 => MatcherType::Newline,
+            13 => {
+                return Err(error(
+                    "Serialized lexer has a rule that was never defined",
+                    self.pos - 1,
+                ))
+            }
+            _ => return Err(self.invalid()),
+        };
+        Ok(Matcher {
+            cull_strategy,
+            name: name.into(),
+            id,
+            matcher_type,
+            show_in_errors,
+            left_recursive: false,
+            memoize,
+            recover,
+            location: None,
+        })
+    }
+}
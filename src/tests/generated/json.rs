@@ -0,0 +1,101 @@
+// Generated by flux_bnf. Don't edit it by hand.
+
+pub fn lexer() -> &'static ::flux_bnf::lexer::Lexer {
+    use ::flux_bnf::codegen::{StaticMatcher as M, StaticMatcherType as T};
+    use ::flux_bnf::lexer::CullStrategy as C;
+
+    static NAMES: &[(&str, usize)] = &[
+        ("root", 0),
+        ("sep", 1),
+        ("object", 2),
+        ("string", 3),
+        ("escape", 4),
+        ("integer", 5),
+        ("decimal", 6),
+        ("boolean", 7),
+        ("list", 8),
+        ("map", 9),
+        ("mapEntry", 10),
+        ("null", 11),
+    ];
+    static MATCHERS: &[M] = &[
+        M { name: Some("root"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Wrapper(2) },
+        M { name: Some("sep"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Repeating(12, 0, usize::MAX) },
+        M { name: Some("object"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Choice(&[7, 11, 3, 6, 5, 9, 8]) },
+        M { name: Some("string"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[23, 24, 25]) },
+        M { name: Some("escape"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[26, 27]) },
+        M { name: Some("integer"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[30, 31]) },
+        M { name: Some("decimal"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[35, 36, 37, 38]) },
+        M { name: Some("boolean"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Choice(&[39, 40]) },
+        M { name: Some("list"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[1, 50, 1, 52, 1, 54, 1]) },
+        M { name: Some("map"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[64, 1, 66, 1, 68, 1]) },
+        M { name: Some("mapEntry"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::List(&[3, 1, 72, 1, 2]) },
+        M { name: Some("null"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::String("null", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[('\t', '\n'), (' ', ' ')], false) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(7) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(11) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(3) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(6) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(5) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(9) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(8) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(4) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[('"', '"')], true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Choice(&[4, 21]) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("\"", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(22, 1, usize::MAX) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("\"", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("\\", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[], true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("-", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[('0', '9')], false) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(28, 0, 1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(29, 1, usize::MAX) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("-", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[('0', '9')], false) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[('0', '9')], false) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(32, 0, 1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(33, 1, usize::MAX) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String(".", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(34, 1, usize::MAX) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("true", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("false", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String(",", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(2) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::List(&[41, 1, 2]) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(2) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(44, 0, usize::MAX) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::List(&[2, 1, 47]) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("[", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(48, 0, 1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("]", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String(",", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(10) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::List(&[56, 1, 10]) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(10) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(59, 0, usize::MAX) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::List(&[10, 1, 62]) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("{", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Repeating(63, 0, 1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("}", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(3) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String(":", true) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::None, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(2) },
+    ];
+
+    static LEXER: ::std::sync::OnceLock<::flux_bnf::lexer::Lexer> = ::std::sync::OnceLock::new();
+    LEXER.get_or_init(|| ::flux_bnf::codegen::load(0, false, NAMES, MATCHERS))
+}
@@ -0,0 +1,46 @@
+// Generated by flux_bnf. Don't edit it by hand.
+
+pub fn lexer() -> &'static ::flux_bnf::lexer::Lexer {
+    use ::flux_bnf::codegen::{StaticMatcher as M, StaticMatcherType as T};
+    use ::flux_bnf::lexer::CullStrategy as C;
+    use ::flux_bnf::precedence::{Associativity as A, Fixity as F};
+
+    static NAMES: &[(&str, usize)] = &[
+        ("root", 0),
+        ("expr", 1),
+        ("term", 2),
+        ("number", 3),
+        ("sep", 4),
+    ];
+    static MATCHERS: &[M] = &[
+        M { name: Some("root"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: Some("expr"), cull_strategy: C::LiftAtMost(1), show_in_errors: true, memoize: false, recover: None, matcher_type: T::Precedence(2, Some(4), &[(7, 1, F::Infix(A::Left)), (8, 2, F::Infix(A::None)), (9, 2, F::Infix(A::None)), (10, 2, F::Infix(A::None)), (11, 3, F::Infix(A::Left)), (12, 3, F::Infix(A::Left)), (13, 4, F::Infix(A::Left)), (14, 4, F::Infix(A::Left)), (15, 5, F::Infix(A::Right)), (16, 6, F::Prefix), (17, 6, F::Prefix), (18, 7, F::Postfix)]) },
+        M { name: Some("term"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Choice(&[3, 23]) },
+        M { name: Some("number"), cull_strategy: C::None, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Repeating(24, 1, usize::MAX) },
+        M { name: Some("sep"), cull_strategy: C::DeleteAll, show_in_errors: true, memoize: false, recover: None, matcher_type: T::Repeating(25, 1, usize::MAX) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(2) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(4) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("||", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("==", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("<", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("<=", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("+", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("-", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("*", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("/", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("^", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("-", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("!", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("?", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String("(", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(1) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String(")", true) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::Wrapper(3) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::List(&[19, 1, 21]) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::CharClass(&[('0', '9')], false) },
+        M { name: None, cull_strategy: C::LiftChildren, show_in_errors: false, memoize: false, recover: None, matcher_type: T::String(" ", true) },
+    ];
+
+    static LEXER: ::std::sync::OnceLock<::flux_bnf::lexer::Lexer> = ::std::sync::OnceLock::new();
+    LEXER.get_or_init(|| ::flux_bnf::codegen::load(0, false, NAMES, MATCHERS))
+}
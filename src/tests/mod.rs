@@ -1,4 +1,17 @@
+use std::collections::HashMap;
+
+use crate::analysis::{DiagnosticKind, Severity};
 use crate::bnf;
+use crate::char_class::CharClass;
+use crate::error::FluxError;
+use crate::grammar::{choice, optional, range, repeat, rule, seq, Expr, Grammar};
+use crate::incremental::IncrementalTree;
+use crate::lexer::{CullStrategy, Lexer};
+use crate::loader::{FileLoader, MemoryLoader};
+use crate::matchers::{Matcher, MatcherType};
+use crate::position::{self, LineCol, LineIndex};
+use crate::tokens::Token;
+use crate::trace::{TextTracer, TraceEvent, Tracer};
 
 // static FENDER_BNF: &'static str = include_str!("bnf/fender.bnf");
 
@@ -10,13 +23,11 @@ fn syntax() {
 }
 
 #[test]
-#[ignore = "not yet implemented"]
 fn self_reference_test1() {
     bnf::parse("root ::= root").unwrap_err();
 }
 
 #[test]
-#[ignore = "not yet implemented"]
 fn self_reference_test2() {
     bnf::parse("root ::= test\ntest ::= test").unwrap_err();
 }
@@ -152,6 +163,298 @@ fn alt_root_test() {
     lexer.check_with("digit", "40").unwrap_err();
 }
 
+#[test]
+fn lookahead() {
+    let lexer = bnf::parse("root ::= &\"a\" [a-z]+").unwrap();
+    lexer.check("abc").unwrap();
+    lexer.check("bc").unwrap_err();
+
+    let lexer = bnf::parse("root ::= &[0-9] number\nnumber ::= [0-9]+").unwrap();
+    let children = lexer.tokenize("123", |t| t.children.len()).unwrap();
+    assert_eq!(children, 1);
+
+    let lexer = bnf::parse("root ::= (\"x\"* &\"a\" | \"b\") [a-z]").unwrap();
+    lexer.check("a").unwrap();
+    lexer.check("xa").unwrap();
+    lexer.check("bc").unwrap();
+    lexer.check("c").unwrap_err();
+
+    let lexer = bnf::parse("root ::= &(\"a\" \"b\") \"a\" \"c\"").unwrap();
+    assert_eq!(lexer.check("ab").unwrap_err().location, 1);
+
+    let lexer = bnf::parse("root ::= !(!word) word\nword ::= [a-z]+").unwrap();
+    let words = lexer
+        .tokenize("abc", |t| t.children_named("word").count())
+        .unwrap();
+    assert_eq!(words, 1);
+
+    let lexer = bnf::parse("root ::= (&(\"a\"?) \"b\" | \"c\")+").unwrap();
+    lexer.check("bcb").unwrap();
+}
+
+#[test]
+fn char_class() {
+    let lexer = bnf::parse("root ::= [a-zA-Z0-9_]+").unwrap();
+    lexer.check("abc_XYZ_019").unwrap();
+    lexer.check("-").unwrap_err();
+    lexer.check("Q").unwrap();
+
+    let lexer = bnf::parse(r#"root ::= [^a-z\]\-\^]"#).unwrap();
+    lexer.check("A").unwrap();
+    lexer.check("q").unwrap_err();
+    lexer.check("]").unwrap_err();
+    lexer.check("-").unwrap_err();
+    lexer.check("^").unwrap_err();
+
+    let lexer = bnf::parse(r#"root ::= [-+\u03b1-\u03c9]+"#).unwrap();
+    lexer.check("+-αβω").unwrap();
+    lexer.check("a").unwrap_err();
+
+    let lexer = bnf::parse("root ::= [^]").unwrap();
+    lexer.check("\u{1F600}").unwrap();
+
+    bnf::parse("root ::= [z-a]").unwrap_err();
+    bnf::parse("root ::= [a-z").unwrap_err();
+}
+
+#[test]
+fn char_class_ranges() {
+    let class = CharClass::new(
+        ['a'..='c', 'b'..='f', 'x'..='x', 'é'..='ü', 'ü'..='ÿ'],
+        false,
+    );
+    assert_eq!(class.ranges(), vec!['a'..='f', 'x'..='x', 'é'..='ÿ']);
+    assert!(class.contains('ë'));
+    assert!(!class.contains('g'));
+}
+
+#[test]
+fn unicode_properties() {
+    let lexer = bnf::parse(r#"root ::= \p{L}+"#).unwrap();
+    lexer.check("héllo").unwrap();
+    lexer.check("Ωμέγα").unwrap();
+    lexer.check("日本").unwrap();
+    lexer.check("1").unwrap_err();
+
+    let lexer = bnf::parse(r#"root ::= [\p{L}_] [\p{L}\p{Nd}_]*"#).unwrap();
+    lexer.check("_x1").unwrap();
+    lexer.check("ß٩").unwrap();
+    lexer.check("9a").unwrap_err();
+
+    let lexer = bnf::parse(r#"root ::= \p{Greek}+ \p{sc=Cyrillic}"#).unwrap();
+    lexer.check("αβγж").unwrap();
+    lexer.check("abcж").unwrap_err();
+
+    let lexer = bnf::parse(r#"root ::= \P{L} [^\p{Lu}] [\P{L}a]"#).unwrap();
+    lexer.check("1a1").unwrap();
+    lexer.check("1aa").unwrap();
+    lexer.check("aa1").unwrap_err();
+    lexer.check("1A1").unwrap_err();
+    lexer.check("1ab").unwrap_err();
+
+    bnf::parse(r#"root ::= \p{Nope}"#).unwrap_err();
+}
+
+#[test]
+fn labeled_fields() {
+    let mut lexer = bnf::parse(
+        "root ::= lhs:number sep? op:[-+] sep? rhs:number\nnumber ::= [0-9]+\nsep ::= \" \"+",
+    )
+    .unwrap();
+    lexer.set_unnamed_rule(CullStrategy::LiftChildren);
+    lexer.add_rule_for_names(["sep"], CullStrategy::DeleteAll);
+    lexer
+        .tokenize("12 + 3", |t| {
+            assert_eq!(t.field("lhs").unwrap().get_match(), "12");
+            assert_eq!(t.field("op").unwrap().get_match(), "+");
+            assert_eq!(
+                t.field("rhs").unwrap().get_name().as_deref(),
+                Some("number")
+            );
+            assert!(t.field("missing").is_none());
+        })
+        .unwrap();
+
+    let mut lexer = bnf::parse(
+        "root ::= item (\",\" item)*\nitem ::= key:word \"=\" value:(word \".\" word)\nword ::= [a-z]+",
+    )
+    .unwrap();
+    lexer.add_rule_for_names(["item"], CullStrategy::LiftChildren);
+    lexer
+        .tokenize("a=b.c,d=e.f", |t| {
+            let keys: Vec<_> = t.fields("key").map(|t| t.get_match()).collect();
+            assert_eq!(keys, ["a", "d"]);
+            let value = t.field("value").unwrap();
+            assert_eq!(value.get_match(), "b.c");
+            assert_eq!(value.children.len(), 3);
+        })
+        .unwrap();
+}
+
+#[test]
+fn cull_annotations() {
+    let lexer = bnf::parse(include_str!("bnf/annotations.bnf")).unwrap();
+    lexer
+        .tokenize("1, (2, 3)", |t| {
+            let names: Vec<_> = t.children.iter().map(|t| t.get_name().clone()).collect();
+            assert_eq!(names, [Some("number".into()), Some("group".into())]);
+            assert!(t.children[1].children.is_empty());
+        })
+        .unwrap();
+
+    let mut lexer = bnf::parse(include_str!("bnf/annotations.bnf")).unwrap();
+    lexer.add_rule_for_names(["sep"], CullStrategy::None);
+    lexer
+        .tokenize("1, 2", |t| assert_eq!(t.children.len(), 3))
+        .unwrap();
+
+    bnf::parse("@nope root ::= \"a\"").unwrap_err();
+    bnf::parse("@lift list<elem> ::= elem+\nroot ::= list<\"a\">").unwrap_err();
+}
+
+#[test]
+fn imports() {
+    let loader = MemoryLoader::new()
+        .with(
+            "lib/common.bnf",
+            "@delete sep ::= \" \"+\nnumber ::= [0-9]+\nlist<elem> ::= elem (sep elem)*",
+        )
+        .with(
+            "words.bnf",
+            "@import \"lib/common.bnf\"\nword ::= [a-z]+ common.number?",
+        );
+
+    let lexer = bnf::parse_with_loader(
+        "@import \"lib/common.bnf\"\nroot ::= common.list<common.number>",
+        &loader,
+    )
+    .unwrap();
+    lexer.check("1 2 3").unwrap();
+    lexer.check("1 a").unwrap_err();
+    lexer.check_with("common.number", "4").unwrap();
+    let names: Vec<_> = lexer
+        .tokenize("1 2", |t| {
+            t.rec_iter()
+                .filter(|t| t.get_name().as_deref() == Some("common.number"))
+                .map(|t| t.get_match().to_string())
+                .collect()
+        })
+        .unwrap();
+    assert_eq!(names, ["1", "2"]);
+
+    let lexer = bnf::parse_with_loader(
+        "@import \"words.bnf\" as w\nroot ::= w.word (\",\" w.common.number)*",
+        &loader,
+    )
+    .unwrap();
+    lexer.check("ab1,2").unwrap();
+    let names: Vec<_> = lexer
+        .tokenize("ab,2", |t| {
+            t.rec_iter().filter_map(|t| t.get_name().clone()).collect()
+        })
+        .unwrap();
+    assert_eq!(names, ["w.word", "w.common.number"]);
+
+    let lexer = bnf::parse_with_loader(
+        "@import \"numbers.bnf\"\nroot ::= numbers.number (\",\" numbers.number)*",
+        &FileLoader::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/bnf")),
+    )
+    .unwrap();
+    lexer.check("1,-2.5").unwrap();
+
+    // Imports are relative to the file they're in, and a file imported twice is parsed once
+    let loaded = std::cell::RefCell::new(Vec::new());
+    let loader = |path: &str| {
+        loaded.borrow_mut().push(path.to_string());
+        let grammar = match path {
+            "lib/a.bnf" => "@import \"./digits.bnf\"\nx ::= \"a\" digits.digit",
+            "lib/b.bnf" => "@import \"../lib/digits.bnf\" as d\ny ::= \"b\" d.digit",
+            "lib/digits.bnf" => "digit ::= [0-9]",
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+        Ok(grammar.to_string())
+    };
+    let lexer = bnf::parse_with_loader(
+        "@import \"lib/a.bnf\"\n@import \"lib/b.bnf\"\nroot ::= a.x b.y",
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(
+        *loaded.borrow(),
+        ["lib/a.bnf", "lib/digits.bnf", "lib/b.bnf"]
+    );
+    let names: Vec<_> = lexer
+        .tokenize("a1b2", |t| {
+            t.rec_iter().filter_map(|t| t.get_name().clone()).collect()
+        })
+        .unwrap();
+    assert_eq!(names, ["a.x", "a.digits.digit", "b.y", "a.digits.digit"]);
+}
+
+#[test]
+fn import_errors() {
+    let loader = MemoryLoader::new()
+        .with("a.bnf", "@import \"b.bnf\"\nx ::= b.y")
+        .with("b.bnf", "// imports a\n@import \"a.bnf\"\ny ::= \"y\"")
+        .with("c.bnf", "x ::= \"x\"");
+
+    let error = bnf::parse_with_loader("@import \"a.bnf\"\nroot ::= a.x", &loader).unwrap_err();
+    assert!(error
+        .description
+        .get_message()
+        .contains("Import cycle: a.bnf -> b.bnf -> a.bnf"));
+    assert_eq!(error.location, 13);
+
+    let error =
+        bnf::parse_with_loader("root ::= \"a\"\n@import \"missing.bnf\"", &loader).unwrap_err();
+    assert_eq!(error.location, 13);
+    bnf::parse("@import \"c.bnf\"\nroot ::= c.x").unwrap_err();
+    bnf::parse_with_loader(
+        "@import \"c.bnf\"\n@import \"c.bnf\"\nroot ::= c.x",
+        &loader,
+    )
+    .unwrap_err();
+    bnf::parse_with_loader("@import \"c.bnf\"\nroot ::= c.y", &loader).unwrap_err();
+    let error = bnf::parse_with_loader("@import \"c.bnf\" c\nroot ::= c.x", &loader).unwrap_err();
+    assert_eq!(error.location, 16);
+    bnf::parse_with_loader("@import \"c.bnf\" // c\nroot ::= c.x", &loader).unwrap();
+
+    let error = bnf::parse("a ::= \"x\"\na ::= \"y\"\nroot ::= a").unwrap_err();
+    assert_eq!(error.location, 10);
+    bnf::parse("t<a> ::= a\nt<b> ::= b\nroot ::= t<\"x\">").unwrap_err();
+}
+
+#[test]
+fn multi_line_rules() {
+    let lexer = bnf::parse(include_str!("bnf/multi_line.bnf")).unwrap();
+    lexer.check("[if,1,while]").unwrap();
+    lexer.check("[else]").unwrap();
+    lexer.check("[x]").unwrap_err();
+    lexer.check_with("other", "x").unwrap();
+
+    let lexer = bnf::parse("root ::= (\n    \"a\"\n    | \"b\"\n    )").unwrap();
+    lexer.check("a").unwrap();
+    lexer.check("b").unwrap();
+}
+
+#[test]
+fn fender_grammar() {
+    let lexer = bnf::parse(include_str!("bnf/fender.bnf")).unwrap();
+    lexer
+        .check(include_str!("../../examples/test_fender.fndr"))
+        .unwrap();
+    lexer
+        .check("$x = -1 + 2 * y ^ 2 >= 3 && !done\nx += [1, 2].len()")
+        .unwrap();
+    lexer
+        .tokenize("$x = a - b - c", |t| {
+            let operation = t.rec_iter().find(|t| t.field("op").is_some()).unwrap();
+            assert!(operation.field("lhs").unwrap().field("op").is_some());
+            assert_eq!(operation.field("rhs").unwrap().get_match(), "c");
+        })
+        .unwrap();
+}
+
 #[test]
 fn newline_test() {
     let lexer = bnf::parse("root ::= <nl>").unwrap();
@@ -159,3 +462,840 @@ fn newline_test() {
     lexer.check("\r\n").unwrap();
     lexer.check("\r").unwrap();
 }
+
+#[test]
+fn left_recursion() {
+    let lexer = bnf::parse(
+        "@unnamed @lift\nroot ::= expr\nexpr ::= expr \"+\" num | expr \"-\" num | num\nnum ::= [0-9]+",
+    )
+    .unwrap();
+    lexer.check("1").unwrap();
+    lexer.check("1+").unwrap_err();
+    lexer
+        .tokenize("1-2+3", |expr| {
+            assert_eq!(expr.children.len(), 2);
+            assert_eq!(expr.children[0].get_match(), "1-2");
+            assert_eq!(expr.children[0].children[0].get_match(), "1");
+            assert_eq!(expr.children[1].get_match(), "3");
+        })
+        .unwrap();
+
+    let lexer = bnf::parse(include_str!("bnf/left_recursion.bnf")).unwrap();
+    lexer.check("a.b(c).d").unwrap();
+    lexer.check("a(b)(c)").unwrap();
+    lexer.check("a.").unwrap_err();
+    let calls: Vec<_> = lexer
+        .tokenize("a.b(c)", |t| {
+            t.rec_iter()
+                .filter(|t| t.get_name().as_deref() == Some("call"))
+                .map(|t| t.field("target").unwrap().get_match().to_string())
+                .collect()
+        })
+        .unwrap();
+    assert_eq!(calls, ["a.b"]);
+}
+
+/// Write out an operation tree with explicit parentheses
+fn parenthesize(token: &Token) -> String {
+    match (token.field("lhs"), token.field("op"), token.field("rhs")) {
+        (Some(lhs), Some(op), Some(rhs)) => format!(
+            "({} {} {})",
+            parenthesize(lhs),
+            op.get_match(),
+            parenthesize(rhs)
+        ),
+        (None, Some(op), Some(rhs)) => format!("({}{})", op.get_match(), parenthesize(rhs)),
+        (Some(lhs), Some(op), None) => format!("({}{})", parenthesize(lhs), op.get_match()),
+        _ => match token.children.first() {
+            Some(child) if token.get_name().as_deref() == Some("term") => parenthesize(child),
+            _ => token.get_match().to_string(),
+        },
+    }
+}
+
+#[test]
+fn operator_precedence() {
+    let lexer = bnf::parse(include_str!("bnf/precedence.bnf")).unwrap();
+    let parse = |input: &str| lexer.tokenize(input, |t| parenthesize(t)).unwrap();
+    assert_eq!(parse("1"), "1");
+    assert_eq!(parse("1 + 2 * 3"), "(1 + (2 * 3))");
+    assert_eq!(parse("1 - 2 - 3"), "((1 - 2) - 3)");
+    assert_eq!(parse("2 ^ 3 ^ 4"), "(2 ^ (3 ^ 4))");
+    assert_eq!(parse("-2 ^ 2 * 3"), "(((-2) ^ 2) * 3)");
+    assert_eq!(parse("1 + 2? * 3"), "(1 + ((2?) * 3))");
+    assert_eq!(parse("1 <= 2 || !3"), "((1 <= 2) || (!3))");
+    assert_eq!(parse("(1 + 2) * 3"), "((1 + 2) * 3)");
+    lexer.check("1 == 2 == 3").unwrap_err();
+    lexer.check("1 + ").unwrap_err();
+
+    lexer
+        .tokenize("1 + 2", |t| {
+            assert_eq!(t.get_name().as_deref(), Some("expr"));
+            let labels: Vec<_> = t.children.iter().map(|c| c.get_label()).collect();
+            assert_eq!(labels, [Some("lhs"), Some("op"), Some("rhs")]);
+            assert_eq!(t.field("lhs").unwrap().get_name().as_deref(), Some("term"));
+        })
+        .unwrap();
+
+    bnf::parse("root ::= @precedence(\"a\")").unwrap_err();
+    bnf::parse("root ::= @precedence(\"a\")\n    middle \"+\"").unwrap_err();
+}
+
+#[test]
+fn cut() {
+    let grammar = "root ::= statement+
+statement ::= import | call
+import ::= \"import \" ~ path \";\"
+call ::= [a-z ]+ \"();\"
+path ::= [a-z]+";
+    let lexer = bnf::parse(grammar).unwrap();
+    lexer.check("import foo;x();").unwrap();
+    let error = lexer.check("x();import f();").unwrap_err();
+    assert_eq!(error.location, 12);
+    bnf::parse(&grammar.replace('~', ""))
+        .unwrap()
+        .check("x();import f();")
+        .unwrap();
+
+    let lexer = bnf::parse("root ::= \"x\" ~ \"y\" | \"xz\" | (!(\"a\" ~ \"b\") [a-z])+").unwrap();
+    lexer.check("ac").unwrap();
+    lexer.check("ab").unwrap_err();
+    lexer.check("xy").unwrap();
+    lexer.check("xz").unwrap_err();
+}
+
+#[test]
+fn grammar_diagnostics() {
+    let diagnostics = bnf::analyze(include_str!("bnf/diagnostics.bnf")).unwrap();
+    let found: Vec<_> = diagnostics
+        .iter()
+        .map(|d| (d.kind, d.location, d.severity))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (DiagnosticKind::UnusedTemplate, 115, Severity::Warning),
+            (DiagnosticKind::NullableRepetition, 13, Severity::Warning),
+            (DiagnosticKind::ShadowedAlternative, 40, Severity::Warning),
+            (DiagnosticKind::ShadowedAlternative, 54, Severity::Warning),
+            (DiagnosticKind::ShadowedAlternative, 77, Severity::Warning),
+            (DiagnosticKind::InfiniteRecursion, 81, Severity::Error),
+            (DiagnosticKind::UnreachableRule, 81, Severity::Warning),
+        ]
+    );
+    let error = bnf::parse(include_str!("bnf/diagnostics.bnf")).unwrap_err();
+    assert_eq!(error.location, 81);
+    assert_eq!(
+        diagnostics[5].to_string(),
+        "error: Rule loop can never match, because it can't avoid referring back to itself \
+         at line 4 col 1"
+    );
+    assert!(error.to_string().contains("at line 4 col 1"));
+
+    assert!(bnf::analyze(include_str!("bnf/fender.bnf"))
+        .unwrap()
+        .is_empty());
+    bnf::parse("root ::= \"a\"\nt<a> ::= a undefined").unwrap_err();
+    bnf::parse("root ::= t<\"a\">\nt<a> ::= a t<a>?").unwrap_err();
+
+    let kinds = |grammar| -> Vec<_> {
+        let diagnostics = bnf::analyze(grammar).unwrap();
+        diagnostics.into_iter().map(|d| d.kind).collect()
+    };
+    assert_eq!(kinds("root ::= \"a\" | i\"ab\""), []);
+    assert_eq!(
+        kinds("root ::= i\"a\" | \"ab\""),
+        [DiagnosticKind::ShadowedAlternative]
+    );
+}
+
+#[test]
+fn bnf_round_trip() {
+    let grammars = [
+        (
+            include_str!("bnf/fender.bnf"),
+            include_str!("../../examples/test_fender.fndr"),
+        ),
+        (include_str!("bnf/precedence.bnf"), "-2 ^ 2 * 3 + 4"),
+        (include_str!("bnf/left_recursion.bnf"), "a.b(c).d"),
+        (
+            include_str!("bnf/json.bnf"),
+            "[1, 2.5, \"\\\"x\", null]",
+        ),
+        (
+            "root ::= i\"\\\"q\\\"\"? [^\\]\\-\\^\\n\\u0001a-c]{2,3} l:!\"x\" ~ [d-z]* (&\"y\" | <eof>)\n",
+            "\"Q\"dd",
+        ),
+    ];
+    for (grammar, input) in grammars {
+        let lexer = bnf::parse(grammar).unwrap();
+        let printed = lexer.to_bnf();
+        let reparsed = bnf::parse(&printed).unwrap();
+        assert_eq!(reparsed.to_bnf(), printed);
+        let tree = |t: &mut Token| format!("{t:?}");
+        assert_eq!(
+            lexer.tokenize(input, tree).unwrap(),
+            reparsed.tokenize(input, tree).unwrap()
+        );
+    }
+
+    let loader = MemoryLoader::new().with("common.bnf", "@delete sep! ::= \" \"+");
+    let lexer = bnf::parse_with_loader(
+        "@import \"common.bnf\"\nroot ::= \"a\" common.sep \"b\"",
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(
+        lexer.to_bnf(),
+        "@delete common.sep! ::= \" \"+\nroot ::= \"a\" common.sep \"b\"\n"
+    );
+    let lexer = bnf::parse_with_loader(
+        "@import \"common.bnf\"\nroot ::= common.sep common_sep\ncommon_sep ::= \"x\"",
+        &loader,
+    )
+    .unwrap();
+    let reparsed = bnf::parse(&lexer.to_bnf()).unwrap();
+    reparsed.check("  x").unwrap();
+    reparsed.check_with("common.sep", " ").unwrap();
+}
+
+#[test]
+fn grammar_export() {
+    let lexer = bnf::parse(
+        "root ::= word (sep ~ word)* <nl>? <eof>\n\
+         sep ::= \" \"+\n\
+         word ::= i\"a'\\\"\" | !\"x\" [a-z]{2,3}",
+    )
+    .unwrap();
+    let ebnf = lexer.to_ebnf();
+    assert_eq!(
+        ebnf.text,
+        "root ::= word (sep word)* (#xD #xA? | #xA)? /* <eof> */\n\
+         sep ::= \" \"+\n\
+         word ::= [Aa] \"'\" '\"' | /* !\"x\" */ ([a-z] [a-z] [a-z]?)\n"
+    );
+    assert_eq!(
+        ebnf.untranslated,
+        [
+            "Cut in rule root was left out",
+            "End of input in rule root was left as a comment",
+            "Negative lookahead in rule word was left as a comment",
+        ]
+    );
+
+    let antlr = lexer.to_antlr("Words");
+    assert_eq!(
+        antlr.text,
+        "grammar Words;\n\n\
+         root : word (Sep word)* ('\\r' '\\n'? | '\\n')? EOF ;\n\
+         word : TOKEN_1 | /* !'x' */ (TOKEN_2 TOKEN_2 TOKEN_2?) ;\n\n\
+         Sep : ' '+ ;\n\
+         TOKEN_1 : [Aa] '\\'\"' ;\n\
+         TOKEN_2 : [a-z] ;\n"
+    );
+    assert_eq!(antlr.untranslated.len(), 2);
+
+    let lexer = bnf::parse(include_str!("bnf/precedence.bnf")).unwrap();
+    let antlr = lexer.to_antlr("Expr");
+    assert!(antlr.text.contains(
+        "expr : expr Sep? '?'\n    \
+         | ('-' | '!') Sep? expr\n    \
+         | <assoc=right> expr Sep? '^' Sep? expr\n    \
+         | expr Sep? ('*' | '/') Sep? expr\n    \
+         | expr Sep? ('+' | '-') Sep? expr\n    \
+         | expr Sep? ('==' | '<' | '<=') Sep? expr\n    \
+         | expr Sep? '||' Sep? expr\n    \
+         | term ;\n"
+    ));
+    assert_eq!(
+        antlr.untranslated,
+        ["Non-associative operators in rule expr were made left-associative"]
+    );
+}
+
+#[test]
+fn railroad_diagrams() {
+    let lexer = bnf::parse(
+        "root ::= (item sep?)* <eof>\n\
+         item ::= !\"#\" [a-z]{2,4} | key:\"x\" <nl>\n\
+         sep ::= \" \"+",
+    )
+    .unwrap();
+    let html = lexer.to_railroad_html("Items <test>");
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>Items &lt;test&gt;</title>"));
+    assert_eq!(html.matches("<svg").count(), 3);
+    assert!(html.contains("<section id=\"rule-item\">"));
+    assert!(html.contains("Used by <a href=\"#rule-root\">root</a>"));
+    assert!(html.contains("<a href=\"#rule-sep\"><g class=\"rule\">"));
+    for text in [
+        "&lt;eof&gt;",
+        "&lt;nl&gt;",
+        "[a-z]",
+        "&quot;#&quot;",
+        "not",
+        "key:",
+    ] {
+        assert!(html.contains(&format!(">{text}</text>")), "{text}");
+    }
+    assert!(html.contains(">2 to 4 times</text>"));
+    assert!(!html.contains("<script"));
+}
+
+#[test]
+fn abnf_grammar() {
+    let mut lexer = bnf::parse_abnf(include_str!("bnf/headers.abnf")).unwrap();
+    lexer.set_unnamed_rule(CullStrategy::LiftChildren);
+    let input = "PUT /a/b HTTP/1.1\r\nHost: example.com\r\nx-Tag:  a b  \r\n\r\n";
+    lexer
+        .tokenize(input, |t| {
+            let names: Vec<_> = t.children.iter().map(|t| t.get_name().clone()).collect();
+            assert_eq!(
+                names
+                    .iter()
+                    .map(|n| n.as_deref().unwrap())
+                    .collect::<Vec<_>>(),
+                [
+                    "start-line",
+                    "header-field",
+                    "CRLF",
+                    "header-field",
+                    "CRLF",
+                    "CRLF"
+                ]
+            );
+            let value = t.children[3]
+                .children
+                .iter()
+                .find(|t| t.get_name().as_deref() == Some("field-value"))
+                .unwrap();
+            assert_eq!(value.get_match(), "a b");
+        })
+        .unwrap();
+
+    // Quoted strings are case-insensitive, but %s strings aren't
+    assert!(lexer.check("get /\r\n\r\n").is_err());
+    assert!(lexer.check("GET / http/1.0\r\n\r\n").is_ok());
+
+    // Names BNF can't have are renamed so they don't clash
+    let lexer = bnf::parse_abnf("root = x-1 x-2\nx-1 = \"x\"\nx-2 = \"y\"").unwrap();
+    let printed = lexer.to_bnf();
+    assert!(printed.contains("root ::= x__ x___\n"));
+    bnf::parse(&printed).unwrap().check("xy").unwrap();
+
+    for (grammar, error) in [
+        ("a = b", "No rule with name b"),
+        ("a = <prose>", "Prose values like <...> can't be parsed"),
+        (
+            "a =/ \"x\"",
+            "Rule a is extended with =/ before it's defined",
+        ),
+        ("a = \"x\"\nA = \"y\"", "Rule A is defined more than once"),
+        ("a = 3*2\"x\"", "Invalid repetition"),
+    ] {
+        let message = bnf::parse_abnf(grammar).unwrap_err().to_string();
+        assert!(message.contains(error), "{message}");
+    }
+}
+
+#[test]
+fn grammar_builder() {
+    let lexer = Grammar::new()
+        .unnamed(CullStrategy::LiftChildren)
+        .rule("root", rule("expr"))
+        .rule(
+            "expr",
+            crate::grammar::precedence(rule("term"))
+                .separator(rule("sep"))
+                .left(["||".into()])
+                .none(["==".into(), "<".into(), "<=".into()])
+                .left(["+".into(), "-".into()])
+                .left(["*".into(), "/".into()])
+                .right(["^".into()])
+                .prefix(["-".into(), "!".into()])
+                .postfix(["?".into()]),
+        )
+        .cull(CullStrategy::LiftAtMost(1))
+        .rule(
+            "term",
+            choice([rule("number"), seq(["(".into(), rule("expr"), ")".into()])]),
+        )
+        .rule("number", repeat(range('0', '9'), 1..))
+        .rule("sep", repeat(" ", 1..))
+        .cull(CullStrategy::DeleteAll)
+        .build()
+        .unwrap();
+    let text = bnf::parse(include_str!("bnf/precedence.bnf")).unwrap();
+    assert_eq!(lexer.to_bnf(), text.to_bnf());
+    let parse = |input: &str| lexer.tokenize(input, |t| parenthesize(t)).unwrap();
+    assert_eq!(parse("-2 ^ 2 * 3"), "(((-2) ^ 2) * 3)");
+
+    // Templates are plain functions
+    fn delimited(item: Expr, separator: &str) -> Expr {
+        seq([item.clone(), repeat(seq([separator.into(), item]), 0..)])
+    }
+    let grammar = Grammar::new()
+        .rule("root", delimited(rule("word"), ","))
+        .rule("word", seq([range('a', 'z'), optional(range('0', '9'))]));
+    grammar.build().unwrap().check("a,b1,c").unwrap();
+
+    let max = 2;
+    for (grammar, error) in [
+        (
+            grammar.clone().rule("word", "x"),
+            "Rule word is defined more than once",
+        ),
+        (
+            Grammar::new().rule("root", rule("missing")),
+            "No rule with name missing",
+        ),
+        (
+            Grammar::new().rule("word", "x"),
+            "No root matcher specified",
+        ),
+        (
+            Grammar::new().rule("root", rule("root")),
+            "Rule root can never match",
+        ),
+        (
+            Grammar::new().rule("root", repeat("x", 3..=max)),
+            "Invalid repetition range",
+        ),
+        (
+            Grammar::new().rule(
+                "root",
+                seq([
+                    "x".into(),
+                    crate::grammar::precedence("y").left(["+".into()]).into(),
+                ]),
+            ),
+            "A precedence table has to be the whole body of a rule",
+        ),
+    ] {
+        let message = grammar.build().unwrap_err().to_string();
+        assert!(message.contains(error), "{message}");
+    }
+}
+
+mod compiled {
+    pub mod json {
+        include!("generated/json.rs");
+    }
+    pub mod precedence {
+        include!("generated/precedence.rs");
+    }
+}
+
+#[test]
+fn compiled_grammar() {
+    for (grammar, generated, compiled, input) in [
+        (
+            include_str!("bnf/json.bnf"),
+            include_str!("generated/json.rs"),
+            compiled::json::lexer(),
+            "{\"a\": [1, 2.5, \"\\\"x\", null], \"b\": true}",
+        ),
+        (
+            include_str!("bnf/precedence.bnf"),
+            include_str!("generated/precedence.rs"),
+            compiled::precedence::lexer(),
+            "-2 ^ 2 * (3 + 4)?",
+        ),
+    ] {
+        let lexer = bnf::parse(grammar).unwrap();
+        assert_eq!(lexer.to_rust(), generated);
+        assert_eq!(
+            lexer.tokenize(input, |t| format!("{t:?}")).unwrap(),
+            compiled.tokenize(input, |t| format!("{t:?}")).unwrap()
+        );
+    }
+    assert!(std::ptr::eq(
+        compiled::json::lexer(),
+        compiled::json::lexer()
+    ));
+}
+
+#[test]
+fn lexer_serialization() {
+    for (grammar, input) in [
+        (include_str!("bnf/json.bnf"), "[1, 2.5, \"\\\"x\", null]"),
+        (include_str!("bnf/precedence.bnf"), "-2 ^ 2 * (3 + 4)?"),
+        (include_str!("bnf/left_recursion.bnf"), "a.b(c).d"),
+    ] {
+        let lexer = bnf::parse(grammar).unwrap();
+        let bytes = lexer.to_bytes();
+        let loaded = Lexer::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+        assert_eq!(
+            lexer.tokenize(input, |t| format!("{t:?}")).unwrap(),
+            loaded.tokenize(input, |t| format!("{t:?}")).unwrap()
+        );
+    }
+
+    let bytes = bnf::parse("root ::= [a-z]+").unwrap().to_bytes();
+    let error = |bytes: &[u8]| Lexer::from_bytes(bytes).unwrap_err().to_string();
+    let mut corrupted = bytes.clone();
+    corrupted[8] ^= 1;
+    assert!(error(&corrupted).contains("checksum doesn't match"));
+    let mut newer = bytes.clone();
+    newer[4] += 1;
+    assert!(error(&newer).contains(&format!("format version {}", bytes[4] + 1)));
+    assert!(error(b"not a lexer").contains("Not a serialized lexer"));
+    assert!(error(&bytes[..bytes.len() - 1]).contains("checksum doesn't match"));
+
+    // Data with a valid checksum can still describe a lexer that can't be run
+    let bytes = bnf::parse(include_str!("bnf/precedence.bnf"))
+        .unwrap()
+        .to_bytes();
+    let parts = crate::serialize::read(&bytes).unwrap();
+    let write = |matchers: &[Matcher]| crate::serialize::write(0, false, &HashMap::new(), matchers);
+    let mut matchers = parts.matchers.clone();
+    matchers[0].matcher_type = MatcherType::Placeholder;
+    assert!(error(&write(&matchers)).contains("rule that was never defined"));
+    let mut matchers = parts.matchers.clone();
+    for matcher in &mut matchers {
+        if let MatcherType::Precedence(table) = &mut matcher.matcher_type {
+            table.operators[0].precedence = 0;
+        }
+    }
+    assert!(error(&write(&matchers)).contains("invalid precedence"));
+}
+
+#[test]
+fn memoization() {
+    let grammar = "root ::= a <eof>\na ::= b \"x\" | b \"y\" | b\nb ::= \"(\" a \")\" | \"z\"";
+    let input = format!("{}z{}", "(".repeat(8), ")".repeat(8));
+    let mut lexer = bnf::parse(grammar).unwrap();
+    lexer.check(&input).unwrap();
+    let stats = lexer.memo_stats(&input);
+    let b = stats.rule("b").unwrap();
+    assert!(b.applications > 3usize.pow(8));
+    assert_eq!(b.positions, 9);
+    assert_eq!(stats.memo_entries(), 0);
+
+    lexer.memoize_rules(["b"]);
+    assert!(lexer.to_bnf().contains("@memo b ::="));
+    let stats = lexer.memo_stats(&input);
+    let b = stats.rule("b").unwrap();
+    assert_eq!(b.applications, 3 * 9);
+    assert_eq!(b.memo_hits, 2 * 9);
+    assert_eq!(b.memo_entries, 9);
+    assert!(stats.memo_bytes() > 0);
+    lexer.check(&input).unwrap();
+    lexer.check("((z)").unwrap_err();
+    let reparsed = bnf::parse(&lexer.to_bnf()).unwrap();
+    assert_eq!(
+        reparsed.memo_stats(&input).rule("b").unwrap().memo_entries,
+        9
+    );
+
+    // Memo entries share their tokens rather than copying the tokens of every entry inside
+    // them, so the table grows linearly with the nesting
+    let bytes = |depth: usize| {
+        let input = format!("{}z{}", "(".repeat(depth), ")".repeat(depth));
+        lexer.memo_stats(input).memo_bytes()
+    };
+    assert!(bytes(64) < 3 * bytes(32));
+
+    // Entries from before a cut are thrown away once it's passed
+    let entries = |grammar: &str| {
+        let mut lexer = bnf::parse(grammar).unwrap();
+        lexer.memoize_all();
+        lexer.check("ab,cd,ef,").unwrap();
+        lexer.memo_stats("ab,cd,ef,").memo_entries()
+    };
+    assert_eq!(entries("root ::= (word \",\")*\nword ::= [a-z]+"), 5);
+    assert_eq!(entries("root ::= (word ~ \",\")*\nword ::= [a-z]+"), 1);
+
+    for (grammar, input) in [
+        (
+            include_str!("bnf/fender.bnf"),
+            include_str!("../../examples/test_fender.fndr"),
+        ),
+        (include_str!("bnf/json.bnf"), "[1, 2.5, \"\\\"x\", null]"),
+        (include_str!("bnf/precedence.bnf"), "-2 ^ 2 * (3 + 4)?"),
+        (include_str!("bnf/left_recursion.bnf"), "a.b(c).d"),
+    ] {
+        let lexer = bnf::parse(grammar).unwrap();
+        let mut memoized = lexer.clone();
+        memoized.memoize_all();
+        assert_eq!(
+            lexer.tokenize(input, |t| format!("{t:?}")).unwrap(),
+            memoized.tokenize(input, |t| format!("{t:?}")).unwrap()
+        );
+        let error = |lexer: &Lexer| lexer.check(&input[..input.len() / 2]).unwrap_err();
+        assert_eq!(error(&lexer).to_string(), error(&memoized).to_string());
+    }
+
+    bnf::parse("@memo list<x> ::= x+\nroot ::= list<\"a\">").unwrap_err();
+}
+
+#[test]
+fn byte_positions() {
+    let lexer = bnf::parse("root ::= (word | <nl> | \" \")+\nword ::= [\\p{L}]+").unwrap();
+    lexer
+        .tokenize("héllo wörld\nçava", |t| {
+            let words: Vec<_> = t
+                .rec_iter()
+                .filter(|t| t.get_name().as_deref() == Some("word"))
+                .collect();
+            assert_eq!(words[1].get_match(), "wörld");
+            assert_eq!(words[1].range, 7..13);
+            assert_eq!(words[1].char_range(), 6..11);
+            assert_eq!(words[2].line_col(), LineCol { line: 2, column: 1 });
+        })
+        .unwrap();
+    let error = lexer.check("héllo wörld\nça1va").unwrap_err();
+    assert_eq!(error.location, 17);
+    assert!(format!("{error:#}").contains("at line 2 col 3"));
+
+    let text = "añb\n\nc";
+    let index = LineIndex::new(text);
+    assert_eq!(index.line_col(3), LineCol { line: 1, column: 3 });
+    assert_eq!(index.line_col(6), LineCol { line: 3, column: 1 });
+    assert_eq!(index.offset(LineCol { line: 1, column: 4 }), Some(4));
+    assert_eq!(index.offset(LineCol { line: 2, column: 1 }), Some(5));
+    assert_eq!(index.offset(LineCol { line: 2, column: 2 }), None);
+    assert_eq!(position::byte_to_char(text, 3), 2);
+    assert_eq!(position::char_to_byte(text, 3), Some(4));
+    assert_eq!(position::char_to_byte(text, 7), None);
+
+    let error = bnf::parse("ñ ::= \"a\"\nroot ::= ñ x").unwrap_err();
+    assert_eq!(error.location, 24);
+}
+
+#[test]
+#[allow(clippy::single_range_in_vec_init)]
+fn incremental_reparsing() {
+    let lexer = bnf::parse(include_str!("bnf/fender.bnf")).unwrap();
+    let fresh = |text: &str| lexer.tokenize(text, |t| format!("{t:?}")).unwrap();
+    let tokens = |tree: &IncrementalTree| tree.tokenize(|t| format!("{t:?}")).unwrap();
+    let mut text = String::from("$a = 1 + 2\n$b = a * 3\nb += [1, 2].len()\n$c = !done\n");
+    let mut tree = lexer.incremental(&text);
+    assert_eq!(tokens(&tree), fresh(&text));
+    let full = tree.rematched_rules();
+
+    let mut edit = |tree: &mut IncrementalTree, range: std::ops::Range<usize>, with: &str| {
+        text.replace_range(range.clone(), with);
+        let changed = tree.edit(range, with);
+        assert_eq!(tree.text(), text);
+        if tree.error().is_none() {
+            assert_eq!(tokens(tree), fresh(&text));
+        }
+        changed
+    };
+    assert_eq!(edit(&mut tree, 20..21, "42"), [20..22]);
+    assert!(tree.rematched_rules() * 4 < full);
+    let changed = edit(&mut tree, 23..23, "$d = 5\n");
+    assert_eq!(changed, [23..30]);
+    edit(&mut tree, 38..38, "x");
+    assert!(tree.error().is_some());
+    assert!(tree.tokenize(|_| ()).is_err());
+    assert_eq!(edit(&mut tree, 38..39, ""), [0..59]);
+    assert!(tree.error().is_none());
+    assert_eq!(edit(&mut tree, 59..59, "$e = 1"), [59..65]);
+    edit(&mut tree, 0..11, "");
+
+    // Reused matches keep their children, and take the labels of where they're reused
+    let lexer = bnf::parse(
+        "root ::= (pair | key:word) (\",\" (pair | key:word))*\n\
+         pair ::= key:word \"=\" value:word\n\
+         word ::= ([a-z] | digit)+\n\
+         digit ::= [0-9]",
+    )
+    .unwrap();
+    let fresh = |text: &str| lexer.tokenize(text, |t| format!("{t:?}")).unwrap();
+    let mut tree = lexer.incremental("a1=b2,c3");
+    tree.edit(2..2, "x");
+    assert_eq!(tokens(&tree), fresh("a1x=b2,c3"));
+    tree.edit(0..4, "");
+    assert_eq!(tokens(&tree), fresh("b2,c3"));
+    assert!(tokens(&tree).contains("label: \"key\", match: \"b2\""));
+}
+
+#[test]
+fn error_recovery() {
+    let grammar = r#"
+root ::= (statement ";")*
+@recover(";") statement ::= "let " name " = " number
+name ::= [a-z]+
+number ::= [0-9]+
+"#;
+    let skipped = |t: &mut Token| -> Vec<String> {
+        t.rec_iter()
+            .filter(|t| t.is_error())
+            .map(|t| t.get_match().to_string())
+            .collect()
+    };
+    let locations =
+        |errors: Vec<FluxError>| -> Vec<usize> { errors.iter().map(|e| e.location).collect() };
+    let lexer = bnf::parse(grammar).unwrap();
+    let built = Grammar::new()
+        .rule("root", repeat(seq([rule("statement"), ";".into()]), 0..))
+        .rule(
+            "statement",
+            seq(["let ".into(), rule("name"), " = ".into(), rule("number")]),
+        )
+        .recover(";")
+        .rule("name", repeat(range('a', 'z'), 1..))
+        .rule("number", repeat(range('0', '9'), 1..))
+        .build()
+        .unwrap();
+    assert_eq!(built.to_bnf(), lexer.to_bnf());
+    assert!(lexer.to_bnf().contains("@recover(\";\") statement ::="));
+    let reparsed = bnf::parse(&lexer.to_bnf()).unwrap();
+    let deserialized = Lexer::from_bytes(&lexer.to_bytes()).unwrap();
+    let mut memoized = lexer.clone();
+    memoized.memoize_all();
+    let input = "let a = 1;let b = x;let = 2;let c = 3;";
+    for lexer in [&lexer, &reparsed, &deserialized, &memoized] {
+        let (tokens, errors) = lexer.tokenize_recovering(input, skipped);
+        assert_eq!(tokens, ["let b = x", "let = 2"]);
+        assert_eq!(locations(errors), [18, 24]);
+    }
+    let (tokens, errors) = lexer.tokenize_recovering("let a = 1;", |t| format!("{t:?}"));
+    assert_eq!(
+        Ok(tokens),
+        lexer.tokenize("let a = 1;", |t| format!("{t:?}"))
+    );
+    assert!(errors.is_empty());
+    // Missing the ";" after the skipped text, the rest of the input is skipped at the root
+    let (root, errors) = lexer.tokenize_recovering("%", |t| format!("{t:?}"));
+    assert_eq!(root, "Token { name: Some(\"root\"), match: \"%\", range: 0..1, children: [Token { name: Some(\"Error\"), match: \"%\", range: 0..1 }], .. }");
+    assert_eq!(locations(errors), [0, 1]);
+
+    // Parens inside the sync expression don't end the annotation
+    let lexer =
+        bnf::parse("root ::= (\"(\" statement \")\")*\n@recover(\")\") statement ::= \"a\"")
+            .unwrap();
+    let (tokens, errors) = lexer.tokenize_recovering("(a)(b)(a)", skipped);
+    assert_eq!(tokens, ["b"]);
+    assert_eq!(locations(errors), [4]);
+    bnf::parse("root ::= s\n@recover((\"(\" | [)] | \"\\\")\")) s ::= \"a\"").unwrap();
+
+    // Without any @recover rules, repetitions skip to the next line break or closing bracket
+    let lexer = bnf::parse(include_str!("bnf/json.bnf")).unwrap();
+    let input = r#"{"a": [1, %], "b" 2}"#;
+    let (tokens, errors) = lexer.tokenize_recovering(input, skipped);
+    assert_eq!(tokens, [", %", r#", "b" 2"#]);
+    assert_eq!(
+        errors[0].to_string(),
+        lexer.check(input).unwrap_err().to_string()
+    );
+    assert_eq!(locations(errors), [10, 18]);
+}
+
+#[test]
+fn expected_set() {
+    let lexer = bnf::parse(include_str!("bnf/precedence.bnf")).unwrap();
+    let error = lexer.check("").unwrap_err();
+    assert_eq!(error.expected(), ["\"-\"", "\"!\"", "number", "\"(\""]);
+    assert!(format!("{error:#}")
+        .starts_with("expected one of `\"-\"`, `\"!\"`, `number`, `\"(\"` at line 1 col 1"));
+    let error = lexer.check("(1 * 2").unwrap_err();
+    assert_eq!(error.location, 6);
+    assert_eq!(error.expected().len(), 12);
+    assert_eq!(error.expected()[11], "\")\"");
+
+    let lexer = bnf::parse("root ::= \"a\" (b | c)\nb ::= \"b\"\nc ::= \"c\"").unwrap();
+    let error = lexer.check("ax").unwrap_err();
+    assert_eq!(
+        (error.location, error.expected()),
+        (1, &["b".into(), "c".into()][..])
+    );
+    let error = lexer.check("x").unwrap_err();
+    assert_eq!(
+        format!("{error:#}").lines().next(),
+        Some("expected `root` at line 1 col 1")
+    );
+}
+
+#[test]
+fn tracing() {
+    let grammar = "root ::= term \"+\" term\nterm ::= number | \"(\" root \")\"\nnumber ::= [0-9]+";
+    let lexer = bnf::parse(grammar).unwrap();
+    let mut tracer = TextTracer::new(&lexer).named_only();
+    lexer
+        .tokenize_traced("1+(2", &mut tracer, |_| ())
+        .unwrap_err();
+    let expected = r#"root at 0: "1+(2"
+  term at 0: "1+(2"
+    number at 0: "1+(2"
+    number matched "1"
+  term matched "1"
+  term at 2: "(2"
+    number at 2: "(2"
+    number failed
+    root at 3: "2"
+      term at 3: "2"
+        number at 3: "2"
+        number matched "2"
+      term matched "2"
+    root failed
+  term failed
+root failed
+"#;
+    assert_eq!(tracer.output(), expected);
+    let mut tracer = TextTracer::new(&lexer);
+    lexer
+        .tokenize_traced("(1+2)+3", &mut tracer, |_| ())
+        .unwrap();
+    let rejected = "      [0-9] failed\n    number failed\n    \"(\" root \")\" at 0:";
+    assert!(tracer.output().contains(rejected));
+
+    // Every event is reported in order, with the depth counting named rules
+    #[derive(Default)]
+    struct Events(Vec<(usize, usize)>, usize);
+    impl Tracer for Events {
+        fn enter(&mut self, event: &TraceEvent) {
+            self.0.push((event.matcher_id, event.pos));
+        }
+        fn exit(&mut self, event: &TraceEvent) {
+            assert_eq!(self.0.pop(), Some((event.matcher_id, event.pos)));
+            if event.name == Some("number") {
+                self.1 = self.1.max(event.depth);
+            }
+        }
+    }
+    let mut events = Events::default();
+    lexer
+        .tokenize_traced("(1+2)+3", &mut events, |_| ())
+        .unwrap();
+    assert_eq!((events.0.len(), events.1), (0, 4));
+}
+
+#[test]
+fn profiling() {
+    let grammar = "root ::= (a | b | c)+\na ::= \"x\" \"1\"\nb ::= \"x\" \"2\"\nc ::= [0-9]";
+    let lexer = bnf::parse(grammar).unwrap();
+    let profile = lexer.profile("x2x15");
+    assert_eq!(profile.rules[0].name, "root");
+    assert_eq!(profile.rules[0].consumed, 5);
+    for rule in &profile.rules {
+        assert_eq!(rule.successes + rule.failures, rule.invocations);
+    }
+    let counts = |name: &str| {
+        let rule = profile.rule(name).unwrap();
+        (rule.invocations, rule.successes, rule.rescanned)
+    };
+    assert_eq!(counts("a"), (3, 1, 2));
+    // b reads "x2" again after a fails on it
+    assert_eq!(counts("b"), (2, 1, 2));
+    let choice = profile
+        .rules
+        .iter()
+        .find(|r| r.name == "a | b | c")
+        .unwrap();
+    assert!(choice.cache.unwrap().is_effective());
+    // Everything left in the input can start another repetition
+    assert!(!profile.rules[0].cache.unwrap().is_effective());
+    let report = profile.to_string();
+    assert!(report
+        .lines()
+        .nth(1)
+        .unwrap()
+        .ends_with(" ineffective root"));
+    let json = profile.to_json();
+    assert!(json.starts_with("{\"rules\": [{\"matcher_id\": 0, \"name\": \"root\", \"named\": true, \"invocations\": 1, "));
+    assert!(json.contains("\"name\": \"\\\"x\\\"\""));
+}
@@ -14,7 +14,7 @@ impl<'a> RecursiveIter<'a> {
         Self {
             token,
             index: 0,
-            stack: vec![(token, 0)],
+            stack: Vec::new(),
             ignored: Vec::new(),
             selected: Vec::new(),
         }
@@ -26,10 +26,7 @@ impl<'a> Iterator for RecursiveIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         while self.index >= self.token.children.len() {
-            let (popped_token, popped_index) = match self.stack.pop() {
-                Some(popped_values) => popped_values,
-                None => return None,
-            };
+            let (popped_token, popped_index) = self.stack.pop()?;
             self.index = popped_index;
             self.token = popped_token;
             self.index += 1;
@@ -1,22 +1,59 @@
 use self::iterators::{iter::Iter, rec_iter::RecursiveIter};
 use crate::matchers::MatcherName;
+use crate::position::{self, LineCol};
 use bumpalo::collections::Vec;
 use std::{fmt::Debug, ops::Range, sync::Arc};
 
 pub mod iterators;
 
+#[derive(Clone)]
 pub struct Token<'a> {
     pub matcher_name: MatcherName,
     pub matcher_id: usize,
     pub children: Vec<'a, Token<'a>>,
-    pub source: Arc<[char]>,
+    pub(crate) label: Option<Arc<str>>,
+    pub source: Arc<str>,
     pub range: Range<usize>,
+    /// Set on a token standing in for one in the memo table while matching, which gets its
+    /// children once matching is done
+    pub(crate) shared: Option<usize>,
 }
 
 impl<'a> Token<'a> {
+    /// Create a token covering text skipped by `Lexer::tokenize_recovering`
+    pub(crate) fn error(source: Arc<str>, range: Range<usize>, alloc: &'a bumpalo::Bump) -> Self {
+        Token {
+            matcher_name: Arc::new(Some("Error".to_string())),
+            matcher_id: usize::MAX,
+            children: Vec::new_in(alloc),
+            label: None,
+            source,
+            range,
+            shared: None,
+        }
+    }
+
+    /// Check if the token covers text skipped by `Lexer::tokenize_recovering`, which is
+    /// named `Error`
+    pub fn is_error(&self) -> bool {
+        self.matcher_id == usize::MAX
+    }
+
     /// Get the content the token is matching from the source.
-    pub fn get_match(&self) -> String {
-        self.source[self.range.clone()].iter().collect()
+    pub fn get_match(&self) -> &str {
+        &self.source[self.range.clone()]
+    }
+
+    /// Get the range of the token in chars, rather than the bytes `range` is in
+    pub fn char_range(&self) -> Range<usize> {
+        let start = position::byte_to_char(&self.source, self.range.start);
+        let len = self.get_match().chars().count();
+        start..start + len
+    }
+
+    /// Get the line and column the token starts at
+    pub fn line_col(&self) -> LineCol {
+        position::line_col(&self.source, self.range.start)
     }
 
     /// Return the name of the matcher that created the token.
@@ -24,9 +61,37 @@ impl<'a> Token<'a> {
         &self.matcher_name
     }
 
+    /// Return the label given to the token with `label:expr` in the grammar, if any.
+    pub fn get_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Get the first token labeled `label` under `self`. Unnamed, unlabeled children are
+    /// searched through, so the field is found no matter how the tree around it was culled.
+    pub fn field(&self, label: &str) -> Option<&Token<'a>> {
+        self.fields(label).next()
+    }
+
+    /// Get every token labeled `label` under `self`, in order
+    pub fn fields<'b>(&'b self, label: &str) -> std::vec::IntoIter<&'b Token<'a>> {
+        let mut fields = std::vec::Vec::new();
+        self.collect_fields(label, &mut fields);
+        fields.into_iter()
+    }
+
+    fn collect_fields<'b>(&'b self, label: &str, fields: &mut std::vec::Vec<&'b Token<'a>>) {
+        for child in self.children.iter() {
+            match child.get_label() {
+                Some(l) if l == label => fields.push(child),
+                None if child.matcher_name.is_none() => child.collect_fields(label, fields),
+                _ => (),
+            }
+        }
+    }
+
     /// Get the first child of the token
-    pub fn first(&self) -> Option<&Token> {
-        self.children.get(0)
+    pub fn first(&self) -> Option<&Token<'a>> {
+        self.children.first()
     }
 
     /// Get an iterator over the direct children of `self` with a given name `name`
@@ -40,12 +105,12 @@ impl<'a> Token<'a> {
     }
 
     /// Get an iterator over children of `self`
-    pub fn iter(&self) -> Iter {
+    pub fn iter(&self) -> Iter<'_> {
         Iter::new(self)
     }
 
     /// Get an iterator over all children in `self`, recursively
-    pub fn rec_iter(&self) -> RecursiveIter {
+    pub fn rec_iter(&self) -> RecursiveIter<'_> {
         RecursiveIter::new(self)
     }
 
@@ -54,6 +119,9 @@ impl<'a> Token<'a> {
             Some(v) => format!("|--{}", v.clone()),
             None => "|--NO_NAME".into(),
         };
+        if let Some(label) = self.get_label() {
+            rec_str.insert_str(3, &format!("{label}:"));
+        }
 
         if self.children.is_empty() {
             rec_str.push_str(&format!("({})", self.get_match()));
@@ -78,6 +146,9 @@ impl<'a> Debug for Token<'a> {
         let mut debug = f.debug_struct("Token");
 
         debug.field("name", &*self.matcher_name);
+        if let Some(label) = &self.label {
+            debug.field("label", label);
+        }
         debug.field("match", &self.get_match());
         debug.field("range", &self.range);
         if !self.children.is_empty() {
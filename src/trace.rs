@@ -0,0 +1,103 @@
+//! Following along as a lexer applies each matcher, to see why a grammar does or doesn't
+//! match some input
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::lexer::Lexer;
+use crate::printer;
+
+/// A matcher being applied at some position, given to a `Tracer`
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEvent<'e> {
+    pub matcher_id: usize,
+    /// The name of the rule, if the matcher is one
+    pub name: Option<&'e str>,
+    pub pos: usize,
+    /// How many named rules the matcher is applied inside of
+    pub depth: usize,
+    pub source: &'e str,
+}
+
+/// Gets told about every matcher applied by `Lexer::tokenize_traced`. Each one gets
+/// `enter`, then `success` or `fail`, then `exit`, with the matchers it applies in turn
+/// traced between `enter` and the rest.
+pub trait Tracer {
+    fn enter(&mut self, _event: &TraceEvent) {}
+    fn success(&mut self, _event: &TraceEvent, _range: Range<usize>) {}
+    fn fail(&mut self, _event: &TraceEvent) {}
+    fn exit(&mut self, _event: &TraceEvent) {}
+}
+
+/// How much of the input to show where a matcher is applied, in chars
+const PREVIEW_LEN: usize = 16;
+
+/// A tracer writing out every matcher applied, indented by how deep it is, along with
+/// what it matched or that it failed. Unnamed matchers are written as their BNF, so each
+/// alternative of a choice can be told apart.
+pub struct TextTracer {
+    descriptions: Vec<String>,
+    named_only: bool,
+    indent: usize,
+    output: String,
+}
+
+impl TextTracer {
+    pub fn new(lexer: &Lexer) -> Self {
+        TextTracer {
+            descriptions: printer::describe_all(lexer.matchers()),
+            named_only: false,
+            indent: 0,
+            output: String::new(),
+        }
+    }
+
+    /// Leave out every matcher that isn't a named rule
+    pub fn named_only(mut self) -> Self {
+        self.named_only = true;
+        self
+    }
+
+    /// Get everything written so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    fn traces(&self, event: &TraceEvent) -> bool {
+        event.name.is_some() || !self.named_only
+    }
+
+    fn write_line(&mut self, event: &TraceEvent, message: std::fmt::Arguments) {
+        let indent = "  ".repeat(self.indent);
+        let description = &self.descriptions[event.matcher_id];
+        let _ = writeln!(self.output, "{indent}{description} {message}");
+    }
+}
+
+impl Tracer for TextTracer {
+    fn enter(&mut self, event: &TraceEvent) {
+        if self.traces(event) {
+            let preview: String = event.source[event.pos..]
+                .chars()
+                .take(PREVIEW_LEN)
+                .collect();
+            self.write_line(event, format_args!("at {}: {preview:?}", event.pos));
+            self.indent += 1;
+        }
+    }
+
+    fn success(&mut self, event: &TraceEvent, range: Range<usize>) {
+        if self.traces(event) {
+            self.indent -= 1;
+            let matched = &event.source[range];
+            self.write_line(event, format_args!("matched {matched:?}"));
+        }
+    }
+
+    fn fail(&mut self, event: &TraceEvent) {
+        if self.traces(event) {
+            self.indent -= 1;
+            self.write_line(event, format_args!("failed"));
+        }
+    }
+}